@@ -0,0 +1,33 @@
+//! Times the interpreter on `examples/is-prime.ni`.
+//!
+//! Run with `cargo bench --bench is_prime`.
+use std::io;
+use std::time::{Duration, Instant};
+
+use nino::{lexer::tokenize, parser::parse, virtual_machine::VirtualMachine};
+
+const RUNS: u32 = 10;
+
+fn main() {
+    let code = include_str!("../examples/is-prime.ni");
+    let tokens = tokenize(code).unwrap();
+
+    let mut timings = vec![];
+    for _ in 0..RUNS {
+        let ast = parse(&tokens).unwrap();
+        let start = Instant::now();
+        let mut vm = VirtualMachine::new();
+        vm.set_output(io::sink());
+        vm.run(ast).unwrap();
+        timings.push(start.elapsed());
+    }
+
+    let total: Duration = timings.iter().sum();
+    let fastest = timings.iter().min().unwrap();
+    println!(
+        "is-prime.ni: {:?} mean, {:?} fastest over {} runs",
+        total / RUNS,
+        fastest,
+        RUNS
+    );
+}
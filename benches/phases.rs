@@ -0,0 +1,76 @@
+//! Times the lexer, the parser and the virtual machine separately on a set of programs.
+//!
+//! Run with `cargo bench --bench phases`. What the programs print is thrown away, so that writing
+//! to the terminal is not part of the timings.
+use std::hint::black_box;
+use std::io;
+use std::time::{Duration, Instant};
+
+use nino::{lexer::tokenize, parser::parse, virtual_machine::VirtualMachine};
+
+const PROGRAMS: [(&str, &str); 5] = [
+    ("is-prime.ni", include_str!("../examples/is-prime.ni")),
+    (
+        "fibonacci.ni",
+        include_str!("../tests/programs/fibonacci.ni"),
+    ),
+    (
+        "factorial.ni",
+        include_str!("../tests/programs/factorial.ni"),
+    ),
+    ("pi.ni", include_str!("../examples/pi.ni")),
+    (
+        "tail-call-optimization.ni",
+        include_str!("../examples/tail-call-optimization.ni"),
+    ),
+];
+
+/// Every phase is repeated until it has run for at least this long
+const MINIMUM_TIME: Duration = Duration::from_millis(200);
+const MINIMUM_RUNS: u32 = 3;
+
+/// Returns the mean time of one run of `phase`. `setup` is not timed.
+fn measure<T>(mut setup: impl FnMut() -> T, mut phase: impl FnMut(T)) -> Duration {
+    let mut total = Duration::ZERO;
+    let mut runs = 0;
+    while total < MINIMUM_TIME || runs < MINIMUM_RUNS {
+        let input = setup();
+        let start = Instant::now();
+        phase(input);
+        total += start.elapsed();
+        runs += 1;
+    }
+    total / runs
+}
+
+fn main() {
+    let mut report = vec![];
+    for (name, code) in PROGRAMS {
+        let tokens = tokenize(code).unwrap();
+        let lexer = measure(|| code, |code| drop(black_box(tokenize(code))));
+        let parser = measure(|| &tokens, |tokens| drop(black_box(parse(tokens))));
+        let virtual_machine = measure(
+            || parse(&tokens).unwrap(),
+            |ast| {
+                let mut vm = VirtualMachine::new();
+                vm.set_output(io::sink());
+                vm.run(black_box(ast)).unwrap()
+            },
+        );
+        report.push((name, lexer, parser, virtual_machine));
+    }
+
+    println!(
+        "{:<28}{:>14}{:>14}{:>14}",
+        "program", "lexer", "parser", "vm"
+    );
+    for (name, lexer, parser, virtual_machine) in report {
+        println!(
+            "{:<28}{:>14}{:>14}{:>14}",
+            name,
+            format!("{:.2?}", lexer),
+            format!("{:.2?}", parser),
+            format!("{:.2?}", virtual_machine)
+        );
+    }
+}
@@ -0,0 +1,13 @@
+//! Both parsers return errors on any program they cannot parse, they never panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    if let Ok(tokens) = nino::lexer::tokenize(input) {
+        let _ = nino::parser::parse_recovering(&tokens);
+    }
+    if let Ok(tree) = nino::syntax::parse(input) {
+        let _ = tree.to_items();
+    }
+});
@@ -0,0 +1,8 @@
+//! The lexer returns an error on any input it cannot tokenize, it never panics
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let _ = nino::lexer::tokenize(input);
+});
@@ -0,0 +1,363 @@
+//! What the editor tools know about a program: its syntax errors, the symbols it declares and
+//! which symbol every name refers to. Names are resolved like [crate::scoped_symbols::ScopedSymbols]
+//! does, with a scope for the parameters of each function on top of the global scope.
+
+use std::collections::HashMap;
+
+use crate::diagnostics::Diagnostic;
+use crate::lexer::{tokenize_recovering, Span, Token, TokenKind};
+use crate::parser::{parse_recovering, Declaration, Expression, FunctionDeclaration, Item, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Function,
+    Parameter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub type_: Type,
+    /// The name where the symbol is declared
+    pub span: Span,
+    /// The whole declaration, or the parameter with its type
+    pub declaration: Span,
+    /// The parameters and return type of functions, like `(x:num):bool`
+    pub signature: Option<String>,
+    pub doc: Option<String>,
+    /// The offsets where the symbol can be used
+    pub scope: (usize, usize),
+}
+
+impl Symbol {
+    /// How the symbol is declared, like `x:num` or `f:fn (x:num):bool`
+    pub fn detail(&self) -> String {
+        match &self.signature {
+            Some(signature) => format!("{}:{} {}", self.name, self.type_, signature),
+            None => format!("{}:{}", self.name, self.type_),
+        }
+    }
+}
+
+/// A name that refers to a symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub span: Span,
+    /// The index of the symbol in [Analysis::symbols]
+    pub symbol: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    /// In the order they are declared
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+}
+
+impl Analysis {
+    pub fn new(source: &str) -> Analysis {
+        let mut analysis = Analysis::default();
+        let (tokens, errors) = tokenize_recovering(source);
+        analysis
+            .diagnostics
+            .extend(errors.iter().map(Diagnostic::from));
+        let (items, errors) = parse_recovering(&tokens);
+        analysis
+            .diagnostics
+            .extend(errors.iter().map(Diagnostic::from));
+
+        let mut resolver = Resolver {
+            analysis: &mut analysis,
+            tokens: &tokens,
+            scopes: vec![HashMap::new()],
+            item_end: usize::MAX,
+        };
+        for item in &items {
+            match item {
+                Item::Declaration(declaration) => resolver.declaration(declaration),
+                Item::Expression(expression) => {
+                    resolver.item_end = usize::MAX;
+                    resolver.expression(expression);
+                }
+                Item::Error => {}
+            }
+        }
+        analysis
+    }
+
+    /// The symbol declared or referred to at the offset
+    pub fn symbol_at(&self, offset: usize) -> Option<&Symbol> {
+        let contains = |span: &Span| span.begin <= offset && offset <= span.end;
+        let reference = self
+            .references
+            .iter()
+            .find(|reference| contains(&reference.span));
+        match reference {
+            Some(reference) => Some(&self.symbols[reference.symbol]),
+            None => self.symbols.iter().find(|symbol| contains(&symbol.span)),
+        }
+    }
+
+    /// The symbols that can be used at the offset. A symbol that is shadowed by another one with
+    /// the same name is left out.
+    pub fn visible_at(&self, offset: usize) -> Vec<&Symbol> {
+        let mut visible: Vec<&Symbol> = vec![];
+        for symbol in &self.symbols {
+            if symbol.scope.0 > offset || offset > symbol.scope.1 {
+                continue;
+            }
+            // Later symbols are declared in inner scopes or redeclare the name
+            visible.retain(|other| other.name != symbol.name);
+            visible.push(symbol);
+        }
+        visible
+    }
+}
+
+/// The span of a name starting at `begin`, whose end is the beginning of its last character
+fn name_span(begin: usize, name: &str) -> Span {
+    let last = name.chars().last().map_or(0, char::len_utf8);
+    Span {
+        begin,
+        end: begin + name.len().saturating_sub(last),
+    }
+}
+
+struct Resolver<'a> {
+    analysis: &'a mut Analysis,
+    tokens: &'a [Token],
+    /// The innermost scope is the last one, each maps a name to its symbol
+    scopes: Vec<HashMap<String, usize>>,
+    /// Where the current item ends, which is where the parameters stop being visible
+    item_end: usize,
+}
+
+impl<'a> Resolver<'a> {
+    fn declare(&mut self, symbol: Symbol) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(symbol.name.clone(), self.analysis.symbols.len());
+        self.analysis.symbols.push(symbol);
+    }
+
+    fn refer(&mut self, name: &str, span: Span) {
+        let symbol = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+        if let Some(&symbol) = symbol {
+            self.analysis.references.push(Reference { span, symbol });
+        }
+    }
+
+    /// The name after the `let` at `begin`
+    fn declared_name(&self, begin: usize) -> Option<Span> {
+        let mut tokens = self
+            .tokens
+            .iter()
+            .skip_while(|token| token.begin != begin)
+            .skip(1)
+            .filter(|token| !matches!(token.kind, TokenKind::Comment(_)));
+        tokens.next().map(Token::span)
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        let location = declaration.location;
+        let (kind, signature) = match declaration.expression.as_ref() {
+            Expression::FunctionDeclaration(function) => {
+                (SymbolKind::Function, Some(signature(function)))
+            }
+            _ => (SymbolKind::Variable, None),
+        };
+        // Declared before its expression, so that functions can call themselves
+        self.declare(Symbol {
+            name: declaration.name.clone(),
+            kind,
+            type_: declaration.type_.clone(),
+            span: self
+                .declared_name(location.begin)
+                .unwrap_or(location.span()),
+            declaration: location.span(),
+            signature,
+            doc: declaration.doc.clone(),
+            scope: (location.begin, usize::MAX),
+        });
+        self.item_end = location.end;
+        self.expression(&declaration.expression);
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier(name, location) => self.refer(name, location.span()),
+            Expression::Number(_) | Expression::Char(_) | Expression::Bool(_) => {}
+            Expression::Array(_, elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expression::FunctionDeclaration(function) => {
+                self.scopes.push(HashMap::new());
+                for parameter in &function.parameters {
+                    let location = parameter.location;
+                    self.declare(Symbol {
+                        name: parameter.name.clone(),
+                        kind: SymbolKind::Parameter,
+                        type_: parameter.type_.clone(),
+                        span: name_span(location.begin, &parameter.name),
+                        declaration: location.span(),
+                        signature: None,
+                        doc: None,
+                        scope: (function.location.begin, self.item_end),
+                    });
+                }
+                self.expression(&function.expression);
+                self.scopes.pop();
+            }
+            Expression::FunctionCall(call) => {
+                self.refer(&call.name, name_span(call.location.begin, &call.name));
+                for argument in &call.arguments {
+                    self.expression(argument);
+                }
+            }
+            Expression::Match(match_) => {
+                self.expression(&match_.value);
+                for (pattern, expression) in &match_.patterns {
+                    self.expression(pattern);
+                    self.expression(expression);
+                }
+                if let Some(default) = &match_.default {
+                    self.expression(default);
+                }
+            }
+            Expression::BinaryOperation(operation) => {
+                self.expression(&operation.left);
+                self.expression(&operation.right);
+            }
+        }
+    }
+}
+
+/// Like `(x:num, y:num):bool`
+fn signature(function: &FunctionDeclaration) -> String {
+    let parameters: Vec<String> = function
+        .parameters
+        .iter()
+        .map(|parameter| format!("{}:{}", parameter.name, parameter.type_))
+        .collect();
+    format!("({}):{}", parameters.join(", "), function.return_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "## Adds one
+let inc:fn = (x:num):num => x + 1;
+let y:num = inc(2);
+let twice:fn = (x:num, y:num):num => inc(x) + y;
+print(y);";
+
+    fn offset(text: &str) -> usize {
+        SOURCE.find(text).unwrap()
+    }
+
+    #[test]
+    fn test_symbols() {
+        let analysis = Analysis::new(SOURCE);
+        assert!(analysis.diagnostics.is_empty());
+        let names: Vec<(&str, SymbolKind)> = analysis
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("inc", SymbolKind::Function),
+                ("x", SymbolKind::Parameter),
+                ("y", SymbolKind::Variable),
+                ("twice", SymbolKind::Function),
+                ("x", SymbolKind::Parameter),
+                ("y", SymbolKind::Parameter),
+            ]
+        );
+        let inc = &analysis.symbols[0];
+        assert_eq!(inc.span, Span { begin: 16, end: 18 });
+        assert_eq!(inc.doc, Some("Adds one".to_string()));
+        assert_eq!(inc.detail(), "inc:fn (x:num):num");
+        assert_eq!(analysis.symbols[2].detail(), "y:num");
+    }
+
+    #[test]
+    fn test_references() {
+        let analysis = Analysis::new(SOURCE);
+        // The parameter, not the global with the same name
+        let y = analysis.symbol_at(offset("y;")).unwrap();
+        assert_eq!(y.kind, SymbolKind::Parameter);
+        let y = analysis.symbol_at(offset("y);")).unwrap();
+        assert_eq!(y.kind, SymbolKind::Variable);
+        let inc = analysis.symbol_at(offset("inc(x)")).unwrap();
+        assert_eq!(inc.name, "inc");
+        assert_eq!(inc.kind, SymbolKind::Function);
+        // On the declaration itself
+        let twice = analysis.symbol_at(offset("twice") + 2).unwrap();
+        assert_eq!(twice.name, "twice");
+        // Builtins are not symbols of the program
+        assert_eq!(analysis.symbol_at(offset("print")), None);
+    }
+
+    #[test]
+    fn test_visible() {
+        let analysis = Analysis::new(SOURCE);
+        let names = |offset: usize| -> Vec<(String, SymbolKind)> {
+            let mut names: Vec<(String, SymbolKind)> = analysis
+                .visible_at(offset)
+                .iter()
+                .map(|symbol| (symbol.name.clone(), symbol.kind))
+                .collect();
+            names.sort_by(|a, b| a.0.cmp(&b.0));
+            names
+        };
+        assert_eq!(
+            names(offset("x + 1")),
+            vec![
+                ("inc".to_string(), SymbolKind::Function),
+                ("x".to_string(), SymbolKind::Parameter)
+            ]
+        );
+        assert_eq!(
+            names(offset("inc(x)")),
+            vec![
+                ("inc".to_string(), SymbolKind::Function),
+                ("twice".to_string(), SymbolKind::Function),
+                ("x".to_string(), SymbolKind::Parameter),
+                ("y".to_string(), SymbolKind::Parameter),
+            ]
+        );
+        assert_eq!(names(offset("print")).len(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let analysis = Analysis::new("let a:num = 1;\nlet b:num = ;\nlet c:num = a;");
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert_eq!(
+            analysis.diagnostics[0].message,
+            "Unexpected token: Semicolon"
+        );
+        // The declarations around the error are still known
+        let names: Vec<&str> = analysis.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "c"]);
+        assert_eq!(analysis.references.len(), 1);
+
+        let analysis = Analysis::new("let a:num = $;");
+        assert_eq!(analysis.diagnostics[0].message, "Unexpected character: $");
+        assert!(analysis.symbols.is_empty());
+        // The tokens after a lexer error are still analyzed
+        let analysis = Analysis::new("let a:num = 1;\nlet b:num = a $;\nlet c:num = \"a;");
+        let names: Vec<&str> = analysis.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(analysis.references.len(), 1);
+    }
+}
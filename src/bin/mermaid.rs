@@ -10,7 +10,7 @@ fn fishify_expression(expression: Expression) -> String {
     let mut result = String::new();
 
     match expression {
-        Expression::Identifier(identifier) => {
+        Expression::Identifier(identifier, _) => {
             result.push_str(&identifier);
         }
 
@@ -33,13 +33,17 @@ fn fishify_expression(expression: Expression) -> String {
             result.push_str(node_name.as_str());
             result.push_str(" --> ");
             result.push_str(&fishify_expression(*binary_operation.left));
-            result.push_str("\n");
+            result.push('\n');
 
             result.push_str(node_name.as_str());
             result.push_str(" --> ");
             result.push_str(&fishify_expression(*binary_operation.right));
         }
-        _ => unimplemented!(),
+        // Anything else is a leaf with its source as the label
+        expression => {
+            let label = expression.to_string().replace('"', "#quot;");
+            result.push_str(format!("Expression_{}[\"{}\"]", count, label).as_str());
+        }
     };
 
     result
@@ -53,26 +57,31 @@ fn main() {
     }
     let code = &args[1];
     let mut chart = String::new();
-    chart.push_str(&code);
-    chart.push_str("\n");
+    chart.push_str(code);
+    chart.push('\n');
     chart.push_str("```mermaid\nflowchart TD\n");
 
-    let tokens = tokenize(code);
+    let tokens = match tokenize(code) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     let ast = match nino::parser::parse(&tokens) {
         Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("{}", e);
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
+            }
             std::process::exit(1);
         }
     };
 
     let expression = ast.first().unwrap();
-    match expression {
-        nino::parser::Item::Expression(expression) => {
-            chart.push_str(&fishify_expression(expression.clone()));
-        }
-        _ => {}
+    if let nino::parser::Item::Expression(expression) = expression {
+        chart.push_str(&fishify_expression(expression.clone()));
     }
 
     let chart = chart + "\n```";
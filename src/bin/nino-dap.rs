@@ -0,0 +1,11 @@
+use std::env;
+use std::io;
+
+fn main() {
+    if env::args().len() > 1 {
+        eprintln!("Usage: {}", env::args().next().unwrap());
+        std::process::exit(1);
+    }
+    let stdin = io::stdin();
+    nino::dap::run(stdin.lock(), io::stdout()).expect("Failed to talk to the client");
+}
@@ -0,0 +1,33 @@
+use std::env;
+use std::io;
+
+use nino::highlight::{legend, textmate_grammar};
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--textmate-grammar | --semantic-tokens-legend]",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    // Exports what editors need to highlight without talking to the server
+    match args.get(1).map(String::as_str) {
+        None => {}
+        Some("--textmate-grammar") => {
+            println!("{}", textmate_grammar());
+            return;
+        }
+        Some("--semantic-tokens-legend") => {
+            println!("{}", legend());
+            return;
+        }
+        Some(_) => usage(&args[0]),
+    }
+
+    let stdin = io::stdin();
+    let code = nino::lsp::run(stdin.lock(), io::stdout()).expect("Failed to talk to the client");
+    std::process::exit(code);
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::fs;
+use std::io::IsTerminal;
+
+use nino::diagnostics::{Diagnostic, SourceMap};
+use nino::formatter::format;
+use nino::syntax;
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [--check] <filename>...", program);
+    std::process::exit(1);
+}
+
+/// Returns the formatted source, or the syntax errors rendered for the terminal
+fn format_file(filename: &str, input: &str) -> Result<String, String> {
+    let source = SourceMap::new(input);
+    let color = std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let render = |diagnostic: Diagnostic| diagnostic.render(filename, &source, color);
+
+    let tree = syntax::parse(input).map_err(|error| render(Diagnostic::from(&error)))?;
+    let (_, errors) = tree.to_items();
+    if !errors.is_empty() {
+        let rendered: Vec<String> = errors.iter().map(|e| render(Diagnostic::from(e))).collect();
+        return Err(rendered.join("\n\n"));
+    }
+    Ok(format(&tree))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut check = false;
+    let mut filenames = vec![];
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ if arg.starts_with("--") => usage(&args[0]),
+            _ => filenames.push(arg),
+        }
+    }
+    if filenames.is_empty() {
+        usage(&args[0]);
+    }
+
+    let mut failed = false;
+    for filename in filenames {
+        let input = fs::read_to_string(filename).expect("Failed to read file");
+        let formatted = match format_file(filename, &input) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                eprintln!("{}\n", errors);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == input {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", filename);
+            failed = true;
+        } else {
+            fs::write(filename, formatted).expect("Failed to write file");
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -1,58 +1,204 @@
 use std::env;
 use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 
-use nino::lexer::tokenize;
-use nino::parser::parse;
+use nino::coverage::Coverage;
+use nino::debugger::{Debugger, STOPPED};
+use nino::diagnostics::{Diagnostic, SourceMap};
+use nino::optimizer::optimize;
+use nino::parser::Item;
+use nino::profiler::Profiler;
+use nino::syntax;
+use nino::testing::{run_test, tests};
 use nino::virtual_machine::VirtualMachine;
 
-fn generate_error_location_display(input: &str, token: &nino::lexer::Token) -> String {
-    let mut line_start = token.begin;
-    while line_start > 0 && input.chars().nth(line_start).unwrap() != '\n' {
-        line_start -= 1;
+#[derive(PartialEq, Clone, Copy)]
+enum ErrorFormat {
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+/// Prints the diagnostics, either with the source they point to or as JSON
+fn report(filename: &str, input: &str, format: ErrorFormat, diagnostics: &[Diagnostic]) {
+    let source = SourceMap::new(input);
+    let color = std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    for diagnostic in diagnostics {
+        match format {
+            ErrorFormat::Human => eprintln!("{}\n", diagnostic.render(filename, &source, color)),
+            ErrorFormat::Json => eprintln!("{}", diagnostic.to_json(filename, &source)),
+        }
     }
-    let mut line_end = token.end;
-    while line_end < input.len() && input.chars().nth(line_end).unwrap() != '\n' {
-        line_end += 1;
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--error-format=human|json] [--debug] [--profile[=<file>]] [--coverage[=<file>]] <filename>",
+        program
+    );
+    eprintln!("       {} test <directory>", program);
+    std::process::exit(1);
+}
+
+/// The program, or the errors that keep it from running
+fn parse(input: &str) -> Result<Vec<Item>, Vec<Diagnostic>> {
+    let tree = syntax::parse(input).map_err(|error| vec![Diagnostic::from(&error)])?;
+    match tree.to_items() {
+        (ast, errors) if errors.is_empty() => Ok(ast),
+        (_, errors) => Err(errors.iter().map(Diagnostic::from).collect()),
     }
-    let line = input[line_start..line_end].to_string();
+}
 
-    let mut pointer = String::new();
-    for _ in 0..(token.begin - line_start)-2 {
-        pointer.push(' ');
+/// The `.ni` files in the directory and the ones below it
+fn find_programs(directory: &Path, programs: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(directory).expect("Failed to read directory");
+    for entry in entries {
+        let path = entry.expect("Failed to read directory").path();
+        if path.is_dir() {
+            find_programs(&path, programs);
+        } else if path.extension().is_some_and(|extension| extension == "ni") {
+            programs.push(path);
+        }
+    }
+}
+
+/// Runs the tests of the programs in the directory, and exits with 1 if any of them failed
+fn test(directory: &str) -> ! {
+    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let mut programs = vec![];
+    find_programs(Path::new(directory), &mut programs);
+    programs.sort();
+
+    let (mut passed, mut failures) = (0, vec![]);
+    for path in programs {
+        let filename = path.to_string_lossy().to_string();
+        let input = fs::read_to_string(&path).expect("Failed to read file");
+        let source = SourceMap::new(&input);
+        let ast = match parse(&input) {
+            Ok(ast) => ast,
+            Err(diagnostics) => {
+                println!("test {} ... FAILED", filename);
+                let rendered: Vec<String> = diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.render(&filename, &source, color))
+                    .collect();
+                failures.push((filename, rendered.join("\n\n")));
+                continue;
+            }
+        };
+        for name in tests(&ast) {
+            let test = format!("{}::{}", filename, name);
+            let result = run_test(&ast, &name);
+            match &result.error {
+                None => {
+                    println!("test {} ... ok", test);
+                    passed += 1;
+                }
+                Some(error) => {
+                    println!("test {} ... FAILED", test);
+                    let mut report = String::new();
+                    if !result.output.is_empty() {
+                        report += &format!("Printed:\n{}\n", result.output.trim_end());
+                    }
+                    report += &Diagnostic::from(error).render(&filename, &source, color);
+                    failures.push((test, report));
+                }
+            }
+        }
     }
-    for _ in token.begin..token.end+1 {
-        pointer.push('^');
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (test, report) in &failures {
+            println!("\n---- {} ----\n{}", test, report);
+        }
     }
-    format!("{}\n{}", line, pointer)
+    let status = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        status,
+        passed,
+        failures.len()
+    );
+    std::process::exit(if failures.is_empty() { 0 } else { 1 });
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <filename>", args[0]);
-        std::process::exit(1);
+    if args.get(1).map(String::as_str) == Some("test") {
+        match &args[2..] {
+            [directory] => test(directory),
+            _ => usage(&args[0]),
+        }
+    }
+    let mut format = ErrorFormat::Human;
+    let mut debug = false;
+    // Where to write the folded stacks when profiling
+    let mut profile = None;
+    // Where to write the lcov report when measuring the coverage
+    let mut coverage = None;
+    let mut filename = None;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--error-format=human" => format = ErrorFormat::Human,
+            "--error-format=json" => format = ErrorFormat::Json,
+            "--debug" => debug = true,
+            "--profile" => profile = Some("profile.folded"),
+            _ if arg.starts_with("--profile=") => profile = Some(&arg["--profile=".len()..]),
+            "--coverage" => coverage = Some("lcov.info"),
+            _ if arg.starts_with("--coverage=") => coverage = Some(&arg["--coverage=".len()..]),
+            _ if arg.starts_with("--") || filename.is_some() => usage(&args[0]),
+            _ => filename = Some(arg),
+        }
     }
+    let filename = match filename {
+        Some(filename) => filename,
+        None => usage(&args[0]),
+    };
 
-    let filename = &args[1];
     let input = fs::read_to_string(filename).expect("Failed to read file");
 
-    let tokens = tokenize(&input);
-
-    let ast = match parse(&tokens) {
+    let ast = match parse(&input) {
         Ok(ast) => ast,
-        Err(e) => {
-            match e.token {
-                Some(token) => eprintln!(
-                    "Parser error!\n{}\nHere: {}",
-                    e.message,
-                    generate_error_location_display(&input, &token)
-                ),
-                None => eprintln!("{}", e.message),
-            }
+        Err(diagnostics) => {
+            report(filename, &input, format, &diagnostics);
             std::process::exit(1);
         }
     };
 
     let mut vm = VirtualMachine::new();
-    vm.run(ast);
+    let result = if debug {
+        // Not optimized, so that every expression is where it is in the source
+        let stdin = std::io::stdin();
+        let mut debugger = Debugger::new(&input, stdin.lock(), std::io::stdout());
+        vm.run_with_hook(ast, &mut debugger)
+    } else if let Some(folded) = profile {
+        // Not optimized, so that the functions that would be inlined are in the report
+        let mut profiler = Profiler::new();
+        let result = vm.run_with_hook(ast, &mut profiler);
+        profiler.finish();
+        eprint!("{}", profiler.report());
+        fs::write(folded, profiler.folded()).expect("Failed to write the folded stacks");
+        result
+    } else if let Some(lcov) = coverage {
+        // Not optimized, so that every expression that runs is counted
+        let mut coverage = Coverage::new(&ast);
+        let result = vm.run_with_hook(ast, &mut coverage);
+        let source = SourceMap::new(&input);
+        eprint!("{}", coverage.summary(&source));
+        let path = fs::canonicalize(filename).expect("Failed to find the file");
+        let report = coverage.lcov(&path.to_string_lossy(), &source);
+        fs::write(lcov, report).expect("Failed to write the coverage report");
+        result
+    } else {
+        vm.run(optimize(ast))
+    };
+    if let Err(error) = result {
+        if debug && error.message == STOPPED {
+            std::process::exit(1);
+        }
+        report(filename, &input, format, &[Diagnostic::from(&error)]);
+        std::process::exit(1);
+    }
 }
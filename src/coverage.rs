@@ -0,0 +1,354 @@
+//! Coverage of a program, see `ninoi --coverage`. The declarations, functions, match arms and
+//! expressions are found in the program before it runs, and counted by their spans through the
+//! hook of the virtual machine. The report is written in the lcov format, where a line is as
+//! covered as the expressions that begin on it, and every match is a block of branches.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::diagnostics::SourceMap;
+use crate::lexer::Span;
+use crate::parser::{Declaration, Expression, Item};
+use crate::virtual_machine::{Hook, State};
+
+/// Something that can run, and how many times it did
+#[derive(Debug, Clone, PartialEq)]
+pub struct Count {
+    pub span: Span,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCount {
+    pub name: String,
+    /// The signature
+    pub span: Span,
+    pub calls: usize,
+}
+
+/// The arms of a match, the default arm being the last one. An arm is located by its
+/// expression, or by its pattern or the match when they are literals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchCount {
+    pub span: Span,
+    pub arms: Vec<Count>,
+}
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    pub declarations: Vec<Count>,
+    pub functions: Vec<FunctionCount>,
+    pub matches: Vec<MatchCount>,
+    pub expressions: Vec<Count>,
+    /// The indices by span
+    declaration_indices: HashMap<Span, usize>,
+    function_indices: HashMap<String, usize>,
+    match_indices: HashMap<Span, usize>,
+    expression_indices: HashMap<Span, usize>,
+}
+
+fn register(counts: &mut Vec<Count>, indices: &mut HashMap<Span, usize>, span: Span) {
+    indices.entry(span).or_insert_with(|| {
+        counts.push(Count { span, count: 0 });
+        counts.len() - 1
+    });
+}
+
+fn covered(counts: &[Count]) -> usize {
+    counts.iter().filter(|count| count.count > 0).count()
+}
+
+impl Coverage {
+    /// Finds what can run in the program, nothing has run yet
+    pub fn new(program: &[Item]) -> Self {
+        let mut coverage = Coverage::default();
+        for item in program {
+            match item {
+                Item::Declaration(declaration) => coverage.declaration(declaration),
+                Item::Expression(expression) => coverage.expression(expression),
+                Item::Error => {}
+            }
+        }
+        coverage
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        let span = declaration.location.span();
+        register(&mut self.declarations, &mut self.declaration_indices, span);
+        if let Expression::FunctionDeclaration(function) = declaration.expression.as_ref() {
+            self.function_indices
+                .insert(declaration.name.clone(), self.functions.len());
+            self.functions.push(FunctionCount {
+                name: declaration.name.clone(),
+                span: function.location.span(),
+                calls: 0,
+            });
+        }
+        self.expression(&declaration.expression);
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        if let Some(span) = expression.span() {
+            register(&mut self.expressions, &mut self.expression_indices, span);
+        }
+        match expression {
+            Expression::FunctionDeclaration(function) => self.expression(&function.expression),
+            Expression::FunctionCall(call) => {
+                for argument in &call.arguments {
+                    self.expression(argument);
+                }
+            }
+            Expression::Match(match_) => {
+                let span = match_.location.span();
+                let mut arms: Vec<Count> = match_
+                    .patterns
+                    .iter()
+                    .map(|(pattern, arm)| Count {
+                        span: arm.span().or(pattern.span()).unwrap_or(span),
+                        count: 0,
+                    })
+                    .collect();
+                if let Some(default) = &match_.default {
+                    arms.push(Count {
+                        span: default.span().unwrap_or(span),
+                        count: 0,
+                    });
+                }
+                self.match_indices.insert(span, self.matches.len());
+                self.matches.push(MatchCount { span, arms });
+
+                self.expression(&match_.value);
+                for (pattern, arm) in &match_.patterns {
+                    self.expression(pattern);
+                    self.expression(arm);
+                }
+                if let Some(default) = &match_.default {
+                    self.expression(default);
+                }
+            }
+            Expression::BinaryOperation(binary) => {
+                self.expression(&binary.left);
+                self.expression(&binary.right);
+            }
+            Expression::Array(_, elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expression::Identifier(..)
+            | Expression::Number(_)
+            | Expression::Char(_)
+            | Expression::Bool(_) => {}
+        }
+    }
+
+    /// How many times the expressions that begin on each line ran, counting the lines from 1
+    fn lines(&self, source: &SourceMap) -> BTreeMap<usize, usize> {
+        let mut lines = BTreeMap::new();
+        for count in self.declarations.iter().chain(&self.expressions) {
+            let line = source.line_index(count.span.begin) + 1;
+            let hits = lines.entry(line).or_insert(0);
+            *hits = count.count.max(*hits);
+        }
+        lines
+    }
+
+    /// How much ran, for people
+    pub fn summary(&self, source: &SourceMap) -> String {
+        let lines = self.lines(source);
+        let arms: Vec<Count> = self
+            .matches
+            .iter()
+            .flat_map(|match_| match_.arms.iter().cloned())
+            .collect();
+        let functions = self
+            .functions
+            .iter()
+            .filter(|function| function.calls > 0)
+            .count();
+        let rows = [
+            (
+                "Lines",
+                lines.values().filter(|&&hits| hits > 0).count(),
+                lines.len(),
+            ),
+            (
+                "Declarations",
+                covered(&self.declarations),
+                self.declarations.len(),
+            ),
+            ("Functions", functions, self.functions.len()),
+            ("Match arms", covered(&arms), arms.len()),
+            (
+                "Expressions",
+                covered(&self.expressions),
+                self.expressions.len(),
+            ),
+        ];
+        rows.iter()
+            .map(|(name, covered, total)| format!("{}: {} of {}\n", name, covered, total))
+            .collect()
+    }
+
+    /// The report in the lcov format, for the file at the path
+    pub fn lcov(&self, path: &str, source: &SourceMap) -> String {
+        let line = |span: Span| source.line_index(span.begin) + 1;
+        let mut report = format!("TN:\nSF:{}\n", path);
+
+        for function in &self.functions {
+            report.push_str(&format!("FN:{},{}\n", line(function.span), function.name));
+        }
+        for function in &self.functions {
+            report.push_str(&format!("FNDA:{},{}\n", function.calls, function.name));
+        }
+        let called = self.functions.iter().filter(|f| f.calls > 0).count();
+        report.push_str(&format!("FNF:{}\nFNH:{}\n", self.functions.len(), called));
+
+        let (mut branches, mut taken) = (0, 0);
+        for (block, match_) in self.matches.iter().enumerate() {
+            let evaluated = self.expression_indices[&match_.span];
+            let evaluated = self.expressions[evaluated].count > 0;
+            for (branch, arm) in match_.arms.iter().enumerate() {
+                let count = match evaluated {
+                    true => arm.count.to_string(),
+                    // The arms of a match that never ran were not even considered
+                    false => "-".to_string(),
+                };
+                report.push_str(&format!(
+                    "BRDA:{},{},{},{}\n",
+                    line(arm.span),
+                    block,
+                    branch,
+                    count
+                ));
+                branches += 1;
+                taken += (arm.count > 0) as usize;
+            }
+        }
+        report.push_str(&format!("BRF:{}\nBRH:{}\n", branches, taken));
+
+        let lines = self.lines(source);
+        for (line, hits) in &lines {
+            report.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        let hit = lines.values().filter(|&&hits| hits > 0).count();
+        report.push_str(&format!("LF:{}\nLH:{}\n", lines.len(), hit));
+        report.push_str("end_of_record\n");
+        report
+    }
+}
+
+impl Hook for Coverage {
+    fn evaluate(&mut self, state: &State) -> Result<(), String> {
+        if let Some(index) = state
+            .span()
+            .and_then(|span| self.expression_indices.get(&span))
+        {
+            self.expressions[*index].count += 1;
+        }
+        Ok(())
+    }
+
+    fn declaration(&mut self, state: &State, _declaration: &Declaration) -> Result<(), String> {
+        if let Some(index) = state
+            .span()
+            .and_then(|span| self.declaration_indices.get(&span))
+        {
+            self.declarations[*index].count += 1;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, state: &State, _tail: bool) {
+        if let Some(index) = state
+            .function()
+            .and_then(|name| self.function_indices.get(&name))
+        {
+            self.functions[*index].calls += 1;
+        }
+    }
+
+    fn arm(&mut self, state: &State, arm: usize) {
+        if let Some(index) = state.span().and_then(|span| self.match_indices.get(&span)) {
+            self.matches[*index].arms[arm].count += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+    use crate::virtual_machine::VirtualMachine;
+
+    const SOURCE: &str = "let sign:fn = (n:num):num => true ? {
+    n < 0 => 0 - 1,
+    n > 0 => 1,
+    0
+};
+let unused:fn = (n:num):num => n + 1;
+let x:num = sign(5) + sign(0);";
+
+    fn run(source: &str) -> Coverage {
+        let items = parse(&tokenize(source).unwrap()).unwrap();
+        let mut coverage = Coverage::new(&items);
+        let mut vm = VirtualMachine::new();
+        vm.run_with_hook(items, &mut coverage).unwrap();
+        coverage
+    }
+
+    #[test]
+    fn test_counts() {
+        let coverage = run(SOURCE);
+        let calls: Vec<(&str, usize)> = coverage
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.calls))
+            .collect();
+        assert_eq!(calls, vec![("sign", 2), ("unused", 0)]);
+        let arms: Vec<usize> = coverage.matches[0]
+            .arms
+            .iter()
+            .map(|arm| arm.count)
+            .collect();
+        assert_eq!(arms, vec![0, 1, 1]);
+        assert!(coverage
+            .declarations
+            .iter()
+            .all(|declaration| declaration.count == 1));
+        assert_eq!(
+            coverage.summary(&SourceMap::new(SOURCE)),
+            "Lines: 5 of 5\nDeclarations: 3 of 3\nFunctions: 1 of 2\nMatch arms: 2 of 3\nExpressions: 10 of 13\n"
+        );
+    }
+
+    #[test]
+    fn test_lcov() {
+        let coverage = run(SOURCE);
+        assert_eq!(
+            coverage.lcov("sign.ni", &SourceMap::new(SOURCE)),
+            "TN:
+SF:sign.ni
+FN:1,sign
+FN:6,unused
+FNDA:2,sign
+FNDA:0,unused
+FNF:2
+FNH:1
+BRDA:2,0,0,0
+BRDA:3,0,1,1
+BRDA:1,0,2,1
+BRF:3
+BRH:2
+DA:1,2
+DA:2,2
+DA:3,2
+DA:6,1
+DA:7,1
+LF:5
+LH:5
+end_of_record
+"
+        );
+    }
+}
@@ -0,0 +1,564 @@
+//! A debug adapter, speaking the Debug Adapter Protocol with the same framing as the language
+//! server, see [crate::lsp]. The program runs inside the adapter: when it stops, the adapter
+//! answers the requests of the client until one of them resumes it.
+//!
+//! Lines and columns are counted from 1, the default of the protocol. There is a single thread,
+//! and what the program prints is sent as output events.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::debugger::{describe, Breakpoint, Resume, Stepper, Stop, STOPPED};
+use crate::diagnostics::{Diagnostic, SourceMap};
+use crate::json::Json;
+use crate::lexer::Span;
+use crate::lsp::{read_message, write_message};
+use crate::parser::{Declaration, Item};
+use crate::syntax;
+use crate::virtual_machine::{Hook, State, VirtualMachine};
+
+const THREAD: usize = 1;
+/// The variables reference of the globals. The arguments of the frame `id` are `id + GLOBALS`.
+const GLOBALS: usize = 1;
+
+/// The client, and the sequence number of the next message sent to it
+struct Connection<R, W> {
+    reader: R,
+    writer: W,
+    seq: usize,
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    /// The next request, `None` when the client has closed the input. Messages that cannot be
+    /// read are skipped, since they have no sequence number to answer to.
+    fn read(&mut self) -> io::Result<Option<Json>> {
+        loop {
+            match read_message(&mut self.reader)? {
+                Some(Ok(message)) => return Ok(Some(message)),
+                Some(Err(_)) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn send(&mut self, type_: &str, members: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        let mut message = vec![("seq", self.seq.into()), ("type", type_.into())];
+        message.extend(members);
+        write_message(&mut self.writer, &Json::object(message))
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", true.into()),
+                ("command", command(request).into()),
+                ("body", body),
+            ],
+        )
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", false.into()),
+                ("command", command(request).into()),
+                ("message", message.into()),
+            ],
+        )
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send("event", vec![("event", event.into()), ("body", body)])
+    }
+
+    fn output(&mut self, category: &str, output: &str) -> io::Result<()> {
+        let body = Json::object([("category", category.into()), ("output", output.into())]);
+        self.event("output", body)
+    }
+
+    /// Answers the requests that do not depend on the program running, returns false for the
+    /// others
+    fn common(&mut self, request: &Json, breakpoints: &mut Vec<Breakpoint>) -> io::Result<bool> {
+        match command(request) {
+            "initialize" => {
+                let capabilities = Json::object([
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsFunctionBreakpoints", true.into()),
+                    ("supportsTerminateRequest", true.into()),
+                ]);
+                self.respond(request, capabilities)?;
+                self.event("initialized", Json::object([]))?;
+            }
+            "threads" => {
+                let thread = Json::object([("id", THREAD.into()), ("name", "main".into())]);
+                let body = Json::object([("threads", Json::Array(vec![thread]))]);
+                self.respond(request, body)?;
+            }
+            "setBreakpoints" => {
+                let lines: Vec<usize> = argument(request, "breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_f64))
+                    .map(|line| line as usize)
+                    .collect();
+                breakpoints.retain(|breakpoint| !matches!(breakpoint, Breakpoint::Line(_)));
+                breakpoints.extend(lines.iter().map(|&line| Breakpoint::Line(line)));
+                let verified = lines
+                    .iter()
+                    .map(|&line| Json::object([("verified", true.into()), ("line", line.into())]))
+                    .collect();
+                self.respond(
+                    request,
+                    Json::object([("breakpoints", Json::Array(verified))]),
+                )?;
+            }
+            "setFunctionBreakpoints" => {
+                let names: Vec<String> = argument(request, "breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint.get("name").and_then(Json::as_str))
+                    .map(String::from)
+                    .collect();
+                breakpoints.retain(|breakpoint| !matches!(breakpoint, Breakpoint::Function(_)));
+                let verified = names
+                    .iter()
+                    .map(|_| Json::object([("verified", true.into())]))
+                    .collect();
+                breakpoints.extend(names.into_iter().map(Breakpoint::Function));
+                self.respond(
+                    request,
+                    Json::object([("breakpoints", Json::Array(verified))]),
+                )?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or("")
+}
+
+fn argument<'a>(request: &'a Json, name: &str) -> Option<&'a Json> {
+    request.get("arguments")?.get(name)
+}
+
+/// What the program prints, until it is sent to the client
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn variable(declaration: &Declaration) -> Json {
+    Json::object([
+        ("name", declaration.name.as_str().into()),
+        ("value", describe(&declaration.expression).into()),
+        ("type", declaration.type_.to_string().into()),
+        ("variablesReference", 0.into()),
+    ])
+}
+
+/// The hook that stops the program and serves the client while it is stopped
+struct Adapter<'a, R, W> {
+    connection: Connection<R, W>,
+    path: &'a str,
+    source: SourceMap<'a>,
+    stepper: Stepper,
+    output: Output,
+    disconnected: bool,
+}
+
+impl<'a, R: BufRead, W: Write> Adapter<'a, R, W> {
+    fn flush_output(&mut self) -> io::Result<()> {
+        let bytes = std::mem::take(&mut *self.output.0.borrow_mut());
+        match bytes.is_empty() {
+            true => Ok(()),
+            false => self
+                .connection
+                .output("stdout", &String::from_utf8_lossy(&bytes)),
+        }
+    }
+
+    /// The frames of the call stack, the current one first, with the position in each of them.
+    /// The top level is the last frame.
+    fn frames(state: &State) -> Vec<(String, Option<Span>, Vec<Declaration>)> {
+        let stack = state.stack();
+        let mut span = state.span();
+        let mut frames = vec![];
+        for frame in stack.into_iter().rev() {
+            frames.push((frame.function, span, frame.arguments));
+            // The caller is where the function was called
+            span = frame.call;
+        }
+        frames.push(("<top level>".to_string(), span, vec![]));
+        frames
+    }
+
+    fn stack_trace(&self, state: &State) -> Json {
+        let name = Path::new(self.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let source = Json::object([("name", name.into()), ("path", self.path.into())]);
+        let frames: Vec<Json> = Self::frames(state)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (function, span, _))| {
+                let (line, column) = match span {
+                    Some(span) => self.source.location(span.begin),
+                    None => (0, 0),
+                };
+                Json::object([
+                    ("id", (i + 1).into()),
+                    ("name", function.into()),
+                    ("source", source.clone()),
+                    ("line", line.into()),
+                    ("column", column.into()),
+                ])
+            })
+            .collect();
+        let total = frames.len();
+        Json::object([
+            ("stackFrames", Json::Array(frames)),
+            ("totalFrames", total.into()),
+        ])
+    }
+
+    fn scopes(state: &State, frame: usize) -> Json {
+        let scope = |name: &str, reference: usize| {
+            Json::object([
+                ("name", name.into()),
+                ("variablesReference", reference.into()),
+                ("expensive", false.into()),
+            ])
+        };
+        let mut scopes = vec![];
+        if frame >= 1 && frame <= state.depth() {
+            scopes.push(scope("Arguments", frame + GLOBALS));
+        }
+        scopes.push(scope("Globals", GLOBALS));
+        Json::object([("scopes", Json::Array(scopes))])
+    }
+
+    /// The globals, or the arguments of a frame
+    fn declarations(state: &State, reference: usize) -> Vec<Declaration> {
+        if reference == GLOBALS {
+            let symbols = state.symbols();
+            return match symbols.parent() {
+                Some(globals) => globals.declarations().into_iter().cloned().collect(),
+                None => vec![],
+            };
+        }
+        match Self::frames(state).into_iter().nth(reference - GLOBALS - 1) {
+            Some((_, _, arguments)) => arguments,
+            None => vec![],
+        }
+    }
+
+    /// The value of a symbol, as seen from a frame: its arguments, then those of its callers, then
+    /// the globals
+    fn evaluate_name(state: &State, name: &str, frame: usize) -> Option<Declaration> {
+        let arguments = Self::frames(state)
+            .into_iter()
+            .skip(frame.max(1) - 1)
+            .flat_map(|(_, _, arguments)| arguments);
+        let globals = Self::declarations(state, GLOBALS);
+        arguments
+            .chain(globals)
+            .find(|declaration| declaration.name == name)
+    }
+
+    /// Tells the client why the program stopped, then answers its requests until one of them
+    /// resumes the program. `None` means the client is gone.
+    fn stop(&mut self, state: &State, stop: Stop) -> io::Result<Option<Resume>> {
+        let (reason, description) = match stop {
+            Stop::Entry => ("entry", None),
+            Stop::Step => ("step", None),
+            Stop::Breakpoint(breakpoint @ Breakpoint::Line(_)) => ("breakpoint", Some(breakpoint)),
+            Stop::Breakpoint(breakpoint) => ("function breakpoint", Some(breakpoint)),
+        };
+        let mut body = vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(breakpoint) = description {
+            body.push((
+                "description",
+                format!("Breakpoint on {}", breakpoint).into(),
+            ));
+        }
+        self.connection.event("stopped", Json::object(body))?;
+
+        loop {
+            let request = match self.connection.read()? {
+                Some(request) => request,
+                None => return Ok(None),
+            };
+            if self
+                .connection
+                .common(&request, &mut self.stepper.breakpoints)?
+            {
+                continue;
+            }
+            let frame = argument(&request, "frameId")
+                .and_then(Json::as_f64)
+                .unwrap_or(1.0) as usize;
+            let resume = match command(&request) {
+                "continue" => Resume::Continue,
+                "next" => Resume::StepOver,
+                "stepIn" => Resume::StepInto,
+                "stepOut" => Resume::StepOut,
+                "disconnect" | "terminate" => {
+                    self.connection.respond(&request, Json::object([]))?;
+                    return Ok(None);
+                }
+                "stackTrace" => {
+                    let body = self.stack_trace(state);
+                    self.connection.respond(&request, body)?;
+                    continue;
+                }
+                "scopes" => {
+                    self.connection
+                        .respond(&request, Self::scopes(state, frame))?;
+                    continue;
+                }
+                "variables" => {
+                    let reference = argument(&request, "variablesReference")
+                        .and_then(Json::as_f64)
+                        .unwrap_or(0.0) as usize;
+                    let variables = match reference {
+                        0 => vec![],
+                        reference => Self::declarations(state, reference),
+                    };
+                    let variables = variables.iter().map(variable).collect();
+                    let body = Json::object([("variables", Json::Array(variables))]);
+                    self.connection.respond(&request, body)?;
+                    continue;
+                }
+                "evaluate" => {
+                    let name = argument(&request, "expression")
+                        .and_then(Json::as_str)
+                        .unwrap_or("")
+                        .trim();
+                    match Self::evaluate_name(state, name, frame) {
+                        Some(declaration) => {
+                            let body = Json::object([
+                                ("result", describe(&declaration.expression).into()),
+                                ("type", declaration.type_.to_string().into()),
+                                ("variablesReference", 0.into()),
+                            ]);
+                            self.connection.respond(&request, body)?;
+                        }
+                        None => {
+                            let message = format!("Unknown symbol: {}", name);
+                            self.connection.fail(&request, &message)?;
+                        }
+                    }
+                    continue;
+                }
+                other => {
+                    let message = format!("Unknown request: {}", other);
+                    self.connection.fail(&request, &message)?;
+                    continue;
+                }
+            };
+            let body = match resume {
+                Resume::Continue => Json::object([("allThreadsContinued", true.into())]),
+                _ => Json::object([]),
+            };
+            self.connection.respond(&request, body)?;
+            return Ok(Some(resume));
+        }
+    }
+}
+
+impl<'a, R: BufRead, W: Write> Hook for Adapter<'a, R, W> {
+    fn evaluate(&mut self, state: &State) -> Result<(), String> {
+        self.flush_output().map_err(|error| error.to_string())?;
+        let span = match state.span() {
+            Some(span) => span,
+            None => return Ok(()),
+        };
+        let line = self.source.line_index(span.begin);
+        let stop = match self.stepper.evaluate(state, line) {
+            Some(stop) => stop,
+            None => return Ok(()),
+        };
+        match self.stop(state, stop) {
+            Ok(Some(resume)) => {
+                self.stepper.resume(resume, state);
+                Ok(())
+            }
+            Ok(None) => {
+                self.disconnected = true;
+                Err(STOPPED.to_string())
+            }
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    fn call(&mut self, state: &State, tail: bool) {
+        self.stepper.call(state, tail);
+    }
+
+    fn return_(&mut self, _state: &State) {
+        self.stepper.return_();
+    }
+}
+
+/// The program to debug, from the launch request
+struct Launch {
+    path: String,
+    source: String,
+    stop_on_entry: bool,
+    no_debug: bool,
+}
+
+/// Debugs a program for a client until it disconnects or closes the input
+pub fn run(reader: impl BufRead, writer: impl Write) -> io::Result<()> {
+    let mut connection = Connection {
+        reader,
+        writer,
+        seq: 0,
+    };
+    let mut breakpoints = vec![];
+
+    // The program starts once it is launched and the breakpoints are set
+    let mut launch = None;
+    let mut configured = false;
+    while launch.is_none() || !configured {
+        let request = match connection.read()? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        if connection.common(&request, &mut breakpoints)? {
+            continue;
+        }
+        match command(&request) {
+            "launch" => {
+                let path = argument(&request, "program")
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                match fs::read_to_string(path) {
+                    Ok(source) => {
+                        let flag = |name| argument(&request, name) == Some(&Json::Bool(true));
+                        launch = Some(Launch {
+                            path: path.to_string(),
+                            source,
+                            stop_on_entry: flag("stopOnEntry"),
+                            no_debug: flag("noDebug"),
+                        });
+                        connection.respond(&request, Json::object([]))?;
+                    }
+                    Err(error) => {
+                        let message = format!("Cannot read {}: {}", path, error.kind());
+                        connection.fail(&request, &message)?;
+                    }
+                }
+            }
+            "configurationDone" => {
+                configured = true;
+                connection.respond(&request, Json::object([]))?;
+            }
+            "disconnect" | "terminate" => {
+                connection.respond(&request, Json::object([]))?;
+                return Ok(());
+            }
+            other => {
+                let message = match other {
+                    "stackTrace" | "scopes" | "variables" | "evaluate" | "continue" | "next"
+                    | "stepIn" | "stepOut" => "The program is not running".to_string(),
+                    other => format!("Unknown request: {}", other),
+                };
+                connection.fail(&request, &message)?;
+            }
+        }
+    }
+    let launch = launch.unwrap();
+    if launch.no_debug {
+        breakpoints.clear();
+    }
+
+    let source = SourceMap::new(&launch.source);
+    let mut adapter = Adapter {
+        connection,
+        path: &launch.path,
+        source,
+        stepper: Stepper::new(launch.stop_on_entry && !launch.no_debug),
+        output: Output::default(),
+        disconnected: false,
+    };
+    adapter.stepper.breakpoints = breakpoints;
+    let errors = match items(&launch.source) {
+        Ok(items) => {
+            let mut vm = VirtualMachine::new();
+            vm.set_output(adapter.output.clone());
+            let result = vm.run_with_hook(items, &mut adapter);
+            adapter.flush_output()?;
+            match result {
+                Err(_) if adapter.disconnected => return Ok(()),
+                Err(error) => vec![Diagnostic::from(&error)],
+                Ok(()) => vec![],
+            }
+        }
+        Err(errors) => errors,
+    };
+    let mut connection = adapter.connection;
+    for error in &errors {
+        let rendered = error.render(&launch.path, &adapter.source, false);
+        connection.output("stderr", &format!("{}\n", rendered))?;
+    }
+    let code = if errors.is_empty() { 0 } else { 1 };
+    connection.event("exited", Json::object([("exitCode", code.into())]))?;
+    connection.event("terminated", Json::object([]))?;
+
+    // The client can still ask, until it disconnects
+    while let Some(request) = connection.read()? {
+        if connection.common(&request, &mut vec![])? {
+            continue;
+        }
+        match command(&request) {
+            "disconnect" => {
+                connection.respond(&request, Json::object([]))?;
+                return Ok(());
+            }
+            _ => connection.fail(&request, "The program has ended")?,
+        }
+    }
+    Ok(())
+}
+
+/// The program, or the errors that keep it from running
+fn items(source: &str) -> Result<Vec<Item>, Vec<Diagnostic>> {
+    let tree = syntax::parse(source).map_err(|error| vec![Diagnostic::from(&error)])?;
+    match tree.to_items() {
+        (items, errors) if errors.is_empty() => Ok(items),
+        (_, errors) => Err(errors.iter().map(Diagnostic::from).collect()),
+    }
+}
@@ -0,0 +1,507 @@
+//! An interactive debugger, driven by commands read line by line, see `ninoi --debug`.
+//!
+//! The debugger stops on lines, like the debuggers of other languages: stepping runs until the
+//! program reaches another line, or the same line in another call. Breakpoints are set on a line,
+//! or on a function to stop when it is called.
+
+use std::io::{BufRead, Write};
+
+use crate::diagnostics::SourceMap;
+use crate::parser::{Declaration, Expression, Type};
+use crate::virtual_machine::{Hook, State};
+
+const HELP: &str = "Commands:
+  step, s              Run until the next line, entering calls
+  next, n              Run until the next line of this function
+  finish, f            Run until the current function returns
+  continue, c          Run until a breakpoint
+  break, b <line|fn>   Stop on a line, or when a function is called
+  delete, d <line|fn>  Remove a breakpoint
+  breakpoints          List the breakpoints
+  print, p <name>      Print a symbol
+  args                 Print the arguments of the current function
+  scope                Print the symbols that can be used, innermost scope first
+  backtrace, bt        Print the call stack
+  quit, q              Stop the program
+An empty line repeats the last command.";
+
+/// The message of the error that stops the program when the debugger quits
+pub const STOPPED: &str = "Stopped by the debugger";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// Counting from 1
+    Line(usize),
+    Function(String),
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line(line) => write!(f, "line {}", line),
+            Breakpoint::Function(name) => write!(f, "function {}", name),
+        }
+    }
+}
+
+/// How to run after a stop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    /// Until a breakpoint
+    Continue,
+    /// Until the next line, entering calls
+    StepInto,
+    /// Until the next line of the current function
+    StepOver,
+    /// Until the current function returns
+    StepOut,
+}
+
+/// Why the program stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// Before the program starts
+    Entry,
+    Step,
+    Breakpoint(Breakpoint),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    StepInto,
+    /// Stops in functions at most as deep as this
+    StepOver(usize),
+    /// Stops in functions less deep than this
+    StepOut(usize),
+}
+
+/// Decides where the program stops, for the debugger and the debug adapter. It is told what the
+/// virtual machine does by the same calls as a [Hook].
+#[derive(Debug)]
+pub struct Stepper {
+    pub breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    /// An identifier for each function call that has not returned, since a recursive call is on
+    /// the same lines as its caller
+    frames: Vec<usize>,
+    calls: usize,
+    /// The line and the call of the last expression
+    position: Option<(usize, usize)>,
+    /// Set when a function breakpoint is hit, to stop at the next expression
+    pending: Option<Breakpoint>,
+}
+
+impl Stepper {
+    pub fn new(stop_on_entry: bool) -> Self {
+        Stepper {
+            breakpoints: vec![],
+            mode: match stop_on_entry {
+                true => Mode::StepInto,
+                false => Mode::Continue,
+            },
+            frames: vec![],
+            calls: 0,
+            position: None,
+            pending: None,
+        }
+    }
+
+    pub fn resume(&mut self, resume: Resume, state: &State) {
+        self.mode = match resume {
+            Resume::Continue => Mode::Continue,
+            Resume::StepInto => Mode::StepInto,
+            Resume::StepOver => Mode::StepOver(state.depth()),
+            Resume::StepOut => Mode::StepOut(state.depth()),
+        };
+    }
+
+    /// Whether to stop before the expression, which is on the line counting from 0
+    pub fn evaluate(&mut self, state: &State, line: usize) -> Option<Stop> {
+        let position = (line, self.frames.last().copied().unwrap_or(0));
+        let entry = self.position.is_none();
+        let moved = self.position != Some(position);
+        self.position = Some(position);
+
+        if let Some(breakpoint) = self.pending.take() {
+            return Some(Stop::Breakpoint(breakpoint));
+        }
+        if !moved {
+            return None;
+        }
+        let breakpoint = Breakpoint::Line(line + 1);
+        if self.breakpoints.contains(&breakpoint) {
+            return Some(Stop::Breakpoint(breakpoint));
+        }
+        let depth = state.depth();
+        let step = match self.mode {
+            Mode::Continue => false,
+            Mode::StepInto => true,
+            Mode::StepOver(over) => depth <= over,
+            Mode::StepOut(out) => depth < out,
+        };
+        match (step, entry) {
+            (false, _) => None,
+            (true, true) => Some(Stop::Entry),
+            (true, false) => Some(Stop::Step),
+        }
+    }
+
+    pub fn call(&mut self, state: &State, tail: bool) {
+        self.calls += 1;
+        match self.frames.last_mut() {
+            Some(frame) if tail => *frame = self.calls,
+            _ => self.frames.push(self.calls),
+        }
+        if let Some(function) = state.function() {
+            let breakpoint = Breakpoint::Function(function);
+            if self.breakpoints.contains(&breakpoint) {
+                self.pending = Some(breakpoint);
+            }
+        }
+    }
+
+    pub fn return_(&mut self) {
+        self.frames.pop();
+    }
+}
+
+pub struct Debugger<'a, R, W> {
+    source: SourceMap<'a>,
+    input: R,
+    output: W,
+    pub stepper: Stepper,
+    last_command: String,
+}
+
+/// A value for display, like it is written in the source
+pub fn describe(expression: &Expression) -> String {
+    match expression {
+        Expression::Number(value) => value.to_string(),
+        Expression::Char(value) => format!("{:?}", *value as char),
+        Expression::Bool(value) => value.to_string(),
+        Expression::Array(Type::Char, elements) => {
+            let bytes: Vec<u8> = elements
+                .iter()
+                .filter_map(|element| match element {
+                    Expression::Char(value) => Some(*value),
+                    _ => None,
+                })
+                .collect();
+            format!("{:?}", String::from_utf8_lossy(&bytes))
+        }
+        Expression::Array(_, elements) => {
+            let elements: Vec<String> = elements.iter().map(describe).collect();
+            format!("[{}]", elements.join(", "))
+        }
+        Expression::FunctionDeclaration(function) => {
+            let parameters: Vec<String> = function
+                .parameters
+                .iter()
+                .map(|parameter| format!("{}:{}", parameter.name, parameter.type_))
+                .collect();
+            format!("fn ({}):{}", parameters.join(", "), function.return_type)
+        }
+        expression => expression.to_string(),
+    }
+}
+
+fn describe_declaration(declaration: &Declaration) -> String {
+    format!(
+        "{}:{} = {}",
+        declaration.name,
+        declaration.type_,
+        describe(&declaration.expression)
+    )
+}
+
+impl<'a, R: BufRead, W: Write> Debugger<'a, R, W> {
+    /// The debugger stops before the program starts
+    pub fn new(source: &'a str, input: R, output: W) -> Self {
+        Debugger {
+            source: SourceMap::new(source),
+            input,
+            output,
+            stepper: Stepper::new(true),
+            last_command: String::new(),
+        }
+    }
+
+    fn location(&self, offset: usize) -> String {
+        let (line, column) = self.source.location(offset);
+        format!("{}:{}", line, column)
+    }
+
+    /// Prints where the program is, then runs the commands until one of them resumes it
+    fn stop(&mut self, state: &State, stop: Stop) -> Result<(), String> {
+        let span = state.span().unwrap();
+        let line = self.source.line_index(span.begin);
+        if let Stop::Breakpoint(breakpoint) = stop {
+            self.print(&format!("Breakpoint on {}", breakpoint));
+        }
+        let function = state
+            .function()
+            .unwrap_or_else(|| "<top level>".to_string());
+        self.print(&format!("{} at {}", function, self.location(span.begin)));
+        self.print(&format!("{:>4} | {}", line + 1, self.source.line(line)));
+
+        loop {
+            write!(self.output, "(ninodb) ").map_err(|error| error.to_string())?;
+            self.output.flush().map_err(|error| error.to_string())?;
+            let mut line = String::new();
+            let read = self
+                .input
+                .read_line(&mut line)
+                .map_err(|error| error.to_string())?;
+            if read == 0 {
+                // Nobody is left to give commands, so the program runs to the end
+                self.stepper.breakpoints.clear();
+                self.stepper.resume(Resume::Continue, state);
+                return Ok(());
+            }
+            let mut line = line.trim().to_string();
+            if line.is_empty() {
+                line = self.last_command.clone();
+            }
+            self.last_command = line.clone();
+            let (command, argument) = match line.split_once(' ') {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (line.as_str(), ""),
+            };
+            let resume = match command {
+                "step" | "s" => Resume::StepInto,
+                "next" | "n" => Resume::StepOver,
+                "finish" | "f" => Resume::StepOut,
+                "continue" | "c" => Resume::Continue,
+                "quit" | "q" => return Err(STOPPED.to_string()),
+                _ => {
+                    self.command(state, command, argument);
+                    continue;
+                }
+            };
+            self.stepper.resume(resume, state);
+            return Ok(());
+        }
+    }
+
+    /// Runs a command that does not resume the program
+    fn command(&mut self, state: &State, command: &str, argument: &str) {
+        match command {
+            "break" | "b" | "delete" | "d" if argument.is_empty() => {
+                self.print("Expected a line or a function name")
+            }
+            "break" | "b" => {
+                let breakpoint = match argument.parse() {
+                    Ok(line) => Breakpoint::Line(line),
+                    Err(_) => Breakpoint::Function(argument.to_string()),
+                };
+                self.print(&format!("Breakpoint set on {}", breakpoint));
+                if !self.stepper.breakpoints.contains(&breakpoint) {
+                    self.stepper.breakpoints.push(breakpoint);
+                }
+            }
+            "delete" | "d" => {
+                let count = self.stepper.breakpoints.len();
+                self.stepper
+                    .breakpoints
+                    .retain(|breakpoint| match breakpoint {
+                        Breakpoint::Line(line) => line.to_string() != argument,
+                        Breakpoint::Function(name) => name != argument,
+                    });
+                if self.stepper.breakpoints.len() == count {
+                    self.print(&format!("No breakpoint on {}", argument));
+                }
+            }
+            "breakpoints" => {
+                let breakpoints: Vec<String> = self
+                    .stepper
+                    .breakpoints
+                    .iter()
+                    .map(|b| b.to_string())
+                    .collect();
+                match breakpoints.is_empty() {
+                    true => self.print("No breakpoints"),
+                    false => self.print(&breakpoints.join("\n")),
+                }
+            }
+            "print" | "p" => match state.symbols().get(argument) {
+                Some(declaration) => self.print(&describe_declaration(&declaration)),
+                None => self.print(&format!("Unknown symbol: {}", argument)),
+            },
+            "args" => match state.stack().pop() {
+                Some(frame) => {
+                    for argument in &frame.arguments {
+                        self.print(&describe_declaration(argument));
+                    }
+                }
+                None => self.print("Not in a function"),
+            },
+            "scope" => {
+                let symbols = state.symbols();
+                let mut scope = Some(&symbols);
+                while let Some(symbols) = scope {
+                    let title = match symbols.parent() {
+                        Some(_) => "Arguments:",
+                        None => "Globals:",
+                    };
+                    self.print(title);
+                    for declaration in symbols.declarations() {
+                        self.print(&format!("  {}", describe_declaration(declaration)));
+                    }
+                    scope = symbols.parent();
+                }
+            }
+            "backtrace" | "bt" => {
+                for (i, frame) in state.stack().iter().rev().enumerate() {
+                    let arguments: Vec<String> = frame
+                        .arguments
+                        .iter()
+                        .map(|argument| {
+                            format!("{} = {}", argument.name, describe(&argument.expression))
+                        })
+                        .collect();
+                    let call = match frame.call {
+                        Some(span) => format!(" called at {}", self.location(span.begin)),
+                        None => String::new(),
+                    };
+                    self.print(&format!(
+                        "#{} {}({}){}",
+                        i,
+                        frame.function,
+                        arguments.join(", "),
+                        call
+                    ));
+                }
+                self.print(&format!("#{} <top level>", state.depth()));
+            }
+            "help" | "h" => self.print(HELP),
+            _ => self.print(&format!("Unknown command {}, try help", command)),
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        // The output is only for the person debugging, a failure to write is not worth stopping for
+        let _ = writeln!(self.output, "{}", text);
+    }
+}
+
+impl<'a, R: BufRead, W: Write> Hook for Debugger<'a, R, W> {
+    fn evaluate(&mut self, state: &State) -> Result<(), String> {
+        let span = match state.span() {
+            Some(span) => span,
+            None => return Ok(()),
+        };
+        let line = self.source.line_index(span.begin);
+        match self.stepper.evaluate(state, line) {
+            Some(stop) => self.stop(state, stop),
+            None => Ok(()),
+        }
+    }
+
+    fn call(&mut self, state: &State, tail: bool) {
+        self.stepper.call(state, tail);
+    }
+
+    fn return_(&mut self, _state: &State) {
+        self.stepper.return_();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+    use crate::virtual_machine::VirtualMachine;
+
+    const SOURCE: &str = "let factorial:fn = (n:num):num => n ? {
+    0 => 1,
+    n * factorial(n - 1)
+};
+let x:num = 2;
+let result:num = factorial(x);";
+
+    /// Runs the program with the commands, and returns what the debugger printed
+    fn debug(commands: &str) -> (String, Result<(), String>) {
+        let items = parse(&tokenize(SOURCE).unwrap()).unwrap();
+        let mut output = vec![];
+        let mut debugger = Debugger::new(SOURCE, commands.as_bytes(), &mut output);
+        let result = VirtualMachine::new().run_with_hook(items, &mut debugger);
+        let result = result.map_err(|error| error.message);
+        (String::from_utf8(output).unwrap(), result)
+    }
+
+    #[test]
+    fn test_stepping() {
+        let (output, result) = debug("n\nn\ns\ns\nbt\nq\n");
+        assert_eq!(result, Err(STOPPED.to_string()));
+        assert_eq!(
+            output,
+            "<top level> at 1:1
+   1 | let factorial:fn = (n:num):num => n ? {
+(ninodb) <top level> at 5:1
+   5 | let x:num = 2;
+(ninodb) <top level> at 6:1
+   6 | let result:num = factorial(x);
+(ninodb) factorial at 1:37
+   1 | let factorial:fn = (n:num):num => n ? {
+(ninodb) factorial at 3:7
+   3 |     n * factorial(n - 1)
+(ninodb) #0 factorial(n = 2) called at 6:18
+#1 <top level>
+(ninodb) "
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (output, result) = debug("b 3\nb factorial\nc\nd factorial\nc\np n\nscope\nbt\nc\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            "<top level> at 1:1
+   1 | let factorial:fn = (n:num):num => n ? {
+(ninodb) Breakpoint set on line 3
+(ninodb) Breakpoint set on function factorial
+(ninodb) Breakpoint on function factorial
+factorial at 1:37
+   1 | let factorial:fn = (n:num):num => n ? {
+(ninodb) (ninodb) Breakpoint on line 3
+factorial at 3:7
+   3 |     n * factorial(n - 1)
+(ninodb) n:num = 2
+(ninodb) Arguments:
+  n:num = 2
+Globals:
+  factorial:fn = fn (n:num):num
+  x:num = 2
+(ninodb) #0 factorial(n = 2) called at 6:18
+#1 <top level>
+(ninodb) Breakpoint on line 3
+factorial at 3:7
+   3 |     n * factorial(n - 1)
+(ninodb) "
+        );
+    }
+
+    #[test]
+    fn test_end_of_input() {
+        // The program runs to the end when there are no more commands
+        let (output, result) = debug("b 3\n");
+        assert_eq!(result, Ok(()));
+        assert!(output.ends_with("(ninodb) Breakpoint set on line 3\n(ninodb) "));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(&Expression::Number(1.5)), "1.5");
+        assert_eq!(describe(&Expression::Char(b'a')), "'a'");
+        let string = Expression::Array(Type::Char, vec![Expression::Char(b'h')]);
+        assert_eq!(describe(&string), "\"h\"");
+        let array = Expression::Array(
+            Type::Number,
+            vec![Expression::Number(1.0), Expression::Bool(true)],
+        );
+        assert_eq!(describe(&array), "[1, true]");
+    }
+}
@@ -0,0 +1,501 @@
+//! Renders errors together with the part of the source they are about.
+//!
+//! Every phase reports its errors with byte offsets into the source. A [SourceMap] turns those
+//! offsets into lines and columns, and a [Diagnostic] is rendered like this:
+//!
+//! ```text
+//! error: Unexpected token: Semicolon
+//!  --> example.ni:1:13
+//!   |
+//! 1 | let x:num = ;
+//!   |             ^
+//! ```
+//!
+//! Tools can also print diagnostics as JSON lines with [Diagnostic::to_json].
+
+use crate::json::Json;
+use crate::lexer::{LexerError, Span};
+use crate::parser::ParserError;
+use crate::virtual_machine::RuntimeError;
+
+/// How wide a tab is when a line is displayed
+const TAB_WIDTH: usize = 4;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Turns byte offsets into lines and columns
+pub struct SourceMap<'a> {
+    source: &'a str,
+    /// The offset of the first character of every line
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        SourceMap {
+            source,
+            line_starts,
+        }
+    }
+
+    /// The line the offset is on, counting from 0
+    pub fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        }
+    }
+
+    /// The line and column of the offset, both counting from 1. Columns count characters.
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_index(offset);
+        let start = self.line_starts[line];
+        let offset = offset.min(self.source.len());
+        let column = match self.source.get(start..offset) {
+            Some(text) => text.chars().count(),
+            None => offset - start,
+        };
+        (line + 1, column + 1)
+    }
+
+    /// The text of the line, counting from 0, without the line break
+    pub fn line(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line];
+        let end = match self.line_starts.get(line + 1) {
+            Some(next) => next - 1,
+            None => self.source.len(),
+        };
+        self.source[start..end].trim_end_matches('\r')
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Note => "\x1b[1;36m",
+        }
+    }
+}
+
+/// A part of the source, underlined with a message next to it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Which kind of problem it is, for example `syntax`
+    pub code: Option<&'static str>,
+    pub message: String,
+    /// The first label is the primary one, which the location in the header points to
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            code: None,
+            message: message.into(),
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic for the source of `file`. With `color` the output contains ANSI
+    /// escape codes.
+    pub fn render(&self, file: &str, source: &SourceMap, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut lines: Vec<usize> = vec![];
+        for label in &self.labels {
+            let first = source.line_index(label.span.begin);
+            let last = source.line_index(label.span.end.max(label.span.begin));
+            lines.extend(first..=last);
+        }
+        lines.sort_unstable();
+        lines.dedup();
+        let gutter = lines.last().map_or(0, |line| (line + 1).to_string().len());
+        let empty_gutter = paint(BLUE, &format!("{} |", " ".repeat(gutter)));
+
+        let mut output = format!(
+            "{}{}",
+            paint(self.severity.color(), self.severity.name()),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+        match self.labels.first() {
+            Some(label) => {
+                let (line, column) = source.location(label.span.begin);
+                output += &format!(
+                    "\n{}{} {}:{}:{}",
+                    " ".repeat(gutter),
+                    paint(BLUE, "-->"),
+                    file,
+                    line,
+                    column
+                );
+            }
+            None => output += &format!("\n{} {}", paint(BLUE, "-->"), file),
+        }
+        if !lines.is_empty() {
+            output += &format!("\n{}", empty_gutter);
+        }
+
+        for (i, &line) in lines.iter().enumerate() {
+            if i > 0 && lines[i - 1] + 1 != line {
+                output += &format!("\n{}", paint(BLUE, "..."));
+            }
+            let text = source.line(line);
+            output += &format!(
+                "\n{} {}",
+                paint(BLUE, &format!("{:>width$} |", line + 1, width = gutter)),
+                text.replace('\t', &" ".repeat(TAB_WIDTH))
+            );
+            let line_start = source.line_starts[line];
+            for (index, label) in self.labels.iter().enumerate() {
+                let end = label.span.end.max(label.span.begin);
+                let first = source.line_index(label.span.begin);
+                let last = source.line_index(end);
+                if line < first || line > last {
+                    continue;
+                }
+                let (padding, width) = underline(text, line_start, label.span.begin, end);
+                let marker = if index == 0 { "^" } else { "-" };
+                let style = if index == 0 {
+                    self.severity.color()
+                } else {
+                    BLUE
+                };
+                let mut underline = marker.repeat(width);
+                // The message goes after the last line of the label
+                if line == last && !label.message.is_empty() {
+                    underline = format!("{} {}", underline, label.message);
+                }
+                output += &format!(
+                    "\n{} {}{}",
+                    empty_gutter,
+                    " ".repeat(padding),
+                    paint(style, &underline)
+                );
+            }
+        }
+
+        for note in &self.notes {
+            output += &format!(
+                "\n{} {} {}",
+                " ".repeat(gutter),
+                paint(BLUE, "="),
+                paint(BOLD, &format!("note: {}", note))
+            );
+        }
+        output
+    }
+}
+
+impl Diagnostic {
+    /// The diagnostic as a JSON object. Ranges are byte offsets where the end is excluded, and
+    /// lines and columns count from 1.
+    pub fn to_json(&self, file: &str, source: &SourceMap) -> Json {
+        let range = |span: Span| {
+            // The end is after the last character, which may be more than a byte long
+            let end = span.end.max(span.begin);
+            let end = end
+                + source
+                    .source
+                    .get(end..)
+                    .and_then(|rest| rest.chars().next())
+                    .map_or(1, char::len_utf8);
+            let (line, column) = source.location(span.begin);
+            let (end_line, end_column) = source.location(end);
+            Json::object([
+                ("begin", span.begin.into()),
+                ("end", end.into()),
+                ("line", line.into()),
+                ("column", column.into()),
+                ("end_line", end_line.into()),
+                ("end_column", end_column.into()),
+            ])
+        };
+        Json::object([
+            ("file", file.into()),
+            ("severity", self.severity.name().into()),
+            ("code", self.code.map_or(Json::Null, Json::from)),
+            ("message", self.message.as_str().into()),
+            (
+                "range",
+                self.labels
+                    .first()
+                    .map_or(Json::Null, |label| range(label.span)),
+            ),
+            (
+                "labels",
+                Json::Array(
+                    self.labels
+                        .iter()
+                        .map(|label| {
+                            Json::object([
+                                ("range", range(label.span)),
+                                ("message", label.message.as_str().into()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "notes",
+                Json::Array(self.notes.iter().map(|note| note.as_str().into()).collect()),
+            ),
+        ])
+    }
+}
+
+/// Where the underline of the part of the span that is on the line begins and how wide it is,
+/// in displayed columns. The span includes `end`.
+fn underline(text: &str, line_start: usize, begin: usize, end: usize) -> (usize, usize) {
+    let mut padding = 0;
+    let mut width = 0;
+    // A span that continues from an earlier line is not underlined under the indentation
+    let indentation = if begin < line_start {
+        text.len() - text.trim_start().len()
+    } else {
+        0
+    };
+    for (i, c) in text.char_indices() {
+        let columns = if c == '\t' { TAB_WIDTH } else { 1 };
+        if line_start + i < begin || i < indentation {
+            padding += columns;
+        } else if line_start + i <= end {
+            width += columns;
+        }
+    }
+    // Spans at the end of a line point right after its last character
+    (padding, width.max(1))
+}
+
+impl From<&LexerError> for Diagnostic {
+    fn from(error: &LexerError) -> Self {
+        Diagnostic::error(error.message.clone())
+            .with_code("lexer")
+            .with_label(error.span, "")
+    }
+}
+
+impl From<&ParserError> for Diagnostic {
+    fn from(error: &ParserError) -> Self {
+        let diagnostic = Diagnostic::error(error.message.clone()).with_code("syntax");
+        match &error.token {
+            Some(token) => diagnostic.with_label(token.span(), ""),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        // The lines after the first one are details, like the values of a failed `assert_eq`
+        let mut lines = error.message.lines();
+        let mut diagnostic =
+            Diagnostic::error(lines.next().unwrap_or_default()).with_code("runtime");
+        for line in lines {
+            diagnostic = diagnostic.with_note(line);
+        }
+        match error.span {
+            Some(span) => diagnostic.with_label(span, ""),
+            None => diagnostic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(begin: usize, end: usize) -> Span {
+        Span { begin, end }
+    }
+
+    #[test]
+    fn test_location() {
+        let source = SourceMap::new("let x:num = 1;\nlet é:num = 2;\n\nx");
+        assert_eq!(source.location(0), (1, 1));
+        assert_eq!(source.location(13), (1, 14));
+        assert_eq!(source.location(15), (2, 1));
+        // Columns count characters, and é takes two bytes
+        assert_eq!(source.location(22), (2, 7));
+        assert_eq!(source.location(31), (3, 1));
+        assert_eq!(source.location(32), (4, 1));
+        assert_eq!(source.line(1), "let é:num = 2;");
+        assert_eq!(source.line(2), "");
+    }
+
+    #[test]
+    fn test_render() {
+        let source = SourceMap::new("let x:num = 1;\nlet y:num = ;\n");
+        let diagnostic = Diagnostic::error("Unexpected token: Semicolon")
+            .with_label(span(27, 27), "expected an expression")
+            .with_note("every declaration needs a value");
+        assert_eq!(
+            diagnostic.render("test.ni", &source, false),
+            "error: Unexpected token: Semicolon
+ --> test.ni:2:13
+  |
+2 | let y:num = ;
+  |             ^ expected an expression
+  = note: every declaration needs a value"
+        );
+    }
+
+    #[test]
+    fn test_render_line_start() {
+        let source = SourceMap::new("$");
+        let diagnostic = Diagnostic::error("Unexpected character: $").with_label(span(0, 0), "");
+        assert_eq!(
+            diagnostic.render("test.ni", &source, false),
+            "error: Unexpected character: $
+ --> test.ni:1:1
+  |
+1 | $
+  | ^"
+        );
+    }
+
+    #[test]
+    fn test_render_multiple_lines() {
+        let source = SourceMap::new("let x:num = 2 ? {\n\t1 => 2,\n\t3 => 4\n};");
+        let diagnostic = Diagnostic::error("No matching pattern found")
+            .with_label(span(14, 35), "")
+            .with_label(span(12, 12), "this is 2");
+        assert_eq!(
+            diagnostic.render("test.ni", &source, false),
+            "error: No matching pattern found
+ --> test.ni:1:15
+  |
+1 | let x:num = 2 ? {
+  |               ^^^
+  |             - this is 2
+2 |     1 => 2,
+  |     ^^^^^^^
+3 |     3 => 4
+  |     ^^^^^^
+4 | };
+  | ^"
+        );
+    }
+
+    #[test]
+    fn test_render_distant_lines() {
+        let source = SourceMap::new("a\nb\nc\nd");
+        let diagnostic = Diagnostic::error("Error")
+            .with_label(span(6, 6), "here")
+            .with_label(span(0, 0), "and here");
+        assert_eq!(
+            diagnostic.render("test.ni", &source, false),
+            "error: Error
+ --> test.ni:4:1
+  |
+1 | a
+  | - and here
+...
+4 | d
+  | ^ here"
+        );
+    }
+
+    #[test]
+    fn test_render_without_labels() {
+        let diagnostic = Diagnostic::error("Unexpected end of input");
+        assert_eq!(
+            diagnostic.render("test.ni", &SourceMap::new(""), false),
+            "error: Unexpected end of input\n--> test.ni"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let source = SourceMap::new("let x:num = 1;\nlet y:num = ;\n");
+        let diagnostic = Diagnostic::error("Unexpected token: Semicolon")
+            .with_code("syntax")
+            .with_label(span(27, 27), "expected an expression");
+        assert_eq!(
+            diagnostic.to_json("test.ni", &source).to_string(),
+            r#"{"file":"test.ni","severity":"error","code":"syntax","message":"Unexpected token: Semicolon","range":{"begin":27,"end":28,"line":2,"column":13,"end_line":2,"end_column":14},"labels":[{"range":{"begin":27,"end":28,"line":2,"column":13,"end_line":2,"end_column":14},"message":"expected an expression"}],"notes":[]}"#
+        );
+        let diagnostic = Diagnostic::error("Unexpected end of input");
+        assert_eq!(
+            diagnostic.to_json("test.ni", &source).to_string(),
+            r#"{"file":"test.ni","severity":"error","code":null,"message":"Unexpected end of input","range":null,"labels":[],"notes":[]}"#
+        );
+    }
+
+    #[test]
+    fn test_json_multibyte() {
+        let source = SourceMap::new("\tlet x:num = é;\n");
+        let diagnostic = Diagnostic::error("Unexpected character").with_label(span(13, 13), "");
+        assert_eq!(
+            diagnostic.to_json("test.ni", &source).to_string(),
+            r#"{"file":"test.ni","severity":"error","code":null,"message":"Unexpected character","range":{"begin":13,"end":15,"line":1,"column":14,"end_line":1,"end_column":15},"labels":[{"range":{"begin":13,"end":15,"line":1,"column":14,"end_line":1,"end_column":15},"message":""}],"notes":[]}"#
+        );
+    }
+
+    #[test]
+    fn test_render_color() {
+        let source = SourceMap::new("x");
+        let diagnostic = Diagnostic::error("Error").with_label(span(0, 0), "");
+        let output = diagnostic.render("test.ni", &source, true);
+        assert!(output.starts_with("\x1b[1;31merror\x1b[0m"));
+        assert!(output.contains("\x1b[1;31m^\x1b[0m"));
+    }
+}
@@ -0,0 +1,311 @@
+//! Prints a program in the canonical style, from its concrete syntax tree so that the comments
+//! are kept.
+//!
+//! The style is the one of the examples: spaces around binary operators, `=` and `=>`, none around
+//! the `:` of type annotations, and every arm of a match on its own line, indented by four spaces.
+//! Blank lines are kept, but several in a row become one. Formatting formatted code does not
+//! change it.
+
+use crate::lexer::TokenKind;
+use crate::syntax::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken, Trivia};
+
+const INDENT: &str = "    ";
+
+/// What goes between two tokens. They are ordered so that the largest request wins.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Separator {
+    None,
+    Space,
+    Newline,
+    BlankLine,
+}
+
+struct Writer {
+    output: String,
+    indent: usize,
+    pending: Separator,
+}
+
+impl Writer {
+    fn separate(&mut self, separator: Separator) {
+        if separator > self.pending {
+            self.pending = separator;
+        }
+    }
+
+    /// Writes the pending separator, then the text
+    fn write(&mut self, text: &str) {
+        if !self.output.is_empty() {
+            match self.pending {
+                Separator::None => {}
+                Separator::Space => self.output.push(' '),
+                Separator::Newline | Separator::BlankLine => {
+                    if self.pending == Separator::BlankLine {
+                        self.output.push('\n');
+                    }
+                    self.output.push('\n');
+                    self.output.push_str(&INDENT.repeat(self.indent));
+                }
+            }
+        }
+        self.pending = Separator::None;
+        self.output.push_str(text);
+    }
+
+    fn token(&mut self, token: &SyntaxToken) {
+        self.leading(&token.leading);
+        self.write(&token.text);
+
+        // The comments after the token stay on its line
+        for trivia in token
+            .trailing
+            .iter()
+            .filter(|trivia| !trivia.is_whitespace())
+        {
+            self.separate(Separator::Space);
+            self.write(&trivia.text);
+            if is_line_comment(&trivia.text) {
+                self.separate(Separator::Newline);
+            } else {
+                self.separate(Separator::Space);
+            }
+        }
+    }
+
+    /// Writes the comments before a token on their own lines, keeping the blank lines
+    fn leading(&mut self, leading: &[Trivia]) {
+        let mut newlines = 0;
+        for (i, trivia) in leading.iter().enumerate() {
+            if trivia.is_whitespace() {
+                newlines += trivia.text.matches('\n').count();
+                continue;
+            }
+            self.separate(Separator::Newline);
+            if newlines > 1 {
+                self.separate(Separator::BlankLine);
+            }
+            newlines = 0;
+            self.write(&trivia.text);
+            let followed_by_newline = leading
+                .get(i + 1)
+                .is_some_and(|next| next.text.contains('\n'));
+            if is_line_comment(&trivia.text) || followed_by_newline {
+                self.separate(Separator::Newline);
+            } else {
+                self.separate(Separator::Space);
+            }
+        }
+        if newlines > 1 && self.pending >= Separator::Newline {
+            self.separate(Separator::BlankLine);
+        }
+    }
+
+    fn element(&mut self, element: &SyntaxElement) {
+        match element {
+            SyntaxElement::Node(node) => self.node(node),
+            SyntaxElement::Token(token) => self.token(token),
+        }
+    }
+
+    fn node(&mut self, node: &SyntaxNode) {
+        match node.kind {
+            SyntaxKind::Program => self.program(node),
+            SyntaxKind::Match => self.match_(node),
+            kind => {
+                let mut previous: Option<&SyntaxElement> = None;
+                for child in &node.children {
+                    if let Some(previous) = previous {
+                        self.separate(separator(kind, previous, child));
+                    }
+                    self.element(child);
+                    previous = Some(child);
+                }
+            }
+        }
+    }
+
+    fn program(&mut self, node: &SyntaxNode) {
+        for child in &node.children {
+            self.separate(Separator::Newline);
+            match child {
+                // Only the comments at the end of the file are left
+                SyntaxElement::Token(eof) => self.leading(&eof.leading),
+                SyntaxElement::Node(item) => self.node(item),
+            }
+        }
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+    }
+
+    fn match_(&mut self, node: &SyntaxNode) {
+        for child in &node.children {
+            match child {
+                SyntaxElement::Node(SyntaxNode {
+                    kind: SyntaxKind::MatchArm | SyntaxKind::Default | SyntaxKind::Error,
+                    ..
+                }) => {
+                    self.separate(Separator::Newline);
+                    self.element(child);
+                }
+                SyntaxElement::Token(token) => match token.kind() {
+                    TokenKind::Question | TokenKind::LeftBrace => {
+                        self.separate(Separator::Space);
+                        self.token(token);
+                        if *token.kind() == TokenKind::LeftBrace {
+                            self.indent += 1;
+                        }
+                    }
+                    TokenKind::RightBrace => {
+                        self.indent = self.indent.saturating_sub(1);
+                        self.separate(Separator::Newline);
+                        self.token(token);
+                    }
+                    _ => self.token(token),
+                },
+                SyntaxElement::Node(value) => self.node(value),
+            }
+        }
+    }
+}
+
+fn is_line_comment(text: &str) -> bool {
+    !text.starts_with("#[")
+}
+
+fn is_token(element: &SyntaxElement, kind: &TokenKind) -> bool {
+    matches!(element, SyntaxElement::Token(token) if token.kind() == kind)
+}
+
+/// The separator between two children of a node
+fn separator(parent: SyntaxKind, previous: &SyntaxElement, child: &SyntaxElement) -> Separator {
+    let closing = matches!(child, SyntaxElement::Token(token)
+        if matches!(token.kind(), TokenKind::RightParen | TokenKind::RightBracket));
+    if is_token(previous, &TokenKind::Comma) && !closing {
+        return Separator::Space;
+    }
+    let token = match child {
+        SyntaxElement::Token(token) => token.kind(),
+        SyntaxElement::Node(_) => {
+            return match parent {
+                SyntaxKind::Declaration
+                | SyntaxKind::Binary
+                | SyntaxKind::Function
+                | SyntaxKind::MatchArm => Separator::Space,
+                _ => Separator::None,
+            };
+        }
+    };
+    match (parent, token) {
+        (_, TokenKind::Comma | TokenKind::Semicolon | TokenKind::Colon) => Separator::None,
+        (SyntaxKind::Declaration, TokenKind::Identifier(_) | TokenKind::Assignment) => {
+            Separator::Space
+        }
+        (SyntaxKind::Binary, _) => Separator::Space,
+        (SyntaxKind::Function | SyntaxKind::MatchArm, TokenKind::Arrow) => Separator::Space,
+        (SyntaxKind::Error, _) => Separator::Space,
+        _ => Separator::None,
+    }
+}
+
+/// Formats a program. The tree should come from source without syntax errors, otherwise the
+/// tokens that did not fit the grammar are only separated by spaces.
+pub fn format(tree: &SyntaxNode) -> String {
+    let mut writer = Writer {
+        output: String::new(),
+        indent: 0,
+        pending: Separator::None,
+    };
+    writer.node(tree);
+    writer.output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse;
+
+    fn format_source(source: &str) -> String {
+        format(&parse(source).unwrap())
+    }
+
+    #[test]
+    fn test_spacing() {
+        assert_eq!(
+            format_source("let  x : num=1+2*-3 ;print( x,[1 ,2] ) ;"),
+            "let x:num = 1 + 2 * -3;\nprint(x, [1, 2]);\n"
+        );
+        assert_eq!(
+            format_source("let f:fn=( a : num,b:[char] ):bool=>(a mod 2)==0;"),
+            "let f:fn = (a:num, b:[char]):bool => (a mod 2) == 0;\n"
+        );
+    }
+
+    #[test]
+    fn test_match_arms() {
+        let source = "let f:fn = (x:num):num => x ? { 0 => 1, 1 => x ? {\t2 => 3, 4}, 5 };";
+        assert_eq!(
+            format_source(source),
+            "let f:fn = (x:num):num => x ? {
+    0 => 1,
+    1 => x ? {
+        2 => 3,
+        4
+    },
+    5
+};
+"
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let source = "# Header
+
+## Doc
+let x:num = 1 #[ inline ]# + 2; # trailing
+let y:num = x ? {
+  # before an arm
+  1 => 2, # after an arm
+  3
+};
+
+
+# At the end
+";
+        assert_eq!(
+            format_source(source),
+            "# Header
+
+## Doc
+let x:num = 1 #[ inline ]# + 2; # trailing
+let y:num = x ? {
+    # before an arm
+    1 => 2, # after an arm
+    3
+};
+
+# At the end
+"
+        );
+    }
+
+    #[test]
+    fn test_line_comment_inside_expression() {
+        let formatted = format_source("print(1 + # one\n2);");
+        assert_eq!(formatted, "print(1 + # one\n2);\n");
+        assert_eq!(format_source(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(format_source(""), "");
+        assert_eq!(format_source("\n\n"), "");
+        assert_eq!(format_source("# only a comment"), "# only a comment\n");
+    }
+
+    #[test]
+    fn test_empty_type() {
+        assert_eq!(format_source("let x: = 3;"), "let x: = 3;\n");
+    }
+}
@@ -0,0 +1,337 @@
+//! Syntax highlighting for editors, derived from the classes of the tokens, see
+//! [crate::lexer::TokenClass]. Semantic tokens follow the legend of the language server, and the
+//! TextMate grammar gives the same result without a server, as far as regular expressions can.
+
+use std::collections::HashMap;
+
+use crate::analysis::{Analysis, SymbolKind};
+use crate::json::Json;
+use crate::lexer::{Token, TokenClass, TokenKind};
+use crate::syntax;
+use crate::virtual_machine::Builtin;
+
+/// The semantic token types, in the order of the legend
+pub const TOKEN_TYPES: [&str; 9] = [
+    "keyword",
+    "type",
+    "operator",
+    "number",
+    "string",
+    "variable",
+    "parameter",
+    "function",
+    "comment",
+];
+
+/// The semantic token modifiers, each one is a bit in the order of the legend
+pub const TOKEN_MODIFIERS: [&str; 3] = ["declaration", "documentation", "defaultLibrary"];
+
+const DECLARATION: u32 = 1;
+const DOCUMENTATION: u32 = 1 << 1;
+const DEFAULT_LIBRARY: u32 = 1 << 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    /// The byte offset where the token begins
+    pub offset: usize,
+    /// In bytes
+    pub length: usize,
+    /// An index in [TOKEN_TYPES]
+    pub token_type: usize,
+    /// A bit set of [TOKEN_MODIFIERS]
+    pub modifiers: u32,
+}
+
+/// The legend of the semantic tokens, as sent to the client
+pub fn legend() -> Json {
+    Json::object([
+        (
+            "tokenTypes",
+            Json::Array(TOKEN_TYPES.iter().map(|&name| name.into()).collect()),
+        ),
+        (
+            "tokenModifiers",
+            Json::Array(TOKEN_MODIFIERS.iter().map(|&name| name.into()).collect()),
+        ),
+    ])
+}
+
+fn token_type(name: &str) -> usize {
+    TOKEN_TYPES.iter().position(|&type_| type_ == name).unwrap()
+}
+
+/// The semantic token type of the tokens that do not need the analysis. Identifiers are variables
+/// until they are resolved.
+fn classify(kind: &TokenKind) -> Option<(&'static str, u32)> {
+    let classified = match (kind.class()?, kind) {
+        (TokenClass::Keyword, _) => ("keyword", 0),
+        (TokenClass::Type, _) => ("type", 0),
+        (TokenClass::Operator, _) => ("operator", 0),
+        (TokenClass::Literal, TokenKind::Number(_, _)) => ("number", 0),
+        (TokenClass::Literal, TokenKind::Boolean(_)) => ("keyword", 0),
+        (TokenClass::Literal, _) => ("string", 0),
+        (TokenClass::Identifier, _) => ("variable", 0),
+        (TokenClass::Comment, TokenKind::DocComment(_)) => ("comment", DOCUMENTATION),
+        (TokenClass::Comment, _) => ("comment", 0),
+    };
+    Some(classified)
+}
+
+/// The semantic tokens of a program, in order. Names are told apart by what they refer to, which
+/// needs the program to be analyzed. A program that cannot be tokenized has none.
+pub fn semantic_tokens(source: &str) -> Vec<SemanticToken> {
+    let tree = match syntax::parse(source) {
+        Ok(tree) => tree,
+        Err(_) => return vec![],
+    };
+    let analysis = Analysis::new(source);
+    // The kind of the symbol every name refers to, and whether it is declared there
+    let mut names: HashMap<usize, (SymbolKind, u32)> = HashMap::new();
+    for reference in &analysis.references {
+        let kind = analysis.symbols[reference.symbol].kind;
+        names.insert(reference.span.begin, (kind, 0));
+    }
+    for symbol in &analysis.symbols {
+        names.insert(symbol.span.begin, (symbol.kind, DECLARATION));
+    }
+
+    let mut tokens = vec![];
+    let mut push = |token: &Token, text: &str| {
+        let Some((mut name, mut modifiers)) = classify(&token.kind) else {
+            return;
+        };
+        if let TokenKind::Identifier(identifier) = &token.kind {
+            match names.get(&token.begin) {
+                Some(&(kind, declaration)) => {
+                    name = match kind {
+                        SymbolKind::Variable => "variable",
+                        SymbolKind::Function => "function",
+                        SymbolKind::Parameter => "parameter",
+                    };
+                    modifiers |= declaration;
+                }
+                None if Builtin::from_name(identifier).is_some() => {
+                    name = "function";
+                    modifiers |= DEFAULT_LIBRARY;
+                }
+                None => {}
+            }
+        }
+        if !text.is_empty() {
+            tokens.push(SemanticToken {
+                offset: token.begin,
+                length: text.len(),
+                token_type: token_type(name),
+                modifiers,
+            });
+        }
+    };
+    for token in tree.tokens() {
+        for comment in &token.leading {
+            if let Some(comment_token) = &comment.token {
+                push(comment_token, &comment.text);
+            }
+        }
+        push(&token.token, &token.text);
+        for comment in &token.trailing {
+            if let Some(comment_token) = &comment.token {
+                push(comment_token, &comment.text);
+            }
+        }
+    }
+    tokens
+}
+
+/// A TextMate grammar for `.ni` files, with a rule for each class of tokens
+pub fn textmate_grammar() -> Json {
+    let rule = |name: &str, pattern: &str| {
+        Json::object([("name", name.into()), ("match", pattern.into())])
+    };
+    let patterns = |rules: Vec<Json>| Json::object([("patterns", Json::Array(rules))]);
+    let escape = rule("constant.character.escape.nino", r"\\(x[0-9a-fA-F]{2}|.)");
+    let builtins: Vec<&str> = Builtin::ALL.iter().map(|builtin| builtin.name()).collect();
+
+    let repository = Json::object([
+        (
+            "comment",
+            patterns(vec![
+                Json::object([("include", "#block-comment".into())]),
+                rule("comment.line.documentation.nino", "##.*$"),
+                rule("comment.line.number-sign.nino", "#.*$"),
+            ]),
+        ),
+        (
+            // Block comments can be nested
+            "block-comment",
+            Json::object([
+                ("name", "comment.block.nino".into()),
+                ("begin", r"#\[".into()),
+                ("end", r"\]#".into()),
+                (
+                    "patterns",
+                    Json::Array(vec![Json::object([("include", "#block-comment".into())])]),
+                ),
+            ]),
+        ),
+        (
+            // The lexer reads a type after every colon
+            "type",
+            patterns(vec![Json::object([
+                ("match", r"(:)\s*([a-z0-9_\[\]]*)".into()),
+                (
+                    "captures",
+                    Json::object([
+                        (
+                            "1",
+                            Json::object([("name", "punctuation.separator.type.nino".into())]),
+                        ),
+                        ("2", Json::object([("name", "storage.type.nino".into())])),
+                    ]),
+                ),
+            ])]),
+        ),
+        (
+            "keyword",
+            patterns(vec![rule("keyword.other.nino", r"\b(let|fn)\b")]),
+        ),
+        (
+            "literal",
+            patterns(vec![
+                rule("constant.language.boolean.nino", r"\b(true|false)\b"),
+                rule(
+                    "constant.numeric.nino",
+                    r"\b(0x[0-9a-fA-F_]+|0b[01_]+|0o[0-7_]+|[0-9][0-9_]*(\.[0-9][0-9_]*)?([eE][+-]?[0-9][0-9_]*)?)\b",
+                ),
+                Json::object([
+                    ("name", "string.quoted.single.nino".into()),
+                    ("match", r"'(\\(x[0-9a-fA-F]{2}|.)|[^'\\])'".into()),
+                ]),
+                Json::object([
+                    ("name", "string.quoted.double.nino".into()),
+                    ("begin", "\"".into()),
+                    ("end", "\"".into()),
+                    ("patterns", Json::Array(vec![escape])),
+                ]),
+            ]),
+        ),
+        (
+            "operator",
+            patterns(vec![
+                rule("keyword.operator.word.nino", r"\bmod\b"),
+                rule("keyword.operator.nino", r"=>|==|!=|<=|>=|[-+*/!<>=?]"),
+            ]),
+        ),
+        (
+            "identifier",
+            patterns(vec![
+                rule(
+                    "support.function.builtin.nino",
+                    &format!(r"\b({})\b", builtins.join("|")),
+                ),
+                rule("variable.other.nino", r"[\p{L}_][\p{L}\p{N}_]*"),
+            ]),
+        ),
+    ]);
+
+    // The order matters: comments first, and names last so that keywords are not taken for them
+    let order = [
+        "comment",
+        "type",
+        "keyword",
+        "literal",
+        "operator",
+        "identifier",
+    ];
+    Json::object([
+        ("name", "Nino".into()),
+        ("scopeName", "source.nino".into()),
+        ("fileTypes", Json::Array(vec!["ni".into()])),
+        (
+            "patterns",
+            Json::Array(
+                order
+                    .iter()
+                    .map(|name| Json::object([("include", format!("#{}", name).into())]))
+                    .collect(),
+            ),
+        ),
+        ("repository", repository),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text, type and modifiers of every semantic token
+    fn highlight(source: &str) -> Vec<(&str, &str, u32)> {
+        semantic_tokens(source)
+            .into_iter()
+            .map(|token| {
+                (
+                    &source[token.offset..token.offset + token.length],
+                    TOKEN_TYPES[token.token_type],
+                    token.modifiers,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let source = "## Doc\nlet f:fn = (x:num):bool => x >= 'a'; # odd\nprint(f(0x1));";
+        assert_eq!(
+            highlight(source),
+            vec![
+                ("## Doc", "comment", DOCUMENTATION),
+                ("let", "keyword", 0),
+                ("f", "function", DECLARATION),
+                ("fn", "type", 0),
+                ("=", "operator", 0),
+                ("x", "parameter", DECLARATION),
+                ("num", "type", 0),
+                ("bool", "type", 0),
+                ("=>", "operator", 0),
+                ("x", "parameter", 0),
+                (">=", "operator", 0),
+                ("'a'", "string", 0),
+                ("# odd", "comment", 0),
+                ("print", "function", DEFAULT_LIBRARY),
+                ("f", "function", 0),
+                ("0x1", "number", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_programs() {
+        assert_eq!(semantic_tokens("let x:num = $;"), vec![]);
+        // Syntax errors still have tokens
+        assert_eq!(
+            highlight("let x:num = ;\nx"),
+            vec![
+                ("let", "keyword", 0),
+                ("x", "variable", 0),
+                ("num", "type", 0),
+                ("=", "operator", 0),
+                ("x", "variable", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_textmate_grammar() {
+        let grammar = textmate_grammar();
+        assert_eq!(
+            grammar.get("scopeName").and_then(Json::as_str),
+            Some("source.nino")
+        );
+        let repository = grammar.get("repository").unwrap();
+        for include in grammar.get("patterns").unwrap().as_array().unwrap() {
+            let name = include.get("include").and_then(Json::as_str).unwrap();
+            assert!(repository.get(&name[1..]).is_some(), "{} is missing", name);
+        }
+        // It survives a round trip, so the regular expressions are escaped properly
+        assert_eq!(Json::parse(&grammar.to_string()).unwrap(), grammar);
+    }
+}
@@ -0,0 +1,362 @@
+//! A minimal JSON value, used for the machine readable output of the tools and the messages of
+//! the language server.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// The members are kept in the order they were added
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object from its members
+    pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Parses a JSON document, which must hold a single value
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            chars: text.char_indices().peekable(),
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some((i, c)) => Err(format!("Unexpected character {} at {}", c, i)),
+        }
+    }
+
+    /// The member of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(elements) => Some(elements),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn whitespace(&mut self) {
+        while let Some((_, ' ' | '\t' | '\n' | '\r')) = self.chars.peek() {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((i, c)) => Err(format!("Expected {} at {}, got {}", expected, i, c)),
+            None => Err(format!("Expected {}, got the end", expected)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        for expected in keyword.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.chars.peek() {
+            Some((_, 'n')) => self.keyword("null", Json::Null),
+            Some((_, 't')) => self.keyword("true", Json::Bool(true)),
+            Some((_, 'f')) => self.keyword("false", Json::Bool(false)),
+            Some((_, '"')) => Ok(Json::String(self.string()?)),
+            Some((_, '[')) => {
+                self.chars.next();
+                let mut elements = vec![];
+                self.whitespace();
+                if let Some((_, ']')) = self.chars.peek() {
+                    self.chars.next();
+                    return Ok(Json::Array(elements));
+                }
+                loop {
+                    elements.push(self.value()?);
+                    self.whitespace();
+                    match self.chars.next() {
+                        Some((_, ',')) => {}
+                        Some((_, ']')) => return Ok(Json::Array(elements)),
+                        _ => return Err("Expected , or ] in array".to_string()),
+                    }
+                }
+            }
+            Some((_, '{')) => {
+                self.chars.next();
+                let mut members = vec![];
+                self.whitespace();
+                if let Some((_, '}')) = self.chars.peek() {
+                    self.chars.next();
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    self.whitespace();
+                    match self.chars.next() {
+                        Some((_, ',')) => {}
+                        Some((_, '}')) => return Ok(Json::Object(members)),
+                        _ => return Err("Expected , or } in object".to_string()),
+                    }
+                }
+            }
+            Some(&(i, c)) if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(&(_, c)) = self.chars.peek() {
+                    if !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E') {
+                        break;
+                    }
+                    number.push(c);
+                    self.chars.next();
+                }
+                number
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("Invalid number {} at {}", number, i))
+            }
+            Some((i, c)) => Err(format!("Unexpected character {} at {}", c, i)),
+            None => Err("Expected a value, got the end".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(string),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => string.push('"'),
+                    Some((_, '\\')) => string.push('\\'),
+                    Some((_, '/')) => string.push('/'),
+                    Some((_, 'b')) => string.push('\u{8}'),
+                    Some((_, 'f')) => string.push('\u{c}'),
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 'r')) => string.push('\r'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, 'u')) => {
+                        let high = self.code_unit()?;
+                        let code = if (0xD800..0xDC00).contains(&high) {
+                            // A surrogate pair
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.code_unit()?;
+                            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+                        } else {
+                            high
+                        };
+                        string.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    _ => return Err("Invalid escape in string".to_string()),
+                },
+                Some((_, c)) => string.push(c),
+                None => return Err("Unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// The four hexadecimal digits of a `\u` escape
+    fn code_unit(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            match self.chars.next().and_then(|(_, c)| c.to_digit(16)) {
+                Some(digit) => value = value * 16 + digit,
+                None => return Err("Expected four hexadecimal digits after \\u".to_string()),
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Json::Number(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Writes the value on a single line
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            // JSON has no infinity or NaN
+            Json::Number(value) if !value.is_finite() => write!(f, "null"),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write_string(f, value),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let json = Json::object([
+            ("null", Json::Null),
+            (
+                "numbers",
+                Json::Array(vec![Json::from(1usize), Json::Number(-2.5)]),
+            ),
+            ("bool", true.into()),
+            ("string", "a \"quoted\"\n\\ line\u{1}".into()),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            r#"{"null":null,"numbers":[1,-2.5],"bool":true,"string":"a \"quoted\"\n\\ line\u0001"}"#
+        );
+    }
+
+    #[test]
+    fn test_non_finite_numbers() {
+        assert_eq!(Json::Number(f64::NAN).to_string(), "null");
+        assert_eq!(Json::Number(f64::INFINITY).to_string(), "null");
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = r#" {"null": null, "numbers": [1, -2.5e1, 0], "bool": false,
+            "string": "a \"quoted\"\n\\ line \u00e9 \ud83d\ude00", "empty": {}, "none": []} "#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(
+            json,
+            Json::object([
+                ("null", Json::Null),
+                (
+                    "numbers",
+                    Json::Array(vec![
+                        Json::Number(1.0),
+                        Json::Number(-25.0),
+                        Json::Number(0.0)
+                    ])
+                ),
+                ("bool", false.into()),
+                ("string", "a \"quoted\"\n\\ line é 😀".into()),
+                ("empty", Json::Object(vec![])),
+                ("none", Json::Array(vec![])),
+            ])
+        );
+        assert_eq!(json.get("bool"), Some(&Json::Bool(false)));
+        assert_eq!(json.get("missing"), None);
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Json::parse("").is_err());
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("\"unterminated").is_err());
+        assert!(Json::parse("nul").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+}
@@ -8,7 +8,8 @@ pub enum TokenKind {
     // types and values
     Type(String),
     Character(u8),
-    Number(f64),
+    /// The value and the text of the literal as it was written
+    Number(f64, String),
     String(String),
     Boolean(bool),
     Function,
@@ -29,6 +30,11 @@ pub enum TokenKind {
     // identifiers
     Identifier(String),
 
+    // comments, with the text between the delimiters
+    Comment(String),
+    /// A `##` line, documenting the declaration that follows
+    DocComment(String),
+
     // operators
     Addition,
     Subtraction,
@@ -54,6 +60,60 @@ pub enum TokenKind {
     EOF,
 }
 
+/// What a token is for highlighting purposes
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TokenClass {
+    Keyword,
+    Type,
+    Operator,
+    Literal,
+    Identifier,
+    Comment,
+}
+
+impl TokenKind {
+    /// The class of the token. Separators and the end of file have none.
+    pub fn class(&self) -> Option<TokenClass> {
+        match self {
+            TokenKind::Let | TokenKind::Function => Some(TokenClass::Keyword),
+            TokenKind::Type(_) => Some(TokenClass::Type),
+            TokenKind::Character(_)
+            | TokenKind::Number(_, _)
+            | TokenKind::String(_)
+            | TokenKind::Boolean(_) => Some(TokenClass::Literal),
+            TokenKind::Identifier(_) => Some(TokenClass::Identifier),
+            TokenKind::Comment(_) | TokenKind::DocComment(_) => Some(TokenClass::Comment),
+            // `mod` is spelled like a keyword but used like the other operators
+            TokenKind::Addition
+            | TokenKind::Subtraction
+            | TokenKind::Multiplication
+            | TokenKind::Division
+            | TokenKind::Not
+            | TokenKind::Modulus
+            | TokenKind::Assignment
+            | TokenKind::Arrow
+            | TokenKind::Question
+            | TokenKind::Equal
+            | TokenKind::NotEqual
+            | TokenKind::GreaterThan
+            | TokenKind::LessThan
+            | TokenKind::GreaterEqualThan
+            | TokenKind::LessEqualThan => Some(TokenClass::Operator),
+            TokenKind::LeftParen
+            | TokenKind::RightParen
+            | TokenKind::LeftBracket
+            | TokenKind::RightBracket
+            | TokenKind::LeftBrace
+            | TokenKind::RightBrace
+            | TokenKind::Comma
+            | TokenKind::Colon
+            | TokenKind::Semicolon
+            | TokenKind::Pipe
+            | TokenKind::EOF => None,
+        }
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
@@ -65,30 +125,149 @@ impl Token {
     pub fn new(kind: TokenKind, begin: usize, end: usize) -> Self {
         Token { kind, begin, end }
     }
+
+    pub fn span(&self) -> Span {
+        Span {
+            begin: self.begin,
+            end: self.end,
+        }
+    }
 }
 
-fn parse_number(chars: &mut Peekable<CharIndices>, negative: bool) -> Token {
+/// A range of byte offsets into the source, both ends included
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct Span {
+    pub begin: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LexerError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl std::fmt::Display for LexerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at {:?}", self.message, self.span)
+    }
+}
+
+/// Parses a number literal: a decimal number with an optional fraction and exponent, or a
+/// hexadecimal (`0x`), binary (`0b`) or octal (`0o`) integer. Digits can be separated by `_`.
+fn parse_number(chars: &mut Peekable<CharIndices>, negative: bool) -> Result<Token, LexerError> {
     let begin = chars.peek().unwrap().0;
     let mut end = begin;
-    let mut string = String::new();
-    if negative {
-        string.push('-');
-    }
-    while let Some((i, c)) = chars.peek() {
-        match c {
-            '0'..='9' | '.' => string.push(*c),
-            _ => break,
+    let mut text = String::new();
+    // Everything that could belong to the number is consumed, so that a malformed number is
+    // reported once instead of being split into several tokens
+    while let Some(&(i, c)) = chars.peek() {
+        let is_exponent_sign =
+            (c == '+' || c == '-') && text.ends_with(['e', 'E']) && radix_prefix(&text).is_none();
+        if !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || is_exponent_sign) {
+            break;
         }
-        end = *i;
+        text.push(c);
+        end = i;
         chars.next();
     }
-    Token {
-        kind: TokenKind::Number(string.parse::<f64>().unwrap()),
-        begin: if negative { begin - 1 } else { begin },
+    let value = number_value(&text).map_err(|(offset, message)| LexerError {
+        message,
+        span: Span {
+            begin: begin + offset,
+            end: begin + offset,
+        },
+    })?;
+    let (begin, value, text) = if negative {
+        (begin - 1, -value, format!("-{}", text))
+    } else {
+        (begin, value, text)
+    };
+    Ok(Token {
+        kind: TokenKind::Number(value, text),
+        begin,
         end,
+    })
+}
+
+fn radix_prefix(text: &str) -> Option<(u32, &'static str)> {
+    match text.get(..2) {
+        Some("0x") => Some((16, "hexadecimal")),
+        Some("0b") => Some((2, "binary")),
+        Some("0o") => Some((8, "octal")),
+        _ => None,
     }
 }
 
+/// Computes the value of a number literal. On error, returns the offset of the offending
+/// character in the text along with the message.
+fn number_value(text: &str) -> Result<f64, (usize, String)> {
+    let radix = radix_prefix(text).map_or(10, |(radix, _)| radix);
+    let bytes = text.as_bytes();
+    let is_digit = |i: Option<usize>| {
+        i.and_then(|i| bytes.get(i))
+            .is_some_and(|&byte| (byte as char).is_digit(radix))
+    };
+    // Separators must sit between two digits
+    for (i, &byte) in bytes.iter().enumerate() {
+        if byte == b'_' && !(is_digit(i.checked_sub(1)) && is_digit(Some(i + 1))) {
+            return Err((
+                i,
+                "Digit separators must be placed between digits".to_string(),
+            ));
+        }
+    }
+
+    if let Some((radix, name)) = radix_prefix(text) {
+        if text.len() == 2 {
+            return Err((1, format!("Expected {} digits after {}", name, text)));
+        }
+        let mut value = 0.0;
+        for (i, c) in text.char_indices().skip(2) {
+            match c.to_digit(radix) {
+                Some(digit) => value = value * radix as f64 + digit as f64,
+                None if c == '_' => {}
+                None => return Err((i, format!("Invalid digit {} in {} number", c, name))),
+            }
+        }
+        return Ok(value);
+    }
+
+    let exponent = text.find(['e', 'E']);
+    let mantissa = &text[..exponent.unwrap_or(text.len())];
+    let mut decimal_point = None;
+    for (i, c) in mantissa.char_indices() {
+        match c {
+            '0'..='9' | '_' => {}
+            '.' if decimal_point.is_some() => {
+                return Err((i, format!("Invalid number: {}", text)));
+            }
+            '.' => decimal_point = Some(i),
+            _ if decimal_point == Some(i - 1) => break,
+            _ => return Err((i, format!("Invalid digit {} in number", c))),
+        }
+    }
+    if let Some(i) = decimal_point {
+        if !mantissa[i + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+            return Err((i, "Expected a digit after the decimal point".to_string()));
+        }
+    }
+    if let Some(e) = exponent {
+        let digits = text[e + 1..].trim_start_matches(['+', '-']);
+        let digits_begin = text.len() - digits.len();
+        if digits.is_empty() {
+            return Err((e, "Expected digits in the exponent".to_string()));
+        }
+        if let Some((i, c)) = digits
+            .char_indices()
+            .find(|(_, c)| !matches!(c, '0'..='9' | '_'))
+        {
+            return Err((digits_begin + i, format!("Invalid digit {} in exponent", c)));
+        }
+    }
+    Ok(text.replace('_', "").parse::<f64>().unwrap())
+}
+
 fn consume_whitespace(chars: &mut Peekable<CharIndices>) {
     while let Some(&(_, c)) = chars.peek() {
         if !c.is_whitespace() {
@@ -98,39 +277,158 @@ fn consume_whitespace(chars: &mut Peekable<CharIndices>) {
     }
 }
 
+/// Parses the escape sequence after a backslash at `begin`, which has already been consumed,
+/// and returns the value of the byte it stands for.
+fn parse_escape(chars: &mut Peekable<CharIndices>, begin: usize) -> Result<u8, LexerError> {
+    let (i, c) = match chars.next() {
+        Some(next) => next,
+        None => {
+            return Err(LexerError {
+                message: "Unterminated escape sequence".to_string(),
+                span: Span { begin, end: begin },
+            })
+        }
+    };
+    match c {
+        'n' => Ok(b'\n'),
+        't' => Ok(b'\t'),
+        '\\' => Ok(b'\\'),
+        '"' => Ok(b'"'),
+        '\'' => Ok(b'\''),
+        'x' => {
+            let mut value = 0;
+            let mut end = i;
+            for _ in 0..2 {
+                match chars.peek() {
+                    Some(&(i, digit)) if digit.is_ascii_hexdigit() => {
+                        value = value * 16 + digit.to_digit(16).unwrap() as u8;
+                        end = i;
+                        chars.next();
+                    }
+                    _ => {
+                        return Err(LexerError {
+                            message: "Expected two hexadecimal digits after \\x".to_string(),
+                            span: Span { begin, end },
+                        })
+                    }
+                }
+            }
+            Ok(value)
+        }
+        c => Err(LexerError {
+            message: format!("Unknown escape sequence: \\{}", c),
+            span: Span { begin, end: i },
+        }),
+    }
+}
+
 /// First token is a quote, so we consume it and then we consume all the
 /// string until we find another quote.
-fn parse_string(chars: &mut Peekable<CharIndices>) -> Token {
+///
+/// Strings are UTF-8, so `\xNN` escapes in them can only stand for ASCII characters.
+fn parse_string(chars: &mut Peekable<CharIndices>) -> Result<Token, LexerError> {
     let begin = chars.peek().unwrap().0;
     assert_eq!(chars.next().unwrap().1, '"');
     let mut end = begin;
     let mut string = String::new();
-    while let Some(&(i, c)) = chars.peek() {
-        if c == '"' {
-            end = i;
-            chars.next();
-            break;
+    // The string is read to its end even after a bad escape, so that lexing goes on after it
+    let mut error = None;
+    loop {
+        match chars.next() {
+            Some((i, '"')) => {
+                end = i;
+                break;
+            }
+            Some((i, '\\')) => {
+                end = i;
+                match parse_escape(chars, i) {
+                    Ok(value) if !value.is_ascii() => {
+                        error = error.or(Some(LexerError {
+                            message: format!(
+                                "Escape \\x{:02X} in a string must be at most \\x7F",
+                                value
+                            ),
+                            span: Span {
+                                begin: i,
+                                end: i + 3,
+                            },
+                        }))
+                    }
+                    Ok(value) => string.push(value as char),
+                    Err(escape) => error = error.or(Some(escape)),
+                }
+            }
+            Some((i, c)) => {
+                end = i;
+                string.push(c);
+            }
+            None => {
+                return Err(error.unwrap_or(LexerError {
+                    message: "Unterminated string".to_string(),
+                    span: Span { begin, end },
+                }))
+            }
         }
-        string.push(c);
-        chars.next();
     }
-    Token {
+    if let Some(error) = error {
+        return Err(error);
+    }
+    Ok(Token {
         kind: TokenKind::String(string),
         begin,
         end,
-    }
+    })
 }
 
-fn parse_char(chars: &mut Peekable<CharIndices>) -> Token {
+/// A `char` is a single byte, so character literals are either ASCII characters or `\xNN` escapes.
+fn parse_char(chars: &mut Peekable<CharIndices>) -> Result<Token, LexerError> {
     let begin = chars.peek().unwrap().0;
     assert_eq!(chars.next().unwrap().1, '\'');
-    let c = chars.next().unwrap().1;
-    let end = chars.next().unwrap().0;
-    Token {
-        kind: TokenKind::Character(c as u8),
+    let value = match chars.next() {
+        Some((i, '\\')) => parse_escape(chars, i)?,
+        Some((i, '\'')) => {
+            return Err(LexerError {
+                message: "Empty character".to_string(),
+                span: Span { begin, end: i },
+            })
+        }
+        Some((_, c)) if c.is_ascii() => c as u8,
+        Some((i, c)) => {
+            return Err(LexerError {
+                message: format!(
+                    "Character {} is not ASCII, use a \\xNN escape for other bytes",
+                    c
+                ),
+                span: Span { begin, end: i },
+            })
+        }
+        None => {
+            return Err(LexerError {
+                message: "Unterminated character".to_string(),
+                span: Span { begin, end: begin },
+            })
+        }
+    };
+    let end = match chars.next() {
+        Some((i, '\'')) => i,
+        Some((i, _)) => {
+            return Err(LexerError {
+                message: "Expected ' after a character".to_string(),
+                span: Span { begin, end: i },
+            })
+        }
+        None => {
+            return Err(LexerError {
+                message: "Unterminated character".to_string(),
+                span: Span { begin, end: begin },
+            })
+        }
+    };
+    Ok(Token {
+        kind: TokenKind::Character(value),
         begin,
         end,
-    }
+    })
 }
 
 /// Parses a type, which is a string of characters that are alphanumeric
@@ -153,12 +451,81 @@ fn parse_type(chars: &mut Peekable<CharIndices>) -> Token {
     }
 }
 
+/// Parses a `#` line comment, a `##` doc comment or a `#[ ]#` block comment. Block comments can
+/// be nested.
+fn parse_comment(chars: &mut Peekable<CharIndices>) -> Result<Token, LexerError> {
+    let (begin, _) = chars.next().unwrap();
+    let mut end = begin;
+    let mut text = String::new();
+    if let Some(&(i, '[')) = chars.peek() {
+        chars.next();
+        end = i;
+        let mut depth = 1;
+        let mut previous = '[';
+        while depth > 0 {
+            let (i, c) = chars.next().ok_or(LexerError {
+                message: "Unterminated block comment".to_string(),
+                span: Span {
+                    begin,
+                    end: begin + 1,
+                },
+            })?;
+            match (previous, c) {
+                ('#', '[') => depth += 1,
+                (']', '#') => depth -= 1,
+                _ => {}
+            }
+            text.push(c);
+            end = i;
+            // The characters of a delimiter cannot be part of another one
+            previous = if matches!((previous, c), ('#', '[') | (']', '#')) {
+                ' '
+            } else {
+                c
+            };
+        }
+        text.truncate(text.len() - 2);
+        return Ok(Token::new(TokenKind::Comment(text), begin, end));
+    }
+
+    let is_doc = matches!(chars.peek(), Some(&(_, '#')));
+    while let Some(&(i, c)) = chars.peek() {
+        if c == '\n' {
+            break;
+        }
+        text.push(c);
+        end = i;
+        chars.next();
+    }
+    if is_doc {
+        let text = text[1..].strip_prefix(' ').unwrap_or(&text[1..]);
+        Ok(Token::new(
+            TokenKind::DocComment(text.trim_end().to_string()),
+            begin,
+            end,
+        ))
+    } else {
+        Ok(Token::new(TokenKind::Comment(text), begin, end))
+    }
+}
+
+/// Identifiers start with a letter or an underscore. Letters from any script are accepted.
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// After the first character, identifiers can also contain digits
+fn is_identifier_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Parses an identifier or a keyword
 fn parse_word(chars: &mut Peekable<CharIndices>) -> Token {
     let begin = chars.peek().unwrap().0;
     let mut end = begin;
     let mut string = String::new();
     while let Some(&(i, s)) = chars.peek() {
-        if !s.is_alphanumeric() && s != '_' {
+        if !is_identifier_continue(s) {
             break;
         }
         end = i;
@@ -178,9 +545,46 @@ fn parse_word(chars: &mut Peekable<CharIndices>) -> Token {
     Token { kind, begin, end }
 }
 
-pub fn tokenize(input: &str) -> Vec<Token> {
+/// The offset of the next character, or of the last character if there are no more. Used as the
+/// end of the comparison operators.
+fn next_offset(chars: &mut Peekable<CharIndices>, last: usize) -> usize {
+    chars.peek().map_or(last, |&(i, _)| i)
+}
+
+/// Adds the token, or the error. When the token did not get past the character it started at,
+/// that character is skipped so that lexing can go on.
+fn push(
+    result: Result<Token, LexerError>,
+    chars: &mut Peekable<CharIndices>,
+    begin: usize,
+    tokens: &mut Vec<Token>,
+    errors: &mut Vec<LexerError>,
+) {
+    match result {
+        Ok(token) => tokens.push(token),
+        Err(error) => {
+            errors.push(error);
+            if chars.peek().is_some_and(|&(i, _)| i == begin) {
+                chars.next();
+            }
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexerError> {
+    let (tokens, mut errors) = tokenize_recovering(input);
+    match errors.is_empty() {
+        true => Ok(tokens),
+        false => Err(errors.remove(0)),
+    }
+}
+
+/// Tokenizes the whole input, even after errors. The characters a token could not be made of are
+/// left out, and the errors are returned in the order they were found.
+pub fn tokenize_recovering(input: &str) -> (Vec<Token>, Vec<LexerError>) {
     let mut chars: Peekable<CharIndices> = input.char_indices().peekable();
     let mut tokens: Vec<Token> = Vec::new();
+    let mut errors: Vec<LexerError> = Vec::new();
 
     while chars.peek().is_some() {
         consume_whitespace(&mut chars); // Needed because early continue skips the bottom consume_whitespace
@@ -190,20 +594,23 @@ pub fn tokenize(input: &str) -> Vec<Token> {
         let &(begin, c) = chars.peek().unwrap();
 
         let token = match c {
-            'a'..='z' => {
+            c if is_identifier_start(c) => {
                 tokens.push(parse_word(&mut chars));
                 continue;
             }
             '0'..='9' => {
-                tokens.push(parse_number(&mut chars, false));
+                let result = parse_number(&mut chars, false);
+                push(result, &mut chars, begin, &mut tokens, &mut errors);
                 continue;
             }
             '"' => {
-                tokens.push(parse_string(&mut chars));
+                let result = parse_string(&mut chars);
+                push(result, &mut chars, begin, &mut tokens, &mut errors);
                 continue;
             }
             '\'' => {
-                tokens.push(parse_char(&mut chars));
+                let result = parse_char(&mut chars);
+                push(result, &mut chars, begin, &mut tokens, &mut errors);
                 continue;
             }
             ':' => {
@@ -214,16 +621,19 @@ pub fn tokenize(input: &str) -> Vec<Token> {
                 });
                 chars.next();
                 consume_whitespace(&mut chars);
-                tokens.push(parse_type(&mut chars));
+                // The type is missing at the end of the input, which the parser reports
+                if chars.peek().is_some() {
+                    tokens.push(parse_type(&mut chars));
+                }
                 continue;
             }
             '!' => {
                 let mut end = begin;
                 chars.next();
                 tokens.push(Token {
-                    kind: match chars.peek().unwrap().1 {
-                        '=' => {
-                            end = chars.peek().unwrap().0;
+                    kind: match chars.peek() {
+                        Some(&(i, '=')) => {
+                            end = i;
                             chars.next(); // consume the '='
                             TokenKind::NotEqual
                         }
@@ -237,14 +647,14 @@ pub fn tokenize(input: &str) -> Vec<Token> {
             '=' => {
                 let mut end = begin;
                 chars.next(); // consume the '='
-                let kind = match chars.peek().unwrap().1 {
-                    '=' => {
-                        end = chars.peek().unwrap().0;
+                let kind = match chars.peek() {
+                    Some(&(i, '=')) => {
+                        end = i;
                         chars.next(); // consume the '='
                         TokenKind::Equal
                     }
-                    '>' => {
-                        end = chars.peek().unwrap().0;
+                    Some(&(i, '>')) => {
+                        end = i;
                         chars.next(); // consume the '>'
                         TokenKind::Arrow
                     }
@@ -255,8 +665,8 @@ pub fn tokenize(input: &str) -> Vec<Token> {
             }
             '<' => {
                 chars.next();
-                let kind = match chars.peek().unwrap().1 {
-                    '=' => {
+                let kind = match chars.peek() {
+                    Some(&(_, '=')) => {
                         chars.next();
                         TokenKind::LessEqualThan
                     }
@@ -265,14 +675,14 @@ pub fn tokenize(input: &str) -> Vec<Token> {
                 tokens.push(Token {
                     kind,
                     begin,
-                    end: chars.peek().unwrap().0,
+                    end: next_offset(&mut chars, input.len() - 1),
                 });
                 continue;
             }
             '>' => {
                 chars.next();
-                let kind = match chars.peek().unwrap().1 {
-                    '=' => {
+                let kind = match chars.peek() {
+                    Some(&(_, '=')) => {
                         chars.next();
                         TokenKind::GreaterEqualThan
                     }
@@ -281,7 +691,7 @@ pub fn tokenize(input: &str) -> Vec<Token> {
                 tokens.push(Token {
                     kind,
                     begin,
-                    end: chars.peek().unwrap().0,
+                    end: next_offset(&mut chars, input.len() - 1),
                 });
                 continue;
             }
@@ -300,31 +710,34 @@ pub fn tokenize(input: &str) -> Vec<Token> {
             '/' => TokenKind::Division,
             '?' => TokenKind::Question,
             '#' => {
-                while let Some(&(_, c)) = chars.peek() {
-                    if c == '\n' {
-                        break;
-                    }
-                    chars.next();
-                }
+                let result = parse_comment(&mut chars);
+                push(result, &mut chars, begin, &mut tokens, &mut errors);
+                continue;
+            }
+            _ => {
+                errors.push(LexerError {
+                    message: format!("Unexpected character: {}", c),
+                    span: Span { begin, end: begin },
+                });
+                chars.next();
                 continue;
             }
-            _ => panic!("Unexpected character: {}", c),
         };
         tokens.push(Token {
             kind: token,
             begin,
-            end: chars.peek().unwrap().0,
+            end: begin,
         });
         chars.next();
         consume_whitespace(&mut chars); // if the last character is a whitespace
     }
     tokens.push(Token {
         kind: TokenKind::EOF,
-        begin: input.len() - 1,
-        end: input.len() - 1,
+        begin: input.len().saturating_sub(1),
+        end: input.len().saturating_sub(1),
     });
 
-    tokens
+    (tokens, errors)
 }
 
 #[cfg(test)]
@@ -347,22 +760,22 @@ mod tests {
     fn test_parse_number() {
         let input = "123 345";
         let mut chars = input.char_indices().peekable();
-        let token = parse_number(&mut chars, false);
+        let token = parse_number(&mut chars, false).unwrap();
         assert_eq!(
             token,
             Token {
-                kind: TokenKind::Number(123.0),
+                kind: TokenKind::Number(123.0, "123".to_string()),
                 begin: 0,
                 end: 2
             }
         );
         assert_eq!(chars.next(), Some((3, ' ')));
         consume_whitespace(&mut chars);
-        let token = parse_number(&mut chars, false);
+        let token = parse_number(&mut chars, false).unwrap();
         assert_eq!(
             token,
             Token {
-                kind: TokenKind::Number(345.0),
+                kind: TokenKind::Number(345.0, "345".to_string()),
                 begin: 4,
                 end: 6
             }
@@ -374,16 +787,19 @@ mod tests {
         let input = "-123";
         let mut chars = input.char_indices().peekable();
         chars.next(); // Needed for correct index
-        let token = tokenize(&input);
+        let token = tokenize(input).unwrap();
         assert_eq!(token.len(), 3);
         assert_eq!(token[0], Token::new(TokenKind::Subtraction, 0, 0),);
-        assert_eq!(token[1], Token::new(TokenKind::Number(123.0), 1, 3),);
+        assert_eq!(
+            token[1],
+            Token::new(TokenKind::Number(123.0, "123".to_string()), 1, 3),
+        );
     }
 
     #[test]
     fn test_parse_identifier_subtraction() {
         let input = "-x";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
         assert_eq!(tokens.len(), 3);
         assert_eq!(tokens[0], Token::new(TokenKind::Subtraction, 0, 0));
         assert_eq!(
@@ -397,11 +813,11 @@ mod tests {
     fn test_parse_float() {
         let input = "123.456";
         let mut chars = input.char_indices().peekable();
-        let token = parse_number(&mut chars, false);
+        let token = parse_number(&mut chars, false).unwrap();
         assert_eq!(
             token,
             Token {
-                kind: TokenKind::Number(123.456),
+                kind: TokenKind::Number(123.456, "123.456".to_string()),
                 begin: 0,
                 end: 6
             }
@@ -420,7 +836,7 @@ mod tests {
     #[test]
     fn test_parse_string() {
         let input = "\"hello world\"";
-        let token = parse_string(&mut input.char_indices().peekable());
+        let token = parse_string(&mut input.char_indices().peekable()).unwrap();
         assert_eq!(
             token,
             Token {
@@ -434,7 +850,7 @@ mod tests {
     #[test]
     fn test_parse_expression() {
         let input = "let x:num = 3;";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
 
         assert_eq!(tokens.len(), 8);
 
@@ -486,7 +902,7 @@ mod tests {
         assert_eq!(
             tokens[5],
             Token {
-                kind: TokenKind::Number(3.0),
+                kind: TokenKind::Number(3.0, "3".to_string()),
                 begin: 12,
                 end: 12
             }
@@ -514,13 +930,13 @@ mod tests {
     #[test]
     fn test_parse_equalities() {
         let input = "1 == 2 != 3 > 4 < 5 >= 6 <= 7";
-        let tokens = tokenize(input);
+        let tokens = tokenize(input).unwrap();
 
         assert_eq!(tokens.len(), 14);
         assert_eq!(
             tokens[0],
             Token {
-                kind: TokenKind::Number(1.0),
+                kind: TokenKind::Number(1.0, "1".to_string()),
                 begin: 0,
                 end: 0
             }
@@ -536,7 +952,7 @@ mod tests {
         assert_eq!(
             tokens[2],
             Token {
-                kind: TokenKind::Number(2.0),
+                kind: TokenKind::Number(2.0, "2".to_string()),
                 begin: 5,
                 end: 5
             }
@@ -552,7 +968,7 @@ mod tests {
         assert_eq!(
             tokens[4],
             Token {
-                kind: TokenKind::Number(3.0),
+                kind: TokenKind::Number(3.0, "3".to_string()),
                 begin: 10,
                 end: 10
             }
@@ -568,7 +984,7 @@ mod tests {
         assert_eq!(
             tokens[6],
             Token {
-                kind: TokenKind::Number(4.0),
+                kind: TokenKind::Number(4.0, "4".to_string()),
                 begin: 14,
                 end: 14
             }
@@ -584,7 +1000,7 @@ mod tests {
         assert_eq!(
             tokens[8],
             Token {
-                kind: TokenKind::Number(5.0),
+                kind: TokenKind::Number(5.0, "5".to_string()),
                 begin: 18,
                 end: 18
             }
@@ -600,7 +1016,7 @@ mod tests {
         assert_eq!(
             tokens[10],
             Token {
-                kind: TokenKind::Number(6.0),
+                kind: TokenKind::Number(6.0, "6".to_string()),
                 begin: 23,
                 end: 23
             }
@@ -616,7 +1032,7 @@ mod tests {
         assert_eq!(
             tokens[12],
             Token {
-                kind: TokenKind::Number(7.0),
+                kind: TokenKind::Number(7.0, "7".to_string()),
                 begin: 28,
                 end: 28
             }
@@ -635,6 +1051,7 @@ mod tests {
     fn test_equality_expression() {
         let input = "let x:bool = 1+3>2 == 1;";
         let tokens = tokenize(input)
+            .unwrap()
             .into_iter()
             .map(|t| t.kind)
             .collect::<Vec<_>>();
@@ -646,13 +1063,13 @@ mod tests {
                 TokenKind::Colon,
                 TokenKind::Type("bool".to_string()),
                 TokenKind::Assignment,
-                TokenKind::Number(1.0),
+                TokenKind::Number(1.0, "1".to_string()),
                 TokenKind::Addition,
-                TokenKind::Number(3.0),
+                TokenKind::Number(3.0, "3".to_string()),
                 TokenKind::GreaterThan,
-                TokenKind::Number(2.0),
+                TokenKind::Number(2.0, "2".to_string()),
                 TokenKind::Equal,
-                TokenKind::Number(1.0),
+                TokenKind::Number(1.0, "1".to_string()),
                 TokenKind::Semicolon,
                 TokenKind::EOF,
             ]
@@ -663,6 +1080,7 @@ mod tests {
     fn test_array() {
         let input = "let x:[num] = [1,2,3];";
         let tokens = tokenize(input)
+            .unwrap()
             .into_iter()
             .map(|t| t.kind)
             .collect::<Vec<_>>();
@@ -675,11 +1093,11 @@ mod tests {
                 TokenKind::Type("[num]".to_string()),
                 TokenKind::Assignment,
                 TokenKind::LeftBracket,
-                TokenKind::Number(1.0),
+                TokenKind::Number(1.0, "1".to_string()),
                 TokenKind::Comma,
-                TokenKind::Number(2.0),
+                TokenKind::Number(2.0, "2".to_string()),
                 TokenKind::Comma,
-                TokenKind::Number(3.0),
+                TokenKind::Number(3.0, "3".to_string()),
                 TokenKind::RightBracket,
                 TokenKind::Semicolon,
                 TokenKind::EOF,
@@ -693,6 +1111,7 @@ mod tests {
 let y:num = 2; # This is another comment";
 
         let tokens = tokenize(input)
+            .unwrap()
             .into_iter()
             .map(|t| t.kind)
             .collect::<Vec<_>>();
@@ -705,15 +1124,71 @@ let y:num = 2; # This is another comment";
                 TokenKind::Colon,
                 TokenKind::Type("num".to_string()),
                 TokenKind::Assignment,
-                TokenKind::Number(1.0),
+                TokenKind::Number(1.0, "1".to_string()),
                 TokenKind::Semicolon,
+                TokenKind::Comment(" This is a comment".to_string()),
                 TokenKind::Let,
                 TokenKind::Identifier("y".to_string()),
                 TokenKind::Colon,
                 TokenKind::Type("num".to_string()),
                 TokenKind::Assignment,
-                TokenKind::Number(2.0),
+                TokenKind::Number(2.0, "2".to_string()),
                 TokenKind::Semicolon,
+                TokenKind::Comment(" This is another comment".to_string()),
+                TokenKind::EOF,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_block_comment() {
+        let input = "1 #[ a #[ nested ]# comment\n over lines ]# + 2";
+        let tokens = tokenize(input).unwrap();
+        assert_eq!(
+            tokens[1],
+            Token::new(
+                TokenKind::Comment(" a #[ nested ]# comment\n over lines ".to_string()),
+                2,
+                41
+            )
+        );
+        assert_eq!(tokens[2], Token::new(TokenKind::Addition, 43, 43));
+        assert_eq!(
+            tokenize("#[]#").unwrap()[0].kind,
+            TokenKind::Comment(String::new())
+        );
+        assert_eq!(
+            tokenize("#[]").unwrap_err().message,
+            "Unterminated block comment"
+        );
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        assert_eq!(
+            tokenize("1 #[ #[ ]#").unwrap_err(),
+            LexerError {
+                message: "Unterminated block comment".to_string(),
+                span: Span { begin: 2, end: 3 }
+            }
+        );
+    }
+
+    #[test]
+    fn test_doc_comment() {
+        let input = "## Adds one\n##\n##   Indented  \n#comment";
+        let tokens = tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect();
+        compare_tokens(
+            tokens,
+            vec![
+                TokenKind::DocComment("Adds one".to_string()),
+                TokenKind::DocComment(String::new()),
+                TokenKind::DocComment("  Indented".to_string()),
+                TokenKind::Comment("comment".to_string()),
                 TokenKind::EOF,
             ],
         );
@@ -727,6 +1202,7 @@ let y:num = 2; # This is another comment";
     4
 };";
         let tokens = tokenize(input)
+            .unwrap()
             .into_iter()
             .map(|t| t.kind)
             .collect::<Vec<_>>();
@@ -739,18 +1215,18 @@ let y:num = 2; # This is another comment";
                 TokenKind::Colon,
                 TokenKind::Type("num".to_string()),
                 TokenKind::Assignment,
-                TokenKind::Number(1.0),
+                TokenKind::Number(1.0, "1".to_string()),
                 TokenKind::Question,
                 TokenKind::LeftBrace,
-                TokenKind::Number(1.0),
+                TokenKind::Number(1.0, "1".to_string()),
                 TokenKind::Arrow,
-                TokenKind::Number(2.0),
+                TokenKind::Number(2.0, "2".to_string()),
                 TokenKind::Comma,
-                TokenKind::Number(2.0),
+                TokenKind::Number(2.0, "2".to_string()),
                 TokenKind::Arrow,
-                TokenKind::Number(3.0),
+                TokenKind::Number(3.0, "3".to_string()),
                 TokenKind::Comma,
-                TokenKind::Number(4.0),
+                TokenKind::Number(4.0, "4".to_string()),
                 TokenKind::RightBrace,
                 TokenKind::Semicolon,
                 TokenKind::EOF,
@@ -762,6 +1238,7 @@ let y:num = 2; # This is another comment";
     fn test_string() {
         let input = "let x:[char] = \"hello world\";";
         let tokens = tokenize(input)
+            .unwrap()
             .into_iter()
             .map(|t| t.kind)
             .collect::<Vec<_>>();
@@ -785,6 +1262,7 @@ let y:num = 2; # This is another comment";
     fn test_char() {
         let input = "let x:char = 'a';";
         let tokens = tokenize(input)
+            .unwrap()
             .into_iter()
             .map(|t| t.kind)
             .collect::<Vec<_>>();
@@ -797,7 +1275,7 @@ let y:num = 2; # This is another comment";
                 TokenKind::Colon,
                 TokenKind::Type("char".to_string()),
                 TokenKind::Assignment,
-                TokenKind::Character('a' as u8),
+                TokenKind::Character(b'a'),
                 TokenKind::Semicolon,
                 TokenKind::EOF,
             ],
@@ -817,6 +1295,7 @@ let y:num = 2; # This is another comment";
     };";
 
         let tokens = tokenize(input)
+            .unwrap()
             .into_iter()
             .map(|t| t.kind)
             .collect::<Vec<_>>();
@@ -865,21 +1344,21 @@ let y:num = 2; # This is another comment";
                 TokenKind::LeftBrace,
                 TokenKind::Identifier("x".to_string()),
                 TokenKind::Equal,
-                TokenKind::Number(1.0),
+                TokenKind::Number(1.0, "1".to_string()),
                 TokenKind::Arrow,
                 TokenKind::Boolean(false),
                 TokenKind::Comma,
                 TokenKind::Identifier("x".to_string()),
                 TokenKind::Equal,
-                TokenKind::Number(2.0),
+                TokenKind::Number(2.0, "2".to_string()),
                 TokenKind::Arrow,
                 TokenKind::Boolean(true),
                 TokenKind::Comma,
                 TokenKind::Identifier("x".to_string()),
                 TokenKind::Modulus,
-                TokenKind::Number(2.0),
+                TokenKind::Number(2.0, "2".to_string()),
                 TokenKind::Equal,
-                TokenKind::Number(0.0),
+                TokenKind::Number(0.0, "0".to_string()),
                 TokenKind::Arrow,
                 TokenKind::Boolean(false),
                 TokenKind::Comma,
@@ -889,7 +1368,7 @@ let y:num = 2; # This is another comment";
                 TokenKind::LeftParen,
                 TokenKind::Identifier("x".to_string()),
                 TokenKind::Comma,
-                TokenKind::Number(3.0),
+                TokenKind::Number(3.0, "3".to_string()),
                 TokenKind::Comma,
                 TokenKind::Identifier("sqrt_x_int".to_string()),
                 TokenKind::RightParen,
@@ -899,4 +1378,368 @@ let y:num = 2; # This is another comment";
             ],
         );
     }
+
+    #[test]
+    fn test_identifiers() {
+        let identifier = |name: &str| TokenKind::Identifier(name.to_string());
+        let tokens = tokenize("Upper _private _ x1 snake_case größe π letter fnord modulo Let")
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect();
+        compare_tokens(
+            tokens,
+            vec![
+                identifier("Upper"),
+                identifier("_private"),
+                identifier("_"),
+                identifier("x1"),
+                identifier("snake_case"),
+                identifier("größe"),
+                identifier("π"),
+                identifier("letter"),
+                identifier("fnord"),
+                identifier("modulo"),
+                identifier("Let"),
+                TokenKind::EOF,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_keywords_next_to_identifiers() {
+        let tokens = tokenize("let X:num = A mod _b;")
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect();
+        compare_tokens(
+            tokens,
+            vec![
+                TokenKind::Let,
+                TokenKind::Identifier("X".to_string()),
+                TokenKind::Colon,
+                TokenKind::Type("num".to_string()),
+                TokenKind::Assignment,
+                TokenKind::Identifier("A".to_string()),
+                TokenKind::Modulus,
+                TokenKind::Identifier("_b".to_string()),
+                TokenKind::Semicolon,
+                TokenKind::EOF,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_identifier_spans() {
+        let tokens = tokenize("Größe _1").unwrap();
+        // Spans are byte offsets, ö and ß take two bytes
+        assert_eq!(
+            tokens[0],
+            Token::new(TokenKind::Identifier("Größe".to_string()), 0, 6)
+        );
+        assert_eq!(
+            tokens[1],
+            Token::new(TokenKind::Identifier("_1".to_string()), 8, 9)
+        );
+    }
+
+    #[test]
+    fn test_unexpected_character() {
+        let error = tokenize("let x:num = 1 $ 2;").unwrap_err();
+        assert_eq!(
+            error,
+            LexerError {
+                message: "Unexpected character: $".to_string(),
+                span: Span { begin: 14, end: 14 }
+            }
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let error = tokenize("print(\"hello)").unwrap_err();
+        assert_eq!(
+            error,
+            LexerError {
+                message: "Unterminated string".to_string(),
+                span: Span { begin: 6, end: 12 }
+            }
+        );
+    }
+
+    #[test]
+    fn test_unterminated_char() {
+        assert_eq!(
+            tokenize("'a").unwrap_err().message,
+            "Unterminated character"
+        );
+        assert_eq!(tokenize("'").unwrap_err().message, "Unterminated character");
+        assert_eq!(
+            tokenize("'ab'").unwrap_err(),
+            LexerError {
+                message: "Expected ' after a character".to_string(),
+                span: Span { begin: 0, end: 2 }
+            }
+        );
+    }
+
+    #[test]
+    fn test_recovering() {
+        let (tokens, errors) = tokenize_recovering("x $ \"a\\qb\" 1.2.3 y");
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        compare_tokens(
+            kinds,
+            vec![
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Identifier("y".to_string()),
+                TokenKind::EOF,
+            ],
+        );
+        let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Unexpected character: $",
+                "Unknown escape sequence: \\q",
+                "Invalid number: 1.2.3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_number() {
+        let error = tokenize("x + 1.2.3").unwrap_err();
+        assert_eq!(
+            error,
+            LexerError {
+                message: "Invalid number: 1.2.3".to_string(),
+                span: Span { begin: 7, end: 7 }
+            }
+        );
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let numbers = [
+            ("1_000_000", 1_000_000.0),
+            ("1e9", 1e9),
+            ("1.5E-3", 1.5e-3),
+            ("2e+2", 200.0),
+            ("0x1F", 31.0),
+            ("0xff_ff", 65535.0),
+            ("0b1010", 10.0),
+            ("0o17", 15.0),
+        ];
+        for (text, value) in numbers {
+            let tokens = tokenize(text).unwrap();
+            assert_eq!(
+                tokens[0],
+                Token::new(
+                    TokenKind::Number(value, text.to_string()),
+                    0,
+                    text.len() - 1
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_hexadecimal_exponent_is_a_digit() {
+        let tokens: Vec<TokenKind> = tokenize("0x1e-3")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect();
+        compare_tokens(
+            tokens,
+            vec![
+                TokenKind::Number(30.0, "0x1e".to_string()),
+                TokenKind::Subtraction,
+                TokenKind::Number(3.0, "3".to_string()),
+                TokenKind::EOF,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_malformed_numbers() {
+        let errors = [
+            ("1.", "Expected a digit after the decimal point", 1),
+            ("1.x", "Expected a digit after the decimal point", 1),
+            ("12a", "Invalid digit a in number", 2),
+            ("1e", "Expected digits in the exponent", 1),
+            ("1e-", "Expected digits in the exponent", 1),
+            ("1e5x", "Invalid digit x in exponent", 3),
+            ("0x", "Expected hexadecimal digits after 0x", 1),
+            ("0b102", "Invalid digit 2 in binary number", 4),
+            ("0o8", "Invalid digit 8 in octal number", 2),
+            ("1_", "Digit separators must be placed between digits", 1),
+            ("1__0", "Digit separators must be placed between digits", 1),
+            ("1_.5", "Digit separators must be placed between digits", 1),
+            ("0x_1", "Digit separators must be placed between digits", 2),
+        ];
+        for (text, message, offset) in errors {
+            assert_eq!(
+                tokenize(text).unwrap_err(),
+                LexerError {
+                    message: message.to_string(),
+                    span: Span {
+                        begin: offset,
+                        end: offset
+                    }
+                },
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_operator_at_end_of_input() {
+        for (input, kind) in [
+            ("=", TokenKind::Assignment),
+            ("<", TokenKind::LessThan),
+            (">", TokenKind::GreaterThan),
+            ("!", TokenKind::Not),
+            ("<=", TokenKind::LessEqualThan),
+        ] {
+            let tokens = tokenize(input).unwrap();
+            assert_eq!(tokens[0].kind, kind);
+            assert_eq!(tokens[1].kind, TokenKind::EOF);
+        }
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(
+            tokenize("").unwrap(),
+            vec![Token::new(TokenKind::EOF, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let tokens = tokenize(r#""a\n\t\\\"\'\x41""#).unwrap();
+        assert_eq!(
+            tokens[0],
+            Token::new(TokenKind::String("a\n\t\\\"'A".to_string()), 0, 16)
+        );
+    }
+
+    #[test]
+    fn test_char_escapes() {
+        for (input, value) in [
+            (r"'\n'", b'\n'),
+            (r"'\t'", b'\t'),
+            (r"'\\'", b'\\'),
+            (r#"'\"'"#, b'"'),
+            (r"'\''", b'\''),
+            (r"'\x7f'", 0x7F),
+            (r"'\xFF'", 0xFF),
+        ] {
+            let tokens = tokenize(input).unwrap();
+            assert_eq!(tokens[0].kind, TokenKind::Character(value), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        assert_eq!(
+            tokenize(r#""a\q""#).unwrap_err(),
+            LexerError {
+                message: "Unknown escape sequence: \\q".to_string(),
+                span: Span { begin: 2, end: 3 }
+            }
+        );
+        assert_eq!(
+            tokenize(r"'\x4'").unwrap_err(),
+            LexerError {
+                message: "Expected two hexadecimal digits after \\x".to_string(),
+                span: Span { begin: 1, end: 3 }
+            }
+        );
+        assert_eq!(
+            tokenize(r#""\xE9""#).unwrap_err(),
+            LexerError {
+                message: "Escape \\xE9 in a string must be at most \\x7F".to_string(),
+                span: Span { begin: 1, end: 4 }
+            }
+        );
+        assert_eq!(
+            tokenize(r#""abc\"#).unwrap_err().message,
+            "Unterminated escape sequence"
+        );
+    }
+
+    #[test]
+    fn test_missing_type() {
+        let tokens = tokenize("let x:").unwrap();
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Let,
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Colon,
+                TokenKind::EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        assert_eq!(
+            tokenize("'é'").unwrap_err(),
+            LexerError {
+                message: "Character é is not ASCII, use a \\xNN escape for other bytes".to_string(),
+                span: Span { begin: 0, end: 1 }
+            }
+        );
+        assert_eq!(tokenize("''").unwrap_err().message, "Empty character");
+        // Strings are kept as they are and become UTF-8 bytes
+        let tokens = tokenize("\"héllo\"").unwrap();
+        assert_eq!(
+            tokens[0],
+            Token::new(TokenKind::String("héllo".to_string()), 0, 7)
+        );
+    }
+
+    #[test]
+    fn test_classes() {
+        let tokens = tokenize("let f:fn = (x:num):bool => x mod 2 == 0; # odd\nf('a');").unwrap();
+        let classes: Vec<Option<TokenClass>> = tokens.iter().map(|t| t.kind.class()).collect();
+        use TokenClass::*;
+        assert_eq!(
+            classes,
+            vec![
+                Some(Keyword),
+                Some(Identifier),
+                None,
+                Some(Type),
+                Some(Operator),
+                None,
+                Some(Identifier),
+                None,
+                Some(Type),
+                None,
+                None,
+                Some(Type),
+                Some(Operator),
+                Some(Identifier),
+                Some(Operator),
+                Some(Literal),
+                Some(Operator),
+                Some(Literal),
+                None,
+                Some(Comment),
+                Some(Identifier),
+                None,
+                Some(Literal),
+                None,
+                None,
+                None,
+            ]
+        );
+    }
 }
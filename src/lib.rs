@@ -1,4 +1,18 @@
+pub mod analysis;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod diagnostics;
+pub mod formatter;
+pub mod highlight;
+pub mod json;
 pub mod lexer;
+pub mod lsp;
+pub mod optimizer;
 pub mod parser;
-pub mod virtual_machine;
+pub mod profiler;
 pub mod scoped_symbols;
+pub mod syntax;
+pub mod tail_calls;
+pub mod testing;
+pub mod virtual_machine;
@@ -0,0 +1,468 @@
+//! A language server, speaking the Language Server Protocol with JSON-RPC messages. Documents are
+//! synchronized in full, and are analyzed again on every change, see [crate::analysis].
+//!
+//! Positions in the protocol are lines and UTF-16 code units, both counted from 0.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::analysis::{Analysis, Symbol, SymbolKind};
+use crate::diagnostics::Severity;
+use crate::highlight::{legend, semantic_tokens};
+use crate::json::Json;
+use crate::lexer::Span;
+use crate::virtual_machine::Builtin;
+
+// Error codes of JSON-RPC
+const PARSE_ERROR: f64 = -32700.0;
+const INVALID_REQUEST: f64 = -32600.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const INVALID_PARAMS: f64 = -32602.0;
+
+// Kinds of the protocol
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_VARIABLE: usize = 13;
+
+#[derive(Default)]
+pub struct Server {
+    /// The text of the open documents by URI
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    /// The exit code, once the client asked to exit
+    pub exit: Option<i32>,
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn error(id: &Json, code: f64, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        (
+            "error",
+            Json::object([("code", code.into()), ("message", message.into())]),
+        ),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+/// The line and character of a byte offset
+fn line_character(text: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(text.len());
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    (line, character)
+}
+
+/// The position of a byte offset
+fn position(text: &str, offset: usize) -> Json {
+    let (line, character) = line_character(text, offset);
+    Json::object([("line", line.into()), ("character", character.into())])
+}
+
+/// The byte offset of a position. Positions past the end of a line are at its end.
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = position.get("line")?.as_f64()? as usize;
+    let character = position.get("character")?.as_f64()? as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+/// The range of a span, whose end is included
+fn range(text: &str, span: Span) -> Json {
+    let end = span.end.max(span.begin).min(text.len());
+    let end = end + text[end..].chars().next().map_or(0, char::len_utf8);
+    Json::object([
+        ("start", position(text, span.begin)),
+        ("end", position(text, end)),
+    ])
+}
+
+fn location(uri: &str, text: &str, span: Span) -> Json {
+    Json::object([("uri", uri.into()), ("range", range(text, span))])
+}
+
+/// The hover text of a symbol, in Markdown
+fn hover(symbol: &Symbol) -> String {
+    let mut contents = format!("```nino\n{}\n```", symbol.detail());
+    if let Some(doc) = &symbol.doc {
+        contents.push_str("\n\n");
+        contents.push_str(doc);
+    }
+    contents
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    /// Handles a message from the client and returns the messages to send back
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = match message.get("method").and_then(Json::as_str) {
+            Some(method) => method,
+            None => return vec![error(&Json::Null, INVALID_REQUEST, "Expected a method")],
+        };
+        let params = message.get("params").unwrap_or(&Json::Null);
+        match message.get("id") {
+            Some(id) => vec![match self.request(method, params) {
+                Ok(result) => response(id, result),
+                Err((code, message)) => error(id, code, &message),
+            }],
+            None => self.notification(method, params),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (f64, String)> {
+        match method {
+            "initialize" => Ok(Json::object([
+                (
+                    "capabilities",
+                    Json::object([
+                        ("textDocumentSync", 1usize.into()),
+                        ("definitionProvider", true.into()),
+                        ("hoverProvider", true.into()),
+                        ("documentSymbolProvider", true.into()),
+                        ("completionProvider", Json::object([])),
+                        (
+                            "semanticTokensProvider",
+                            Json::object([("legend", legend()), ("full", true.into())]),
+                        ),
+                    ]),
+                ),
+                ("serverInfo", Json::object([("name", "nino-lsp".into())])),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let document = params.get("textDocument");
+        let uri = document.and_then(|document| document.get("uri")?.as_str());
+        match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = document.and_then(|document| document.get("text")?.as_str());
+                self.update(uri, text.unwrap_or_default())
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // The whole text is sent on every change, the last one is the current one
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last()?.get("text")?.as_str());
+                match text {
+                    Some(text) => self.update(uri, text),
+                    None => vec![],
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(uri);
+                vec![self.diagnostics(uri, &Analysis::default())]
+            }
+            ("exit", _) => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: &str) -> Vec<Json> {
+        self.documents.insert(uri.to_string(), text.to_string());
+        vec![self.diagnostics(uri, &Analysis::new(text))]
+    }
+
+    fn diagnostics(&self, uri: &str, analysis: &Analysis) -> Json {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                // Errors at the end of the input have no label
+                let span = diagnostic.labels.first().map_or(
+                    Span {
+                        begin: text.len(),
+                        end: text.len(),
+                    },
+                    |label| label.span,
+                );
+                let severity: usize = match diagnostic.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                    Severity::Note => 3,
+                };
+                Json::object([
+                    ("range", range(text, span)),
+                    ("severity", severity.into()),
+                    ("code", diagnostic.code.map_or(Json::Null, Json::from)),
+                    ("source", "nino".into()),
+                    ("message", diagnostic.message.as_str().into()),
+                ])
+            })
+            .collect();
+        notification(
+            "textDocument/publishDiagnostics",
+            Json::object([
+                ("uri", uri.into()),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        )
+    }
+
+    /// The URI, text and offset of a request about a position
+    fn document<'a>(
+        &'a self,
+        params: &'a Json,
+    ) -> Result<(&'a str, &'a str, usize), (f64, String)> {
+        let invalid = || {
+            (
+                INVALID_PARAMS,
+                "Expected a document and a position".to_string(),
+            )
+        };
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri")?.as_str())
+            .ok_or_else(invalid)?;
+        let text = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document {}", uri)))?;
+        let offset = match params.get("position") {
+            Some(position) => offset(text, position).ok_or_else(invalid)?,
+            None => 0,
+        };
+        Ok((uri, text, offset))
+    }
+
+    fn definition(&self, params: &Json) -> Result<Json, (f64, String)> {
+        let (uri, text, offset) = self.document(params)?;
+        let analysis = Analysis::new(text);
+        Ok(analysis
+            .symbol_at(offset)
+            .map_or(Json::Null, |symbol| location(uri, text, symbol.span)))
+    }
+
+    fn hover(&self, params: &Json) -> Result<Json, (f64, String)> {
+        let (_, text, offset) = self.document(params)?;
+        let analysis = Analysis::new(text);
+        Ok(analysis.symbol_at(offset).map_or(Json::Null, |symbol| {
+            Json::object([(
+                "contents",
+                Json::object([("kind", "markdown".into()), ("value", hover(symbol).into())]),
+            )])
+        }))
+    }
+
+    fn document_symbols(&self, params: &Json) -> Result<Json, (f64, String)> {
+        let (_, text, _) = self.document(params)?;
+        let analysis = Analysis::new(text);
+        let symbols = analysis
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.kind != SymbolKind::Parameter)
+            .map(|symbol| {
+                let kind = match symbol.kind {
+                    SymbolKind::Function => SYMBOL_FUNCTION,
+                    _ => SYMBOL_VARIABLE,
+                };
+                Json::object([
+                    ("name", symbol.name.as_str().into()),
+                    ("detail", symbol.detail().into()),
+                    ("kind", kind.into()),
+                    ("range", range(text, symbol.declaration)),
+                    ("selectionRange", range(text, symbol.span)),
+                ])
+            })
+            .collect();
+        Ok(Json::Array(symbols))
+    }
+
+    fn completion(&self, params: &Json) -> Result<Json, (f64, String)> {
+        let (_, text, offset) = self.document(params)?;
+        let analysis = Analysis::new(text);
+        let mut items: Vec<Json> = analysis
+            .visible_at(offset)
+            .into_iter()
+            .map(|symbol| {
+                let kind = match symbol.kind {
+                    SymbolKind::Function => COMPLETION_FUNCTION,
+                    _ => COMPLETION_VARIABLE,
+                };
+                let mut item = vec![
+                    ("label", symbol.name.as_str().into()),
+                    ("kind", kind.into()),
+                    ("detail", symbol.detail().into()),
+                ];
+                if let Some(doc) = &symbol.doc {
+                    item.push(("documentation", doc.as_str().into()));
+                }
+                Json::object(item)
+            })
+            .collect();
+        items.extend(Builtin::ALL.iter().map(|builtin| {
+            Json::object([
+                ("label", builtin.name().into()),
+                ("kind", COMPLETION_FUNCTION.into()),
+                ("detail", "builtin".into()),
+                ("documentation", builtin.description().into()),
+            ])
+        }));
+        Ok(Json::Array(items))
+    }
+
+    fn semantic_tokens(&self, params: &Json) -> Result<Json, (f64, String)> {
+        let (_, text, _) = self.document(params)?;
+        // Each token is five numbers: the line and character relative to the previous token, the
+        // length, the type and the modifiers. Tokens on several lines are split.
+        let mut data: Vec<Json> = vec![];
+        let (mut previous_line, mut previous_character) = (0, 0);
+        for token in semantic_tokens(text) {
+            let mut offset = token.offset;
+            for part in text[token.offset..token.offset + token.length].split('\n') {
+                let (line, character) = line_character(text, offset);
+                offset += part.len() + 1;
+                let part = part.trim_end_matches('\r');
+                if part.is_empty() {
+                    continue;
+                }
+                if line != previous_line {
+                    previous_character = 0;
+                }
+                data.extend([
+                    (line - previous_line).into(),
+                    (character - previous_character).into(),
+                    part.chars().map(char::len_utf16).sum::<usize>().into(),
+                    token.token_type.into(),
+                    (token.modifiers as usize).into(),
+                ]);
+                (previous_line, previous_character) = (line, character);
+            }
+        }
+        Ok(Json::object([("data", Json::Array(data))]))
+    }
+}
+
+/// Reads a message with its `Content-Length` header. Returns `None` at the end of the input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Result<Json, String>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => return Ok(Some(Err("Missing Content-Length header".to_string()))),
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(
+        String::from_utf8(body)
+            .map_err(|error| error.to_string())
+            .and_then(|body| Json::parse(&body)),
+    ))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Serves a client until it asks to exit or closes the input, and returns the exit code
+pub fn run(mut reader: impl BufRead, mut writer: impl Write) -> io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut reader)? {
+        let replies = match message {
+            Ok(message) => server.handle(&message),
+            Err(message) => vec![error(&Json::Null, PARSE_ERROR, &message)],
+        };
+        for reply in replies {
+            write_message(&mut writer, &reply)?;
+        }
+        if let Some(code) = server.exit {
+            return Ok(code);
+        }
+    }
+    Ok(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, character: usize) -> Json {
+        Json::object([("line", line.into()), ("character", character.into())])
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "let é:num = 1;\n\n😀x";
+        assert_eq!(position(text, 0), at(0, 0));
+        assert_eq!(position(text, 6), at(0, 5));
+        assert_eq!(position(text, 17), at(2, 0));
+        assert_eq!(position(text, 21), at(2, 2));
+        assert_eq!(offset(text, &at(0, 5)), Some(6));
+        assert_eq!(offset(text, &at(1, 0)), Some(16));
+        assert_eq!(offset(text, &at(1, 7)), Some(16));
+        assert_eq!(offset(text, &at(2, 2)), Some(21));
+        assert_eq!(offset(text, &at(5, 0)), None);
+    }
+
+    #[test]
+    fn test_read_message() {
+        let input = "Content-Length: 17\r\nContent-Type: x\r\n\r\n{\"method\":\"exit\"}";
+        let mut reader = input.as_bytes();
+        let message = read_message(&mut reader).unwrap().unwrap().unwrap();
+        assert_eq!(message, Json::object([("method", "exit".into())]));
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+}
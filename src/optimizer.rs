@@ -0,0 +1,532 @@
+//! Optimizations on the AST.
+//!
+//! - Arithmetic and comparisons on constants are folded into their result.
+//! - Match arms whose pattern is a constant that can never match are removed, and a match on a
+//!   constant that is known to select an arm is replaced by that arm.
+//! - Calls to trivial functions, that is functions without any calls in their body, are replaced
+//!   by the body of the function with the arguments substituted for the parameters, when the
+//!   arguments are constants or parameters.
+
+use std::collections::HashMap;
+use std::mem::discriminant;
+
+use crate::parser::{
+    BinaryOperation, BinaryOperator, Declaration, Expression, FunctionDeclaration, Item, Match,
+};
+
+pub fn optimize(items: Vec<Item>) -> Vec<Item> {
+    let mut declarations = HashMap::new();
+    for item in &items {
+        if let Item::Declaration(declaration) = item {
+            *declarations.entry(declaration.name.clone()).or_insert(0) += 1;
+        }
+    }
+
+    let mut optimizer = Optimizer {
+        functions: HashMap::new(),
+    };
+    let mut result = vec![];
+    for item in items {
+        match item {
+            Item::Declaration(declaration) => {
+                let expression = optimizer.expression(*declaration.expression, &[]);
+                // A function can only be inlined when its name always refers to it. The calls in
+                // the items before the declaration run before it exists, so they are left alone.
+                if let Expression::FunctionDeclaration(function) = &expression {
+                    if declarations[&declaration.name] == 1 && is_pure(&function.expression) {
+                        optimizer
+                            .functions
+                            .insert(declaration.name.clone(), function.clone());
+                    }
+                }
+                result.push(Item::Declaration(Declaration {
+                    expression: Box::new(expression),
+                    ..declaration
+                }));
+            }
+            Item::Expression(expression) => {
+                result.push(Item::Expression(optimizer.expression(expression, &[])));
+            }
+            Item::Error => result.push(Item::Error),
+        }
+    }
+    result
+}
+
+/// Constants are literals and arrays of constants
+fn is_constant(expression: &Expression) -> bool {
+    match expression {
+        Expression::Number(..) | Expression::Char(..) | Expression::Bool(..) => true,
+        Expression::Array(_, elements) => elements.iter().all(is_constant),
+        _ => false,
+    }
+}
+
+/// Pure expressions do not call any function, so evaluating them has no side effects and they
+/// can be evaluated any number of times
+fn is_pure(expression: &Expression) -> bool {
+    match expression {
+        Expression::Identifier(..)
+        | Expression::Number(..)
+        | Expression::Char(..)
+        | Expression::Bool(..)
+        | Expression::FunctionDeclaration(..) => true,
+        Expression::Array(_, elements) => elements.iter().all(is_pure),
+        Expression::FunctionCall(..) => false,
+        Expression::Match(match_) => {
+            is_pure(&match_.value)
+                && match_
+                    .patterns
+                    .iter()
+                    .all(|(pattern, arm)| is_pure(pattern) && is_pure(arm))
+                && match_.default.as_deref().is_none_or(is_pure)
+        }
+        Expression::BinaryOperation(binary) => is_pure(&binary.left) && is_pure(&binary.right),
+    }
+}
+
+/// Whether the argument can be substituted for a parameter. The function may use the parameter
+/// any number of times, maybe never, so evaluating the argument must have no effect and must not
+/// fail. Only constants and the parameters of the function the call is in are safe: a global may
+/// be used before it is declared, and an operation may be given values of the wrong type.
+fn is_substitutable(argument: &Expression, parameters: &[String]) -> bool {
+    match argument {
+        Expression::Identifier(name, _) => parameters.contains(name),
+        argument => is_constant(argument),
+    }
+}
+
+/// Whether any identifier in the expression is one of `names`
+fn refers_to(expression: &Expression, names: &[String]) -> bool {
+    match expression {
+        Expression::Identifier(name, _) => names.contains(name),
+        Expression::Number(..)
+        | Expression::Char(..)
+        | Expression::Bool(..)
+        | Expression::FunctionDeclaration(..) => false,
+        Expression::Array(_, elements) => elements.iter().any(|e| refers_to(e, names)),
+        Expression::FunctionCall(function_call) => {
+            names.contains(&function_call.name)
+                || function_call.arguments.iter().any(|e| refers_to(e, names))
+        }
+        Expression::Match(match_) => {
+            refers_to(&match_.value, names)
+                || match_
+                    .patterns
+                    .iter()
+                    .any(|(pattern, arm)| refers_to(pattern, names) || refers_to(arm, names))
+                || match_
+                    .default
+                    .as_deref()
+                    .is_some_and(|default| refers_to(default, names))
+        }
+        Expression::BinaryOperation(binary) => {
+            refers_to(&binary.left, names) || refers_to(&binary.right, names)
+        }
+    }
+}
+
+/// Replaces the parameters of a function with the arguments of a call
+fn substitute(expression: Expression, arguments: &HashMap<&str, &Expression>) -> Expression {
+    match expression {
+        Expression::Identifier(name, location) => match arguments.get(name.as_str()) {
+            Some(argument) => (*argument).clone(),
+            None => Expression::Identifier(name, location),
+        },
+        Expression::Array(type_, elements) => Expression::Array(
+            type_,
+            elements
+                .into_iter()
+                .map(|element| substitute(element, arguments))
+                .collect(),
+        ),
+        Expression::Match(match_) => Expression::Match(Match {
+            value: Box::new(substitute(*match_.value, arguments)),
+            patterns: match_
+                .patterns
+                .into_iter()
+                .map(|(pattern, arm)| (substitute(pattern, arguments), substitute(arm, arguments)))
+                .collect(),
+            default: match_
+                .default
+                .map(|default| Box::new(substitute(*default, arguments))),
+            location: match_.location,
+        }),
+        Expression::BinaryOperation(binary) => Expression::BinaryOperation(BinaryOperation {
+            operator: binary.operator,
+            left: Box::new(substitute(*binary.left, arguments)),
+            right: Box::new(substitute(*binary.right, arguments)),
+            location: binary.location,
+        }),
+        // Function declarations have their own parameters and the body of a trivial function
+        // has no calls
+        _ => expression,
+    }
+}
+
+/// Computes a binary operation on two constants, if it is valid
+fn fold(operator: BinaryOperator, left: &Expression, right: &Expression) -> Option<Expression> {
+    match (left, right) {
+        (Expression::Number(left), Expression::Number(right)) => {
+            let (left, right) = (*left, *right);
+            Some(match operator {
+                BinaryOperator::Add => Expression::Number(left + right),
+                BinaryOperator::Subtract => Expression::Number(left - right),
+                BinaryOperator::Multiply => Expression::Number(left * right),
+                BinaryOperator::Divide => Expression::Number(left / right),
+                BinaryOperator::Modulo => Expression::Number(left % right),
+                BinaryOperator::Equal => Expression::Bool(left == right),
+                BinaryOperator::NotEqual => Expression::Bool(left != right),
+                BinaryOperator::GreaterThan => Expression::Bool(left > right),
+                BinaryOperator::LessThan => Expression::Bool(left < right),
+                BinaryOperator::GreaterEqualThan => Expression::Bool(left >= right),
+                BinaryOperator::LessEqualThan => Expression::Bool(left <= right),
+                BinaryOperator::And | BinaryOperator::Or => return None,
+            })
+        }
+        (Expression::Array(left_type, left), Expression::Array(right_type, right)) => {
+            match operator {
+                BinaryOperator::Equal => Some(Expression::Bool(left == right)),
+                BinaryOperator::Add if left_type == right_type => {
+                    let mut result = left.clone();
+                    result.extend(right.iter().cloned());
+                    Some(Expression::Array(left_type.clone(), result))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Removes the arms that can never match. If the arm that is taken is known, the match is
+/// replaced by it.
+fn eliminate_arms(match_: Match) -> Expression {
+    let location = match_.location;
+    let value = *match_.value;
+    let constant_value = is_constant(&value);
+    let mut patterns: Vec<(Expression, Expression)> = vec![];
+    let mut arms = match_.patterns.into_iter();
+    let mut default = match_.default;
+
+    while let Some((pattern, arm)) = arms.next() {
+        if !is_constant(&pattern) {
+            patterns.push((pattern, arm));
+            continue;
+        }
+        // A pattern that is equal to an earlier one can never match
+        let duplicate = patterns
+            .iter()
+            .any(|(earlier, _)| is_constant(earlier) && *earlier == pattern);
+        if duplicate {
+            continue;
+        }
+        if !constant_value {
+            patterns.push((pattern, arm));
+            continue;
+        }
+        if discriminant(&value) != discriminant(&pattern) {
+            // This is an error at runtime, which is kept as is
+            patterns.push((pattern, arm));
+            patterns.extend(arms);
+            return Expression::Match(Match {
+                value: Box::new(value),
+                patterns,
+                default,
+                location,
+            });
+        }
+        if value == pattern {
+            // None of the arms after this one can be reached
+            if patterns.is_empty() {
+                return arm;
+            }
+            default = Some(Box::new(arm));
+            break;
+        }
+    }
+
+    match default {
+        Some(default) if constant_value && patterns.is_empty() => *default,
+        default => Expression::Match(Match {
+            value: Box::new(value),
+            patterns,
+            default,
+            location,
+        }),
+    }
+}
+
+struct Optimizer {
+    /// The trivial functions that can be inlined
+    functions: HashMap<String, FunctionDeclaration>,
+}
+
+impl Optimizer {
+    /// `parameters` are the parameters of the function the expression is in
+    fn expression(&self, expression: Expression, parameters: &[String]) -> Expression {
+        match expression {
+            Expression::Identifier(..)
+            | Expression::Number(..)
+            | Expression::Char(..)
+            | Expression::Bool(..) => expression,
+            Expression::Array(type_, elements) => Expression::Array(
+                type_,
+                elements
+                    .into_iter()
+                    .map(|element| self.expression(element, parameters))
+                    .collect(),
+            ),
+            Expression::FunctionDeclaration(function) => {
+                let parameters: Vec<String> = function
+                    .parameters
+                    .iter()
+                    .map(|parameter| parameter.name.clone())
+                    .collect();
+                Expression::FunctionDeclaration(FunctionDeclaration {
+                    expression: Box::new(self.expression(*function.expression, &parameters)),
+                    ..function
+                })
+            }
+            Expression::FunctionCall(mut function_call) => {
+                function_call.arguments = function_call
+                    .arguments
+                    .into_iter()
+                    .map(|argument| self.expression(argument, parameters))
+                    .collect();
+                match self.inline(&function_call.name, &function_call.arguments, parameters) {
+                    Some(body) => self.expression(body, parameters),
+                    None => Expression::FunctionCall(function_call),
+                }
+            }
+            Expression::Match(match_) => eliminate_arms(Match {
+                value: Box::new(self.expression(*match_.value, parameters)),
+                patterns: match_
+                    .patterns
+                    .into_iter()
+                    .map(|(pattern, arm)| {
+                        (
+                            self.expression(pattern, parameters),
+                            self.expression(arm, parameters),
+                        )
+                    })
+                    .collect(),
+                default: match_
+                    .default
+                    .map(|default| Box::new(self.expression(*default, parameters))),
+                location: match_.location,
+            }),
+            Expression::BinaryOperation(binary) => {
+                let left = self.expression(*binary.left, parameters);
+                let right = self.expression(*binary.right, parameters);
+                if is_constant(&left) && is_constant(&right) {
+                    if let Some(result) = fold(binary.operator, &left, &right) {
+                        return result;
+                    }
+                }
+                Expression::BinaryOperation(BinaryOperation {
+                    operator: binary.operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    location: binary.location,
+                })
+            }
+        }
+    }
+
+    /// Returns the body of the function with the arguments substituted, if the call can be
+    /// inlined
+    fn inline(
+        &self,
+        name: &str,
+        arguments: &[Expression],
+        parameters: &[String],
+    ) -> Option<Expression> {
+        // A parameter with the same name hides the function
+        if parameters.iter().any(|parameter| parameter == name) {
+            return None;
+        }
+        let function = self.functions.get(name)?;
+        if function.parameters.len() != arguments.len()
+            || !arguments
+                .iter()
+                .all(|argument| is_substitutable(argument, parameters))
+        {
+            return None;
+        }
+        // The globals the function refers to must not be hidden by the parameters at the call
+        let own_parameters: Vec<String> = function
+            .parameters
+            .iter()
+            .map(|parameter| parameter.name.clone())
+            .collect();
+        let hidden: Vec<String> = parameters
+            .iter()
+            .filter(|parameter| !own_parameters.contains(parameter))
+            .cloned()
+            .collect();
+        if refers_to(&function.expression, &hidden) {
+            return None;
+        }
+
+        let arguments = function
+            .parameters
+            .iter()
+            .map(|parameter| parameter.name.as_str())
+            .zip(arguments)
+            .collect();
+        Some(substitute((*function.expression).clone(), &arguments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::tokenize;
+    use crate::parser::{parse, Location};
+
+    use super::*;
+
+    fn assert_optimized(code: &str, expected: &str) {
+        let items = optimize(parse(&tokenize(code).unwrap()).unwrap());
+        assert_eq!(items, parse(&tokenize(expected).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        assert_optimized("let x:num = 1 + 2 * 3 - 8 / 4;", "let x:num = 5;");
+        assert_optimized("let x:num = 7 mod 4 * y;", "let x:num = 3 * y;");
+        // Only the constant part is folded
+        assert_optimized(
+            "let f:fn = (n:num):num => 4 * n / (2 * 3 + 1);",
+            "let f:fn = (n:num):num => 4 * n / 7;",
+        );
+    }
+
+    #[test]
+    fn test_fold_comparison() {
+        assert_optimized("let x:bool = 1 + 3 > 2;", "let x:bool = true;");
+        assert_optimized("let x:bool = 1 == 2;", "let x:bool = false;");
+        assert_optimized(
+            "let x:bool = \"ab\" == \"a\" + \"b\";",
+            "let x:bool = true;",
+        );
+    }
+
+    #[test]
+    fn test_invalid_operation() {
+        let items = optimize(parse(&tokenize("let x:num = 1 + true;").unwrap()).unwrap());
+        assert_eq!(
+            items,
+            vec![Item::Declaration(Declaration {
+                name: "x".to_string(),
+                type_: crate::parser::Type::Number,
+                expression: Box::new(Expression::BinaryOperation(BinaryOperation {
+                    operator: BinaryOperator::Add,
+                    left: Box::new(Expression::Number(1.0)),
+                    right: Box::new(Expression::Bool(true)),
+                    location: Location::default(),
+                })),
+                location: Location::default(),
+                doc: None,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_constant_match() {
+        assert_optimized(
+            "let x:num = true ? { 1 > 2 => a, 2 > 1 => b, c };",
+            "let x:num = b;",
+        );
+        assert_optimized("let x:num = 3 ? { 1 => a, 2 => b, c };", "let x:num = c;");
+    }
+
+    #[test]
+    fn test_dead_arms() {
+        // Arms after a non-constant pattern are kept, unless they can never match
+        assert_optimized(
+            "let x:num = true ? { y => a, false => b, true => c, d => e, f };",
+            "let x:num = true ? { y => a, c };",
+        );
+        // Duplicated patterns can never match
+        assert_optimized(
+            "let x:num = y ? { 0 => a, 1 => b, 0 => c, d };",
+            "let x:num = y ? { 0 => a, 1 => b, d };",
+        );
+    }
+
+    #[test]
+    fn test_match_type_error() {
+        assert_optimized(
+            "let x:num = 1 ? { 2 => a, true => b, 1 => c };",
+            "let x:num = 1 ? { true => b, 1 => c };",
+        );
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_optimized(
+            "let inc:fn = (x:num):num => x + 1;
+let two:num = inc(1);
+let f:fn = (y:num):num => inc(y);",
+            "let inc:fn = (x:num):num => x + 1;
+let two:num = 2;
+let f:fn = (y:num):num => y + 1;",
+        );
+    }
+
+    #[test]
+    fn test_inline_match() {
+        assert_optimized(
+            "let polarity:fn = (x:num):num => true ? {
+    x mod 2 == 0 => 1,
+    -1
+};
+let a:num = polarity(3);
+let g:fn = (n:num):num => polarity(n);",
+            "let polarity:fn = (x:num):num => true ? {
+    x mod 2 == 0 => 1,
+    -1
+};
+let a:num = -1;
+let g:fn = (n:num):num => true ? {
+    n mod 2 == 0 => 1,
+    -1
+};",
+        );
+    }
+
+    #[test]
+    fn test_no_inline() {
+        let code = "let a:num = inc(1);
+let inc:fn = (x:num):num => x + k;
+let fact:fn = (n:num):num => n ? { 0 => 1, n * fact(n - 1) };
+let b:num = inc(print(1));
+let c:num = fact(3);
+let g:fn = (k:num):num => inc(k);
+let h:fn = (inc:fn):num => inc(1);";
+        // The call before the declaration, the call with an argument that has side effects, the
+        // recursive function, the hidden global and the hiding parameter
+        assert_optimized(code, code);
+
+        let code = "let inc:fn = (x:num):num => x + 1;
+let a:num = inc(1);
+let inc:fn = (x:num):num => x + 2;";
+        // The function is declared twice
+        assert_optimized(code, code);
+
+        let code = "let zero:fn = (x:num):num => 0;
+let a:num = zero(n ? { 1 => 2 });";
+        // The argument fails when n is not 1, even though the parameter is not used
+        assert_optimized(code, code);
+
+        let code = "let zero:fn = (x:num):num => 0;
+print(zero(1 + true));
+print(zero(b));
+let b:num = 1;
+let f:fn = (y:num):num => zero(y * 2);";
+        // The arguments fail on the types, on the global used before its declaration and on the
+        // parameter that is not a number
+        assert_optimized(code, code);
+    }
+}
@@ -1,6 +1,6 @@
-use std::{iter::Peekable, slice::Iter};
+use std::{collections::HashMap, iter::Peekable, slice::Iter};
 
-use crate::lexer::{Token, TokenKind};
+use crate::lexer::{Span, Token, TokenKind};
 
 #[derive(Debug, PartialEq)]
 pub struct ParserError {
@@ -14,6 +14,33 @@ impl std::fmt::Display for ParserError {
     }
 }
 
+/// Where a node was parsed from. Locations are ignored when comparing nodes, so that the same
+/// program compares equal no matter how it is laid out in the source.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Location {
+    pub begin: usize,
+    pub end: usize,
+}
+
+impl Location {
+    pub fn new(begin: usize, end: usize) -> Self {
+        Location { begin, end }
+    }
+
+    pub fn span(self) -> Span {
+        Span {
+            begin: self.begin,
+            end: self.end,
+        }
+    }
+}
+
+impl PartialEq for Location {
+    fn eq(&self, _: &Location) -> bool {
+        true
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Type {
     Number,
@@ -23,10 +50,25 @@ pub enum Type {
     Array(Box<Type>),
 }
 
+/// Writes the type as it is written in the source
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Number => write!(f, "num"),
+            Type::Char => write!(f, "char"),
+            Type::Boolean => write!(f, "bool"),
+            Type::Function => write!(f, "fn"),
+            Type::Array(type_) => write!(f, "[{}]", type_),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct FunctionParameter {
     pub name: String,
     pub type_: Type,
+    /// From the name to the type
+    pub location: Location,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -34,12 +76,18 @@ pub struct FunctionDeclaration {
     pub parameters: Vec<FunctionParameter>,
     pub return_type: Type,
     pub expression: Box<Expression>,
+    /// The signature, from the left parenthesis to the arrow
+    pub location: Location,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FunctionCall {
     pub name: String,
     pub arguments: Vec<Expression>,
+    /// Whether the call is in tail position, see [crate::tail_calls]
+    pub tail: bool,
+    /// From the name to the right parenthesis
+    pub location: Location,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -47,6 +95,8 @@ pub struct Match {
     pub value: Box<Expression>,
     pub patterns: Vec<(Expression, Expression)>,
     pub default: Option<Box<Expression>>,
+    /// From the question mark to the right brace
+    pub location: Location,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -71,6 +121,8 @@ pub struct BinaryOperation {
     pub operator: BinaryOperator,
     pub left: Box<Expression>,
     pub right: Box<Expression>,
+    /// The operator
+    pub location: Location,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -78,11 +130,15 @@ pub struct Declaration {
     pub name: String,
     pub type_: Type,
     pub expression: Box<Expression>,
+    /// From `let` to the semicolon
+    pub location: Location,
+    /// The lines of the `##` comments right before the declaration
+    pub doc: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Identifier(String),
+    Identifier(String, Location),
 
     Number(f64),
     Char(u8),
@@ -97,19 +153,286 @@ pub enum Expression {
     BinaryOperation(BinaryOperation),
 }
 
-#[derive(Debug, PartialEq)]
+impl Expression {
+    /// Where the expression is in the source. Literals and arrays have no location of their own.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expression::Identifier(_, location) => Some(location.span()),
+            Expression::FunctionDeclaration(function) => Some(function.location.span()),
+            Expression::FunctionCall(function_call) => Some(function_call.location.span()),
+            Expression::BinaryOperation(binary) => Some(binary.location.span()),
+            Expression::Match(match_) => Some(match_.location.span()),
+            Expression::Number(..)
+            | Expression::Char(..)
+            | Expression::Bool(..)
+            | Expression::Array(..) => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Item {
     Declaration(Declaration),
     Expression(Expression),
+    /// Placeholder for an item that could not be parsed, see [parse_recovering]
+    Error,
+}
+
+/// Writes the operator as it is written in the source
+impl std::fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let operator = match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "mod",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::LessEqualThan => "<=",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::GreaterEqualThan => ">=",
+            // Not in the grammar yet
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+        };
+        write!(f, "{}", operator)
+    }
+}
+
+impl BinaryOperator {
+    /// How tightly the operator binds, from [parse_equality] to [parse_factor]. The operators of
+    /// a level are parsed from left to right.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::And
+            | BinaryOperator::Or => 1,
+            BinaryOperator::LessThan
+            | BinaryOperator::LessEqualThan
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterEqualThan => 2,
+            BinaryOperator::Add | BinaryOperator::Subtract => 3,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulo => 4,
+        }
+    }
+}
+
+/// A match is parsed after a primary expression, so it cannot be the value of another match
+const MATCH_PRECEDENCE: u8 = 5;
+const PRIMARY_PRECEDENCE: u8 = 6;
+
+impl Expression {
+    /// How tightly the expression binds. The body of a function goes as far as it can, so a
+    /// function binds the least.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::FunctionDeclaration(..) => 0,
+            Expression::BinaryOperation(binary) => binary.operator.precedence(),
+            Expression::Match(..) => MATCH_PRECEDENCE,
+            _ => PRIMARY_PRECEDENCE,
+        }
+    }
+
+    /// Writes the expression where one that binds at least as tightly as `precedence` is
+    /// expected, in parentheses if it does not
+    fn write(&self, f: &mut std::fmt::Formatter, precedence: u8) -> std::fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+fn write_list(f: &mut std::fmt::Formatter, expressions: &[Expression]) -> std::fmt::Result {
+    for (i, expression) in expressions.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", expression)?;
+    }
+    Ok(())
+}
+
+/// The characters that are written as they are in character and string literals
+fn is_printable(value: u8) -> bool {
+    (b' '..=b'~').contains(&value)
+}
+
+/// Writes the expression as it can be written in the source, with as few parentheses as the
+/// precedence of the operators allows. Numbers that have no literal are written as a division.
+///
+/// An empty array has no literal either, since the parser takes the type of an array from its
+/// first element. Empty strings are written as `""`, but other empty arrays are written as `[]`,
+/// which does not parse back.
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expression::Identifier(name, _) => write!(f, "{}", name),
+            Expression::Number(value) if value.is_nan() => write!(f, "(0 / 0)"),
+            Expression::Number(value) if value.is_infinite() => {
+                write!(f, "({} / 0)", value.signum())
+            }
+            Expression::Number(value) => write!(f, "{}", value),
+            Expression::Char(value) => match value {
+                b'\'' => write!(f, "'\\''"),
+                b'\\' => write!(f, "'\\\\'"),
+                b'\n' => write!(f, "'\\n'"),
+                b'\t' => write!(f, "'\\t'"),
+                value if is_printable(*value) => write!(f, "'{}'", *value as char),
+                value => write!(f, "'\\x{:02X}'", value),
+            },
+            Expression::Bool(value) => write!(f, "{}", value),
+            // Strings can only have escapes for ASCII characters
+            Expression::Array(Type::Char, elements)
+                if elements.iter().all(
+                    |element| matches!(element, Expression::Char(value) if value.is_ascii()),
+                ) =>
+            {
+                write!(f, "\"")?;
+                for element in elements {
+                    if let Expression::Char(value) = element {
+                        match value {
+                            b'"' => write!(f, "\\\"")?,
+                            b'\\' => write!(f, "\\\\")?,
+                            b'\n' => write!(f, "\\n")?,
+                            b'\t' => write!(f, "\\t")?,
+                            value if is_printable(*value) => write!(f, "{}", *value as char)?,
+                            value => write!(f, "\\x{:02X}", value)?,
+                        }
+                    }
+                }
+                write!(f, "\"")
+            }
+            Expression::Array(_, elements) => {
+                write!(f, "[")?;
+                write_list(f, elements)?;
+                write!(f, "]")
+            }
+            Expression::FunctionDeclaration(function) => {
+                write!(f, "(")?;
+                for (i, parameter) in function.parameters.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", parameter)?;
+                }
+                write!(f, "):{} => {}", function.return_type, function.expression)
+            }
+            Expression::FunctionCall(call) => {
+                write!(f, "{}(", call.name)?;
+                write_list(f, &call.arguments)?;
+                write!(f, ")")
+            }
+            Expression::Match(match_) => {
+                match_.value.write(f, PRIMARY_PRECEDENCE)?;
+                write!(f, " ? {{")?;
+                let mut arms = 0;
+                for (pattern, arm) in &match_.patterns {
+                    let separator = if arms == 0 { " " } else { ", " };
+                    write!(f, "{}{} => {}", separator, pattern, arm)?;
+                    arms += 1;
+                }
+                if let Some(default) = &match_.default {
+                    let separator = if arms == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, default)?;
+                    arms += 1;
+                }
+                if arms > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "}}")
+            }
+            Expression::BinaryOperation(binary) => {
+                let precedence = binary.operator.precedence();
+                binary.left.write(f, precedence)?;
+                write!(f, " {} ", binary.operator)?;
+                binary.right.write(f, precedence + 1)
+            }
+        }
+    }
+}
+
+/// Writes the parameter as it is written in the source
+impl std::fmt::Display for FunctionParameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.name, self.type_)
+    }
+}
+
+/// Writes the declaration as it is written in the source, after its doc comments
+impl std::fmt::Display for Declaration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for line in self.doc.iter().flat_map(|doc| doc.lines()) {
+            writeln!(f, "## {}", line)?;
+        }
+        write!(f, "let {}:{} = {};", self.name, self.type_, self.expression)
+    }
+}
+
+/// Writes the item as it is written in the source
+impl std::fmt::Display for Item {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Item::Declaration(declaration) => write!(f, "{}", declaration),
+            Item::Expression(expression) => write!(f, "{};", expression),
+            Item::Error => write!(f, "# This item could not be parsed"),
+        }
+    }
+}
+
+/// The type written as `name` in the source
+pub(crate) fn parse_type(name: &str) -> Option<Type> {
+    match name {
+        "num" => Some(Type::Number),
+        "char" => Some(Type::Char),
+        "bool" => Some(Type::Boolean),
+        "fn" => Some(Type::Function),
+        "[num]" => Some(Type::Array(Box::new(Type::Number))),
+        "[bool]" => Some(Type::Array(Box::new(Type::Boolean))),
+        "[char]" => Some(Type::Array(Box::new(Type::Char))),
+        "[fn]" => Some(Type::Array(Box::new(Type::Function))),
+        _ => None,
+    }
+}
+
+/// The type of an array, taken from its first element
+pub(crate) fn array_type(first: &Expression) -> Type {
+    match first {
+        Expression::Number(..) => Type::Number,
+        Expression::Char(..) => Type::Char,
+        Expression::Bool(..) => Type::Boolean,
+        Expression::Array(..) => Type::Array(Box::new(Type::Function)),
+        Expression::FunctionDeclaration(..) => Type::Function,
+        Expression::BinaryOperation(..)
+        | Expression::Identifier(..)
+        | Expression::FunctionCall(..)
+        | Expression::Match(..) => Type::Number,
+    }
+}
+
+fn end_of_input() -> ParserError {
+    ParserError {
+        message: "Unexpected end of input".to_string(),
+        token: None,
+    }
+}
+
+fn next_token<'a>(tokens: &mut Peekable<Iter<'a, Token>>) -> Result<&'a Token, ParserError> {
+    tokens.next().ok_or_else(end_of_input)
+}
+
+fn peek_token<'a>(tokens: &mut Peekable<Iter<'a, Token>>) -> Result<&'a Token, ParserError> {
+    tokens.peek().copied().ok_or_else(end_of_input)
 }
 
 fn parse_group(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, ParserError> {
-    let expression = match parse_expression(tokens) {
-        Ok(expression) => expression,
-        Err(error) => return Err(error),
-    };
+    let expression = parse_expression(tokens)?;
 
-    match tokens.next().unwrap() {
+    match next_token(tokens)? {
         Token {
             kind: TokenKind::RightParen,
             ..
@@ -125,12 +448,14 @@ fn parse_group(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, ParserE
     Ok(expression)
 }
 
+/// `begin` is where the left parenthesis is, which has already been consumed
 fn parse_function_declaration(
     tokens: &mut Peekable<Iter<Token>>,
+    begin: usize,
 ) -> Result<Expression, ParserError> {
     let mut arguments = vec![];
     loop {
-        match tokens.peek().unwrap() {
+        match peek_token(tokens)? {
             Token {
                 kind: TokenKind::RightParen,
                 ..
@@ -145,11 +470,12 @@ fn parse_function_declaration(
                 let _ = tokens.next();
             }
             _ => {
-                let name = match tokens.next().unwrap() {
+                let (name, name_begin) = match next_token(tokens)? {
                     Token {
                         kind: TokenKind::Identifier(name),
+                        begin,
                         ..
-                    } => name.clone(),
+                    } => (name.clone(), *begin),
                     token => {
                         return Err(ParserError {
                             message: format!("Expected identifier, got {:?}", token.kind),
@@ -157,7 +483,7 @@ fn parse_function_declaration(
                         })
                     }
                 };
-                match tokens.next().unwrap() {
+                match next_token(tokens)? {
                     Token {
                         kind: TokenKind::Colon,
                         ..
@@ -169,31 +495,27 @@ fn parse_function_declaration(
                         })
                     }
                 };
-                let type_ = match tokens.next().unwrap() {
+                let (type_, type_end) = match next_token(tokens)? {
                     Token {
                         kind: TokenKind::Type(type_),
                         begin,
                         end,
-                    } => match type_.as_str() {
-                        "num" => Type::Number,
-                        "char" => Type::Char,
-                        "bool" => Type::Boolean,
-                        "fn" => Type::Function,
-                        "[num]" => Type::Array(Box::new(Type::Number)),
-                        "[bool]" => Type::Array(Box::new(Type::Boolean)),
-                        "[char]" => Type::Array(Box::new(Type::Char)),
-                        "[fn]" => Type::Array(Box::new(Type::Function)),
-                        _ => {
-                            return Err(ParserError {
-                                message: format!("Unknown type: {:?}", type_),
-                                token: Some(Token {
-                                    kind: TokenKind::Type(type_.clone()),
-                                    begin: *begin,
-                                    end: *end,
-                                }),
-                            })
-                        }
-                    },
+                    } => (
+                        match parse_type(type_) {
+                            Some(type_) => type_,
+                            None => {
+                                return Err(ParserError {
+                                    message: format!("Unknown type: {:?}", type_),
+                                    token: Some(Token {
+                                        kind: TokenKind::Type(type_.clone()),
+                                        begin: *begin,
+                                        end: *end,
+                                    }),
+                                })
+                            }
+                        },
+                        *end,
+                    ),
                     token => {
                         return Err(ParserError {
                             message: format!("Expected type, got {:?}", token.kind),
@@ -201,12 +523,16 @@ fn parse_function_declaration(
                         })
                     }
                 };
-                arguments.push(FunctionParameter { name, type_ });
+                arguments.push(FunctionParameter {
+                    name,
+                    type_,
+                    location: Location::new(name_begin, type_end),
+                });
             }
         }
     }
 
-    match tokens.next().unwrap() {
+    match next_token(tokens)? {
         Token {
             kind: TokenKind::Colon,
             ..
@@ -219,21 +545,14 @@ fn parse_function_declaration(
         }
     };
 
-    let return_type = match tokens.next().unwrap() {
+    let return_type = match next_token(tokens)? {
         Token {
             kind: TokenKind::Type(type_),
             begin,
             end,
-        } => match type_.as_str() {
-            "num" => Type::Number,
-            "char" => Type::Char,
-            "bool" => Type::Boolean,
-            "fn" => Type::Function,
-            "[num]" => Type::Array(Box::new(Type::Number)),
-            "[bool]" => Type::Array(Box::new(Type::Boolean)),
-            "[char]" => Type::Array(Box::new(Type::Char)),
-            "[fn]" => Type::Array(Box::new(Type::Function)),
-            _ => {
+        } => match parse_type(type_) {
+            Some(type_) => type_,
+            None => {
                 return Err(ParserError {
                     message: format!("Unknown type: {:?}", type_),
                     token: Some(Token {
@@ -252,11 +571,12 @@ fn parse_function_declaration(
         }
     };
 
-    match tokens.next().unwrap() {
+    let end = match next_token(tokens)? {
         Token {
             kind: TokenKind::Arrow,
+            end,
             ..
-        } => {}
+        } => *end,
         token => {
             return Err(ParserError {
                 message: format!("Expected arrow, got {:?}", token.kind),
@@ -265,38 +585,38 @@ fn parse_function_declaration(
         }
     };
 
-    let expression = match parse_expression(tokens) {
-        Ok(expression) => expression,
-        Err(error) => return Err(error),
-    };
+    let expression = parse_expression(tokens)?;
 
     Ok(Expression::FunctionDeclaration(FunctionDeclaration {
         parameters: arguments,
         return_type,
         expression: Box::new(expression),
+        location: Location::new(begin, end),
     }))
 }
 
 pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, ParserError> {
-    let expression = match tokens.next().unwrap() {
+    let expression = match next_token(tokens)? {
         Token {
             kind: TokenKind::Identifier(name),
-            ..
-        } => match tokens.peek().unwrap() {
+            begin,
+            end,
+        } => match peek_token(tokens)? {
             Token {
                 kind: TokenKind::LeftParen,
                 ..
             } => {
                 let _ = tokens.next();
                 let mut arguments = vec![];
-                loop {
-                    match tokens.peek().unwrap() {
+                let end = loop {
+                    match peek_token(tokens)? {
                         Token {
                             kind: TokenKind::RightParen,
+                            end,
                             ..
                         } => {
                             let _ = tokens.next();
-                            break;
+                            break *end;
                         }
                         Token {
                             kind: TokenKind::Comma,
@@ -305,23 +625,23 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
                             let _ = tokens.next();
                         }
                         _ => {
-                            let expression = match parse_expression(tokens) {
-                                Ok(expression) => expression,
-                                Err(error) => return Err(error),
-                            };
+                            let expression = parse_expression(tokens)?;
                             arguments.push(expression);
                         }
                     }
-                }
+                };
                 Expression::FunctionCall(FunctionCall {
                     name: name.clone(),
                     arguments,
+                    tail: false,
+                    location: Location::new(*begin, end),
                 })
             }
-            _ => Expression::Identifier(name.clone()),
+            _ => Expression::Identifier(name.clone(), Location::new(*begin, *end)),
         },
         Token {
             kind: TokenKind::LeftParen,
+            begin,
             ..
         } => {
             // TODO: This is a hack, we should be able to parse a group without cloning the tokens
@@ -332,20 +652,20 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
 
             match possible_group {
                 Ok(_) => parse_group(tokens),
-                Err(_) => parse_function_declaration(tokens),
+                Err(_) => parse_function_declaration(tokens, *begin),
             }?
         }
         Token {
-            kind: TokenKind::Number(value),
+            kind: TokenKind::Number(value, _),
             ..
         } => Expression::Number(*value),
         Token {
             kind: TokenKind::Subtraction,
             ..
         } => {
-            let value = match tokens.next().unwrap() {
+            let value = match next_token(tokens)? {
                 Token {
-                    kind: TokenKind::Number(value),
+                    kind: TokenKind::Number(value, _),
                     ..
                 } => *value,
                 token => {
@@ -368,10 +688,7 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
         Token {
             kind: TokenKind::String(value),
             ..
-        } => Expression::Array(
-            Type::Char,
-            value.chars().map(|c| Expression::Char(c as u8)).collect(),
-        ),
+        } => Expression::Array(Type::Char, value.bytes().map(Expression::Char).collect()),
         Token {
             kind: TokenKind::LeftBracket,
             begin,
@@ -379,7 +696,7 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
         } => {
             let mut elements = vec![];
             loop {
-                match tokens.peek().unwrap() {
+                match peek_token(tokens)? {
                     Token {
                         kind: TokenKind::RightBracket,
                         ..
@@ -394,25 +711,13 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
                         let _ = tokens.next();
                     }
                     _ => {
-                        let expression = match parse_expression(tokens) {
-                            Ok(expression) => expression,
-                            Err(error) => return Err(error),
-                        };
+                        let expression = parse_expression(tokens)?;
                         elements.push(expression);
                     }
                 }
             }
-            // Get the type from the first element
             let array_type = match elements.first() {
-                Some(Expression::Number(..)) => Type::Number,
-                Some(Expression::Char(..)) => Type::Char,
-                Some(Expression::Bool(..)) => Type::Boolean,
-                Some(Expression::Array(..)) => Type::Array(Box::new(Type::Function)),
-                Some(Expression::FunctionDeclaration(..)) => Type::Function,
-                Some(Expression::BinaryOperation(..)) => Type::Number,
-                Some(Expression::Identifier(..)) => Type::Number,
-                Some(Expression::FunctionCall(..)) => Type::Number,
-                Some(Expression::Match(..)) => Type::Number,
+                Some(first) => array_type(first),
                 None => {
                     return Err(ParserError {
                         message: "Empty array, cannot infer array type".to_string(),
@@ -436,10 +741,11 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
 
     if let Some(token) = tokens.peek() {
         if token.kind == TokenKind::Question {
+            let begin = token.begin;
             let _ = tokens.next(); // Consumes question mark
             let mut patterns = vec![];
             let mut default = None;
-            match tokens.next().unwrap() {
+            match next_token(tokens)? {
                 Token {
                     kind: TokenKind::LeftBrace,
                     ..
@@ -451,14 +757,15 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
                     })
                 }
             }
-            loop {
-                match tokens.peek().unwrap() {
+            let end = loop {
+                match peek_token(tokens)? {
                     Token {
                         kind: TokenKind::RightBrace,
+                        end,
                         ..
                     } => {
                         let _ = tokens.next();
-                        break;
+                        break *end;
                     }
                     Token {
                         kind: TokenKind::Comma,
@@ -467,11 +774,8 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
                         let _ = tokens.next();
                     }
                     _ => {
-                        let value = match parse_expression(tokens) {
-                            Ok(expression) => expression,
-                            Err(error) => return Err(error),
-                        };
-                        match tokens.peek().unwrap() {
+                        let value = parse_expression(tokens)?;
+                        match peek_token(tokens)? {
                             Token {
                                 kind: TokenKind::RightBrace,
                                 ..
@@ -491,22 +795,20 @@ pub fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, P
                                         "Expected arrow or default, got {:?}",
                                         token.kind
                                     ),
-                                    token: Some((**token).clone()),
+                                    token: Some(token.clone()),
                                 })
                             }
                         };
-                        let expression = match parse_expression(tokens) {
-                            Ok(expression) => expression,
-                            Err(error) => return Err(error),
-                        };
+                        let expression = parse_expression(tokens)?;
                         patterns.push((value, expression));
                     }
                 }
-            }
+            };
             return Ok(Expression::Match(Match {
                 value: Box::new(expression),
                 patterns,
                 default,
+                location: Location::new(begin, end),
             }));
         }
     }
@@ -518,10 +820,7 @@ pub fn parse_unary(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, Par
 }
 
 pub fn parse_factor(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, ParserError> {
-    let mut expression = match parse_unary(tokens) {
-        Ok(expression) => expression,
-        Err(error) => return Err(error),
-    };
+    let mut expression = parse_unary(tokens)?;
 
     while let Some(token) = tokens.peek() {
         match token {
@@ -529,7 +828,8 @@ pub fn parse_factor(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, Pa
                 kind: TokenKind::Multiplication | TokenKind::Division | TokenKind::Modulus,
                 ..
             } => {
-                let operator = match tokens.next().unwrap() {
+                let operator_token = next_token(tokens)?;
+                let operator = match operator_token {
                     Token {
                         kind: TokenKind::Multiplication,
                         ..
@@ -552,14 +852,12 @@ pub fn parse_factor(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, Pa
                         })
                     }
                 };
-                let right = match parse_unary(tokens) {
-                    Ok(expression) => expression,
-                    Err(error) => return Err(error),
-                };
+                let right = parse_unary(tokens)?;
                 expression = Expression::BinaryOperation(BinaryOperation {
                     operator,
                     left: Box::new(expression),
                     right: Box::new(right),
+                    location: Location::new(operator_token.begin, operator_token.end),
                 });
             }
             _ => break,
@@ -569,10 +867,7 @@ pub fn parse_factor(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, Pa
     Ok(expression)
 }
 pub fn parse_term(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, ParserError> {
-    let mut expression = match parse_factor(tokens) {
-        Ok(expression) => expression,
-        Err(error) => return Err(error),
-    };
+    let mut expression = parse_factor(tokens)?;
 
     while let Some(token) = tokens.peek() {
         match token {
@@ -580,7 +875,8 @@ pub fn parse_term(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, Pars
                 kind: TokenKind::Addition | TokenKind::Subtraction,
                 ..
             } => {
-                let operator = match tokens.next().unwrap() {
+                let operator_token = next_token(tokens)?;
+                let operator = match operator_token {
                     Token {
                         kind: TokenKind::Addition,
                         ..
@@ -596,14 +892,12 @@ pub fn parse_term(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, Pars
                         })
                     }
                 };
-                let right = match parse_factor(tokens) {
-                    Ok(expression) => expression,
-                    Err(error) => return Err(error),
-                };
+                let right = parse_factor(tokens)?;
                 expression = Expression::BinaryOperation(BinaryOperation {
                     operator,
                     left: Box::new(expression),
                     right: Box::new(right),
+                    location: Location::new(operator_token.begin, operator_token.end),
                 });
             }
             _ => break,
@@ -613,10 +907,7 @@ pub fn parse_term(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, Pars
     Ok(expression)
 }
 pub fn parse_comparison(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, ParserError> {
-    let mut expression = match parse_term(tokens) {
-        Ok(expression) => expression,
-        Err(error) => return Err(error),
-    };
+    let mut expression = parse_term(tokens)?;
 
     while let Some(token) = tokens.peek() {
         match token {
@@ -636,7 +927,8 @@ pub fn parse_comparison(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression
                 kind: TokenKind::GreaterEqualThan,
                 ..
             } => {
-                let operator = match tokens.next().unwrap() {
+                let operator_token = next_token(tokens)?;
+                let operator = match operator_token {
                     Token{kind: TokenKind::LessThan, ..} => BinaryOperator::LessThan,
                     Token{kind: TokenKind::LessEqualThan, ..} => BinaryOperator::LessEqualThan,
                     Token{kind: TokenKind::GreaterThan, ..} => BinaryOperator::GreaterThan,
@@ -648,14 +940,12 @@ pub fn parse_comparison(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression
                         })
                     }
                 };
-                let right = match parse_term(tokens) {
-                    Ok(expression) => expression,
-                    Err(error) => return Err(error),
-                };
+                let right = parse_term(tokens)?;
                 expression = Expression::BinaryOperation(BinaryOperation {
                     operator,
                     left: Box::new(expression),
                     right: Box::new(right),
+                    location: Location::new(operator_token.begin, operator_token.end),
                 });
             }
             _ => break,
@@ -665,10 +955,7 @@ pub fn parse_comparison(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression
     Ok(expression)
 }
 pub fn parse_equality(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression, ParserError> {
-    let mut expression = match parse_comparison(tokens) {
-        Ok(expression) => expression,
-        Err(error) => return Err(error),
-    };
+    let mut expression = parse_comparison(tokens)?;
 
     while let Some(token) = tokens.peek() {
         match token {
@@ -676,7 +963,8 @@ pub fn parse_equality(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression,
                 kind: TokenKind::Equal | TokenKind::NotEqual,
                 ..
             } => {
-                let operator = match tokens.next().unwrap() {
+                let operator_token = next_token(tokens)?;
+                let operator = match operator_token {
                     Token {
                         kind: TokenKind::Equal,
                         ..
@@ -692,14 +980,12 @@ pub fn parse_equality(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression,
                         })
                     }
                 };
-                let right = match parse_comparison(tokens) {
-                    Ok(expression) => expression,
-                    Err(error) => return Err(error),
-                };
+                let right = parse_comparison(tokens)?;
                 expression = Expression::BinaryOperation(BinaryOperation {
                     operator,
                     left: Box::new(expression),
                     right: Box::new(right),
+                    location: Location::new(operator_token.begin, operator_token.end),
                 });
             }
             _ => break,
@@ -714,11 +1000,12 @@ pub fn parse_expression(tokens: &mut Peekable<Iter<Token>>) -> Result<Expression
 }
 
 pub fn parse_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<Declaration, ParserError> {
-    let _ = match tokens.next().unwrap() {
+    let begin = match next_token(tokens)? {
         Token {
             kind: TokenKind::Let,
+            begin,
             ..
-        } => {}
+        } => *begin,
         token => {
             return Err(ParserError {
                 message: format!("Expected let, got {:?}", token.kind),
@@ -727,7 +1014,7 @@ pub fn parse_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<Declarati
         }
     };
 
-    let name = match tokens.next().unwrap() {
+    let name = match next_token(tokens)? {
         Token {
             kind: TokenKind::Identifier(name),
             ..
@@ -740,7 +1027,7 @@ pub fn parse_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<Declarati
         }
     };
 
-    match tokens.next().unwrap() {
+    match next_token(tokens)? {
         Token {
             kind: TokenKind::Colon,
             ..
@@ -753,21 +1040,14 @@ pub fn parse_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<Declarati
         }
     };
 
-    let type_ = match tokens.next().unwrap() {
+    let type_ = match next_token(tokens)? {
         Token {
             kind: TokenKind::Type(type_),
             begin,
             end,
-        } => match type_.as_str() {
-            "num" => Type::Number,
-            "char" => Type::Char,
-            "fn" => Type::Function,
-            "bool" => Type::Boolean,
-            "[num]" => Type::Array(Box::new(Type::Number)),
-            "[bool]" => Type::Array(Box::new(Type::Boolean)),
-            "[char]" => Type::Array(Box::new(Type::Char)),
-            "[fn]" => Type::Array(Box::new(Type::Function)),
-            _ => {
+        } => match parse_type(type_) {
+            Some(type_) => type_,
+            None => {
                 return Err(ParserError {
                     message: format!("Unknown type: {:?}", type_),
                     token: Some(Token {
@@ -786,7 +1066,7 @@ pub fn parse_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<Declarati
         }
     };
 
-    match tokens.next().unwrap() {
+    match next_token(tokens)? {
         Token {
             kind: TokenKind::Assignment,
             ..
@@ -799,16 +1079,14 @@ pub fn parse_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<Declarati
         }
     };
 
-    let expression = match parse_expression(tokens) {
-        Ok(expression) => expression,
-        Err(error) => return Err(error),
-    };
+    let expression = parse_expression(tokens)?;
 
-    match tokens.next().unwrap() {
+    let end = match next_token(tokens)? {
         Token {
             kind: TokenKind::Semicolon,
+            end,
             ..
-        } => {}
+        } => *end,
         token => {
             return Err(ParserError {
                 message: format!("Expected semicolon, got {:?}", token.kind),
@@ -820,41 +1098,112 @@ pub fn parse_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<Declarati
         name,
         type_,
         expression: Box::new(expression),
+        location: Location::new(begin, end),
+        doc: None,
     })
 }
 
-pub fn parse(tokens: &[Token]) -> Result<Vec<Item>, ParserError> {
-    let mut tokens = tokens.iter().peekable();
-    let mut items = vec![];
+fn parse_item(tokens: &mut Peekable<Iter<Token>>) -> Result<Item, ParserError> {
+    if peek_token(tokens)?.kind == TokenKind::Let {
+        return Ok(Item::Declaration(parse_declaration(tokens)?));
+    }
+    let expression = parse_expression(tokens)?;
+    match next_token(tokens)? {
+        Token {
+            kind: TokenKind::Semicolon,
+            ..
+        } => {}
+        token => {
+            return Err(ParserError {
+                message: format!("Expected semicolon, got {:?}", token.kind),
+                token: Some(token.clone()),
+            })
+        }
+    }
+    Ok(Item::Expression(expression))
+}
+
+/// Skips an item that failed to parse, starting from its first token. The item ends at the first
+/// semicolon that is not inside braces, and a `let` outside of braces starts the next item.
+fn synchronize(tokens: &mut Peekable<Iter<Token>>) {
+    let mut depth = 0usize;
+    // The first token is always skipped, so that the parser makes progress
+    tokens.next();
     while let Some(token) = tokens.peek() {
         match token.kind {
             TokenKind::EOF => break,
-            TokenKind::Let => match parse_declaration(&mut tokens) {
-                Ok(declaration) => items.push(Item::Declaration(declaration)),
-                Err(error) => return Err(error),
-            },
-            _ => {
-                let expression = match parse_expression(&mut tokens) {
-                    Ok(expression) => expression,
-                    Err(error) => return Err(error),
-                };
-                items.push(Item::Expression(expression));
-                match tokens.next().unwrap() {
-                    Token {
-                        kind: TokenKind::Semicolon,
-                        ..
-                    } => {}
-                    token => {
-                        return Err(ParserError {
-                            message: format!("Expected semicolon, got {:?}", token.kind),
-                            token: Some(token.clone()),
-                        })
-                    }
+            TokenKind::Let if depth == 0 => break,
+            TokenKind::Semicolon if depth == 0 => {
+                tokens.next();
+                break;
+            }
+            TokenKind::LeftBrace => depth += 1,
+            TokenKind::RightBrace => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        tokens.next();
+    }
+}
+
+/// Removes the comments, which are not part of the grammar. The doc comments are returned by the
+/// offset of the `let` they document.
+fn strip_comments(tokens: &[Token]) -> (Vec<Token>, HashMap<usize, String>) {
+    let mut code = vec![];
+    let mut docs = HashMap::new();
+    let mut doc: Vec<&str> = vec![];
+    for token in tokens {
+        match &token.kind {
+            TokenKind::Comment(_) => {}
+            TokenKind::DocComment(line) => doc.push(line),
+            kind => {
+                if *kind == TokenKind::Let && !doc.is_empty() {
+                    docs.insert(token.begin, doc.join("\n"));
                 }
+                doc.clear();
+                code.push(token.clone());
+            }
+        }
+    }
+    (code, docs)
+}
+
+/// Parses every item, even after syntax errors. The items that could not be parsed are replaced
+/// by [Item::Error] and their errors are returned in the order they were found.
+pub fn parse_recovering(tokens: &[Token]) -> (Vec<Item>, Vec<ParserError>) {
+    let (tokens, mut docs) = strip_comments(tokens);
+    let mut tokens = tokens.iter().peekable();
+    let mut items = vec![];
+    let mut errors = vec![];
+    while let Some(token) = tokens.peek() {
+        if token.kind == TokenKind::EOF {
+            break;
+        }
+        let start = tokens.clone();
+        match parse_item(&mut tokens) {
+            Ok(Item::Declaration(mut declaration)) => {
+                declaration.doc = docs.remove(&declaration.location.begin);
+                items.push(Item::Declaration(declaration));
+            }
+            Ok(item) => items.push(item),
+            Err(error) => {
+                errors.push(error);
+                items.push(Item::Error);
+                tokens = start;
+                synchronize(&mut tokens);
             }
         }
     }
-    Ok(items)
+    (items, errors)
+}
+
+/// Parses the program, returning every syntax error if there are any
+pub fn parse(tokens: &[Token]) -> Result<Vec<Item>, Vec<ParserError>> {
+    let (items, errors) = parse_recovering(tokens);
+    if errors.is_empty() {
+        Ok(items)
+    } else {
+        Err(errors)
+    }
 }
 
 #[cfg(test)]
@@ -864,10 +1213,10 @@ mod tests {
     /// Testing `1 == 1`
     #[test]
     fn test_equality() {
-        let tokens = vec![
-            Token::new(TokenKind::Number(1.0), 0, 0),
+        let tokens = [
+            Token::new(TokenKind::Number(1.0, "1".to_string()), 0, 0),
             Token::new(TokenKind::Equal, 2, 3),
-            Token::new(TokenKind::Number(1.0), 5, 5),
+            Token::new(TokenKind::Number(1.0, "1".to_string()), 5, 5),
             Token::new(TokenKind::EOF, 6, 6),
         ];
         let mut iter = tokens.iter().peekable();
@@ -878,6 +1227,7 @@ mod tests {
                 operator: BinaryOperator::Equal,
                 left: Box::new(Expression::Number(1.0)),
                 right: Box::new(Expression::Number(1.0)),
+                location: Location::default(),
             }))
         );
         assert_eq!(*iter.next().unwrap(), Token::new(TokenKind::EOF, 6, 6));
@@ -886,10 +1236,10 @@ mod tests {
     /// Testing `print(1);`
     #[test]
     fn test_function_call() {
-        let tokens = vec![
+        let tokens = [
             Token::new(TokenKind::Identifier("print".to_string()), 0, 4),
             Token::new(TokenKind::LeftParen, 5, 5),
-            Token::new(TokenKind::Number(1.0), 6, 6),
+            Token::new(TokenKind::Number(1.0, "1".to_string()), 6, 6),
             Token::new(TokenKind::RightParen, 7, 7),
             Token::new(TokenKind::Semicolon, 8, 8),
             Token::new(TokenKind::EOF, 9, 9),
@@ -901,6 +1251,8 @@ mod tests {
             Ok(Expression::FunctionCall(FunctionCall {
                 name: "print".to_string(),
                 arguments: vec![Expression::Number(1.0)],
+                tail: false,
+                location: Location::default(),
             }))
         );
         assert_eq!(
@@ -909,28 +1261,26 @@ mod tests {
         );
     }
 
-
-
     /// Testing `(1+1) ? {1 => 2, 2 => 3, 4 };`
     #[test]
     fn test_match_group() {
         let tokens = vec![
             Token::new(TokenKind::LeftParen, 0, 0),
-            Token::new(TokenKind::Number(1.0), 1, 1),
+            Token::new(TokenKind::Number(1.0, "1".to_string()), 1, 1),
             Token::new(TokenKind::Addition, 2, 2),
-            Token::new(TokenKind::Number(1.0), 3, 3),
+            Token::new(TokenKind::Number(1.0, "1".to_string()), 3, 3),
             Token::new(TokenKind::RightParen, 4, 4),
             Token::new(TokenKind::Question, 6, 6),
             Token::new(TokenKind::LeftBrace, 8, 8),
-            Token::new(TokenKind::Number(1.0), 9, 9),
+            Token::new(TokenKind::Number(1.0, "1".to_string()), 9, 9),
             Token::new(TokenKind::Arrow, 11, 12),
-            Token::new(TokenKind::Number(2.0), 14, 14),
+            Token::new(TokenKind::Number(2.0, "2".to_string()), 14, 14),
             Token::new(TokenKind::Comma, 16, 16),
-            Token::new(TokenKind::Number(2.0), 18, 18),
+            Token::new(TokenKind::Number(2.0, "2".to_string()), 18, 18),
             Token::new(TokenKind::Arrow, 20, 21),
-            Token::new(TokenKind::Number(3.0), 23, 23),
+            Token::new(TokenKind::Number(3.0, "3".to_string()), 23, 23),
             Token::new(TokenKind::Comma, 25, 25),
-            Token::new(TokenKind::Number(4.0), 27, 27),
+            Token::new(TokenKind::Number(4.0, "4".to_string()), 27, 27),
             Token::new(TokenKind::RightBrace, 28, 28),
             Token::new(TokenKind::Semicolon, 29, 29),
             Token::new(TokenKind::EOF, 30, 30),
@@ -945,24 +1295,26 @@ mod tests {
                     operator: BinaryOperator::Add,
                     left: Box::new(Expression::Number(1.0)),
                     right: Box::new(Expression::Number(1.0)),
+                    location: Location::default(),
                 })),
                 patterns: vec![
                     (Expression::Number(1.0), Expression::Number(2.0),),
                     (Expression::Number(2.0), Expression::Number(3.0),),
                 ],
                 default: Some(Box::new(Expression::Number(4.0))),
+                location: Location::default(),
             }))
         );
     }
 
     /// Testing `(1 + 2)`
     #[test]
-    fn test_group(){
-        let tokens = vec![
+    fn test_group() {
+        let tokens = [
             Token::new(TokenKind::LeftParen, 0, 0),
-            Token::new(TokenKind::Number(1.0), 1, 1),
+            Token::new(TokenKind::Number(1.0, "1".to_string()), 1, 1),
             Token::new(TokenKind::Addition, 3, 3),
-            Token::new(TokenKind::Number(2.0), 5, 5),
+            Token::new(TokenKind::Number(2.0, "2".to_string()), 5, 5),
             Token::new(TokenKind::RightParen, 6, 6),
             Token::new(TokenKind::EOF, 7, 7),
         ];
@@ -976,8 +1328,103 @@ mod tests {
                 operator: BinaryOperator::Add,
                 left: Box::new(Expression::Number(1.0)),
                 right: Box::new(Expression::Number(2.0)),
+                location: Location::default(),
             }))
         );
         assert_eq!(*iter.next().unwrap(), Token::new(TokenKind::EOF, 7, 7));
     }
+
+    fn parse_source(source: &str) -> Vec<Item> {
+        parse(&crate::lexer::tokenize(source).unwrap()).unwrap()
+    }
+
+    fn display(source: &str) -> String {
+        let items: Vec<String> = parse_source(source).iter().map(Item::to_string).collect();
+        items.join("\n")
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(display("(1+2)*3;"), "(1 + 2) * 3;");
+        assert_eq!(display("1+(2*3);"), "1 + 2 * 3;");
+        assert_eq!(display("(1-2)-3;"), "1 - 2 - 3;");
+        assert_eq!(display("1-(2-3);"), "1 - (2 - 3);");
+        assert_eq!(display("1 - -2;"), "1 - -2;");
+        assert_eq!(
+            display("(1 < 2) == (3 mod 2 >= 1);"),
+            "1 < 2 == 3 mod 2 >= 1;"
+        );
+        assert_eq!(display("(1 == 2) < 3;"), "(1 == 2) < 3;");
+        assert_eq!(
+            display("(x + 1) ? {1 => (2), (y ? {3 => 4}) ? {true => 5} => 6, 7};"),
+            "(x + 1) ? { 1 => 2, (y ? { 3 => 4 }) ? { true => 5 } => 6, 7 };"
+        );
+        assert_eq!(display("x ? {} + 1;"), "x ? {} + 1;");
+        assert_eq!(
+            display("let f:fn = (x:num, g:fn):[num] => [x, g(x)];"),
+            "let f:fn = (x:num, g:fn):[num] => [x, g(x)];"
+        );
+        assert_eq!(
+            display("let f:fn = ():fn => ((x:num):num => x);"),
+            "let f:fn = ():fn => (x:num):num => x;"
+        );
+        assert_eq!(
+            display("let x:[char] = \"a\\\"\\n\\x01\";\nprint(['\\'', '\\xFF']);"),
+            "let x:[char] = \"a\\\"\\n\\x01\";\nprint(['\\'', '\\xFF']);"
+        );
+        assert_eq!(
+            display("## Doubles\n## a number\nlet double:fn = (x:num):num => 2 * x;"),
+            "## Doubles\n## a number\nlet double:fn = (x:num):num => 2 * x;"
+        );
+        // Folded numbers can have no literal
+        let infinity = Expression::BinaryOperation(BinaryOperation {
+            operator: BinaryOperator::Multiply,
+            left: Box::new(Expression::Number(f64::NEG_INFINITY)),
+            right: Box::new(Expression::Number(2.0)),
+            location: Location::default(),
+        });
+        assert_eq!(infinity.to_string(), "(-1 / 0) * 2");
+        // Values turned back into expressions can be empty arrays
+        assert_eq!(Expression::Array(Type::Char, vec![]).to_string(), "\"\"");
+        let empty = Expression::Array(Type::Number, vec![]).to_string();
+        assert_eq!(empty, "[]");
+        let tokens = crate::lexer::tokenize(&empty).unwrap();
+        assert_eq!(
+            parse_expression(&mut tokens.iter().peekable())
+                .unwrap_err()
+                .message,
+            "Empty array, cannot infer array type"
+        );
+    }
+
+    /// The programs of the parser tests are parsed back from what is displayed
+    #[test]
+    fn test_display_round_trip() {
+        for source in [
+            "1 == 1;",
+            "print(1);",
+            "(1+1) ? {1 => 2, 2 => 3, 4 };",
+            "(1 + 2);",
+            "let x:num = 3;",
+            "let x:num = -3.0;",
+            "let x:char = 'a';",
+            "let x:bool = true;",
+            "let x:fn = (x:num, y:num):num => x+y;",
+            "let x:[num] = [1, 2, 3];",
+            "let x:num = 1 ? {1 => 2, 2 => 3, 4 };",
+            "let x:[char] = \"nino\";",
+            "let x:bool = 1+3>2 == 1;",
+            "let f:fn = (x:num):num => x ? { 1 => g(x) };",
+            "## Doubles a number\nlet double:fn = (x:num):num => x * 2;",
+            "let x:num = 1 #[ one ]# + # two\n 2;",
+            "let f:fn = (n:num):num => 4 * n / (2 * 3 + 1);",
+            "let g:fn = (f:fn):fn => (x:num):num => f(f(x));\nprint(g((x:num):num => x + 1));",
+            "let x:[fn] = [[1], [2, 3]];",
+        ] {
+            let items = parse_source(source);
+            let displayed = display(source);
+            assert_eq!(parse_source(&displayed), items, "{}", displayed);
+            assert_eq!(display(&displayed), displayed);
+        }
+    }
 }
@@ -0,0 +1,307 @@
+//! A profiler, see `ninoi --profile`. It times every call of a nino function through the hook of
+//! the virtual machine, and keeps the tree of calls for the folded stacks that flame graph tools
+//! read.
+//!
+//! A tail call replaces the frame of its caller, so the caller is done: its time stops there and
+//! the callee is counted as called from the caller's caller, like the virtual machine runs it.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::virtual_machine::{Hook, State};
+
+/// The name of the root of the call tree
+const TOP_LEVEL: &str = "<top level>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    /// Including the tail calls
+    pub calls: usize,
+    /// The calls that replaced the frame of their caller, the iterations of a tail recursive loop
+    pub tail_calls: usize,
+    /// The time spent in the function and the functions it called. The time of a recursive call
+    /// is only counted once.
+    pub inclusive: Duration,
+    /// The time spent in the function itself
+    pub exclusive: Duration,
+    /// How many calls of the function have not returned
+    active: usize,
+}
+
+/// A node of the call tree, a function called from a path of callers
+struct Node {
+    function: Option<usize>,
+    parent: usize,
+    exclusive: Duration,
+}
+
+/// A function call that has not returned
+struct Frame {
+    function: usize,
+    node: usize,
+    start: Instant,
+    /// The inclusive time of the calls it made
+    children: Duration,
+}
+
+pub struct Profiler {
+    start: Instant,
+    /// The total time, once the run is finished
+    total: Option<Duration>,
+    functions: Vec<FunctionProfile>,
+    indices: HashMap<String, usize>,
+    stack: Vec<Frame>,
+    /// The call tree, the top level being the root
+    nodes: Vec<Node>,
+    children: HashMap<(usize, usize), usize>,
+    /// The inclusive time of the calls made from the top level
+    top_level_children: Duration,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    /// The clock starts now
+    pub fn new() -> Self {
+        Profiler {
+            start: Instant::now(),
+            total: None,
+            functions: vec![],
+            indices: HashMap::new(),
+            stack: vec![],
+            nodes: vec![Node {
+                function: None,
+                parent: 0,
+                exclusive: Duration::ZERO,
+            }],
+            children: HashMap::new(),
+            top_level_children: Duration::ZERO,
+        }
+    }
+
+    fn function(&mut self, name: String) -> usize {
+        if let Some(&index) = self.indices.get(&name) {
+            return index;
+        }
+        self.indices.insert(name.clone(), self.functions.len());
+        self.functions.push(FunctionProfile {
+            name,
+            calls: 0,
+            tail_calls: 0,
+            inclusive: Duration::ZERO,
+            exclusive: Duration::ZERO,
+            active: 0,
+        });
+        self.functions.len() - 1
+    }
+
+    fn node(&mut self, parent: usize, function: usize) -> usize {
+        let count = self.nodes.len();
+        let node = *self.children.entry((parent, function)).or_insert(count);
+        if node == count {
+            self.nodes.push(Node {
+                function: Some(function),
+                parent,
+                exclusive: Duration::ZERO,
+            });
+        }
+        node
+    }
+
+    /// Ends the call on top of the stack
+    fn end(&mut self, now: Instant) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let inclusive = now - frame.start;
+        let exclusive = inclusive.saturating_sub(frame.children);
+        let function = &mut self.functions[frame.function];
+        function.exclusive += exclusive;
+        function.active -= 1;
+        if function.active == 0 {
+            function.inclusive += inclusive;
+        }
+        self.nodes[frame.node].exclusive += exclusive;
+        match self.stack.last_mut() {
+            Some(caller) => caller.children += inclusive,
+            None => self.top_level_children += inclusive,
+        }
+    }
+
+    /// Stops the clock, ending the calls that have not returned if the program failed
+    pub fn finish(&mut self) {
+        let now = Instant::now();
+        while !self.stack.is_empty() {
+            self.end(now);
+        }
+        let total = now - self.start;
+        self.nodes[0].exclusive = total.saturating_sub(self.top_level_children);
+        self.total = Some(total);
+    }
+
+    /// The functions that were called, the most time spent in itself first
+    pub fn functions(&self) -> Vec<&FunctionProfile> {
+        let mut functions: Vec<&FunctionProfile> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.name.cmp(&b.name)));
+        functions
+    }
+
+    /// A table of the functions, for people
+    pub fn report(&self) -> String {
+        let milliseconds = |duration: Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        let total = self.total.unwrap_or_else(|| self.start.elapsed());
+        let width = self
+            .functions
+            .iter()
+            .map(|function| function.name.len())
+            .chain([TOP_LEVEL.len(), "function".len()])
+            .max()
+            .unwrap();
+        let mut report = format!(
+            "{:<width$} {:>10} {:>10} {:>14} {:>14}\n",
+            "function", "calls", "tail calls", "inclusive ms", "exclusive ms"
+        );
+        for function in self.functions() {
+            report.push_str(&format!(
+                "{:<width$} {:>10} {:>10} {:>14} {:>14}\n",
+                function.name,
+                function.calls,
+                function.tail_calls,
+                milliseconds(function.inclusive),
+                milliseconds(function.exclusive)
+            ));
+        }
+        report.push_str(&format!(
+            "{:<width$} {:>10} {:>10} {:>14} {:>14}\n",
+            TOP_LEVEL,
+            "",
+            "",
+            milliseconds(total),
+            milliseconds(self.nodes[0].exclusive)
+        ));
+        report
+    }
+
+    /// One line per path of calls with the time spent at its end in microseconds, the format of
+    /// the folded stacks of flame graph tools
+    pub fn folded(&self) -> String {
+        let mut lines = vec![];
+        for (index, node) in self.nodes.iter().enumerate() {
+            let mut names = vec![];
+            let mut current = index;
+            while let Some(function) = self.nodes[current].function {
+                names.push(self.functions[function].name.as_str());
+                current = self.nodes[current].parent;
+            }
+            names.push(TOP_LEVEL);
+            names.reverse();
+            lines.push(format!(
+                "{} {}\n",
+                names.join(";"),
+                node.exclusive.as_micros()
+            ));
+        }
+        lines.concat()
+    }
+}
+
+impl Hook for Profiler {
+    fn call(&mut self, state: &State, tail: bool) {
+        let now = Instant::now();
+        if tail {
+            self.end(now);
+        }
+        let name = state
+            .function()
+            .unwrap_or_else(|| "<anonymous>".to_string());
+        let function = self.function(name);
+        let parent = self.stack.last().map(|frame| frame.node).unwrap_or(0);
+        let node = self.node(parent, function);
+        let profile = &mut self.functions[function];
+        profile.calls += 1;
+        profile.active += 1;
+        if tail {
+            profile.tail_calls += 1;
+        }
+        self.stack.push(Frame {
+            function,
+            node,
+            start: now,
+            children: Duration::ZERO,
+        });
+    }
+
+    fn return_(&mut self, _state: &State) {
+        self.end(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+    use crate::virtual_machine::VirtualMachine;
+
+    fn profile(source: &str) -> Profiler {
+        let items = parse(&tokenize(source).unwrap()).unwrap();
+        let mut profiler = Profiler::new();
+        VirtualMachine::new()
+            .run_with_hook(items, &mut profiler)
+            .unwrap();
+        profiler.finish();
+        profiler
+    }
+
+    #[test]
+    fn test_calls() {
+        let profiler = profile(
+            "let count:fn = (n:num):num => n ? { 0 => 0, count(n - 1) };
+let fib:fn = (n:num):num => n ? { 0 => 0, 1 => 1, fib(n - 1) + fib(n - 2) };
+let start:fn = (n:num):num => count(n) + fib(n);
+let x:num = start(3);",
+        );
+        let mut calls: Vec<(&str, usize, usize)> = profiler
+            .functions()
+            .iter()
+            .map(|function| (function.name.as_str(), function.calls, function.tail_calls))
+            .collect();
+        calls.sort();
+        assert_eq!(calls, vec![("count", 4, 3), ("fib", 5, 0), ("start", 1, 0)]);
+
+        let fib = profiler.functions[profiler.indices["fib"]].clone();
+        assert!(fib.exclusive <= fib.inclusive);
+        let start = profiler.functions[profiler.indices["start"]].clone();
+        assert!(fib.inclusive <= start.inclusive);
+    }
+
+    #[test]
+    fn test_folded() {
+        let profiler = profile(
+            "let count:fn = (n:num):num => n ? { 0 => 0, count(n - 1) };
+let fib:fn = (n:num):num => n ? { 0 => 0, 1 => 1, fib(n - 1) + fib(n - 2) };
+let x:num = count(2) + fib(2);",
+        );
+        let folded = profiler.folded();
+        let stacks: Vec<&str> = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        // The tail calls do not grow the stack, the recursive calls do
+        assert_eq!(
+            stacks,
+            vec![
+                "<top level>",
+                "<top level>;count",
+                "<top level>;fib",
+                "<top level>;fib;fib",
+            ]
+        );
+    }
+}
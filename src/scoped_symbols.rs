@@ -8,6 +8,12 @@ pub struct ScopedSymbols<'a> {
     parent: Option<&'a ScopedSymbols<'a>>,
 }
 
+impl<'a> Default for ScopedSymbols<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> ScopedSymbols<'a> {
     pub fn new() -> Self {
         ScopedSymbols {
@@ -16,7 +22,7 @@ impl<'a> ScopedSymbols<'a> {
         }
     }
 
-    pub fn with_parent<'parent>(parent: &'a ScopedSymbols) -> Self {
+    pub fn with_parent(parent: &'a ScopedSymbols) -> Self {
         ScopedSymbols {
             symbols: HashMap::new(),
             parent: Some(parent),
@@ -27,6 +33,17 @@ impl<'a> ScopedSymbols<'a> {
         self.symbols.insert(key, declaration);
     }
 
+    /// The declarations of this scope, without the ones of its parents, in the order of the source
+    pub fn declarations(&self) -> Vec<&Declaration> {
+        let mut declarations: Vec<&Declaration> = self.symbols.values().collect();
+        declarations.sort_by(|a, b| (a.location.begin, &a.name).cmp(&(b.location.begin, &b.name)));
+        declarations
+    }
+
+    pub fn parent(&self) -> Option<&'a ScopedSymbols<'a>> {
+        self.parent
+    }
+
     pub fn get(&self, key: &str) -> Option<Declaration> {
         match self.symbols.get(key) {
             Some(declaration) => Some(declaration.clone()),
@@ -40,7 +57,7 @@ impl<'a> ScopedSymbols<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::{Expression, Type};
+    use crate::parser::{Expression, Location, Type};
 
     use super::*;
 
@@ -53,6 +70,8 @@ mod tests {
                 name: "a".to_string(),
                 type_: Type::Number,
                 expression: Box::new(Expression::Number(1.0)),
+                location: Location::default(),
+                doc: None,
             },
         );
 
@@ -63,6 +82,8 @@ mod tests {
                 name: "b".to_string(),
                 type_: Type::Number,
                 expression: Box::new(Expression::Number(2.0)),
+                location: Location::default(),
+                doc: None,
             },
         );
 
@@ -75,5 +96,14 @@ mod tests {
         assert_eq!(b.name, "b");
         assert_eq!(b.type_, Type::Number);
         assert_eq!(*b.expression, Expression::Number(2.0));
+
+        let names: Vec<&str> = symbols2
+            .declarations()
+            .iter()
+            .map(|declaration| declaration.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["b"]);
+        assert!(symbols2.parent().is_some());
+        assert!(symbols.parent().is_none());
     }
 }
@@ -0,0 +1,1074 @@
+//! A lossless concrete syntax tree. Unlike the AST it keeps every token, along with the
+//! whitespace and comments around it, so the source can be printed back exactly as it was written.
+//! Tools that rewrite code, like the formatter, work on this tree. The AST is derived from it with
+//! [SyntaxNode::to_items].
+//!
+//! Building the tree never fails: tokens that do not fit the grammar are wrapped in
+//! [SyntaxKind::Error] nodes and missing tokens are simply absent.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::slice::Iter;
+
+use crate::lexer::{tokenize, LexerError, Token, TokenKind};
+use crate::parser::{
+    array_type, parse_type, BinaryOperation, BinaryOperator, Declaration, Expression, FunctionCall,
+    FunctionDeclaration, FunctionParameter, Item, Location, Match, ParserError, Type,
+};
+
+/// Whitespace or a comment. Comments keep the token they were lexed as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub text: String,
+    /// `None` for whitespace
+    pub token: Option<Token>,
+}
+
+impl Trivia {
+    pub fn is_whitespace(&self) -> bool {
+        self.token.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub token: Token,
+    /// The text of the token as written in the source, empty for the end of file
+    pub text: String,
+    /// The trivia since the previous token's trailing trivia
+    pub leading: Vec<Trivia>,
+    /// The whitespace and comments after the token on the same line
+    pub trailing: Vec<Trivia>,
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> &TokenKind {
+        &self.token.kind
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntaxKind {
+    Program,
+    /// `let name:type = expression;`
+    Declaration,
+    /// An expression followed by a semicolon
+    ExpressionItem,
+    Literal,
+    Name,
+    /// `-` followed by an expression
+    Negation,
+    Binary,
+    /// An expression in parentheses
+    Group,
+    /// `(parameters):type => expression`
+    Function,
+    Parameters,
+    /// `name:type`
+    Parameter,
+    /// A name followed by arguments
+    Call,
+    Arguments,
+    Array,
+    /// `value ? { arms }`
+    Match,
+    /// `pattern => expression`
+    MatchArm,
+    /// The last arm of a match, without a pattern
+    Default,
+    /// Tokens that do not fit the grammar
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    /// Builds the tree of a whole program from its source and tokens
+    pub fn new(source: &str, tokens: Vec<Token>) -> SyntaxNode {
+        let mut builder = Builder {
+            tokens: attach_trivia(source, tokens),
+            position: 0,
+        };
+        builder.program()
+    }
+
+    /// Every token in the tree, in source order
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    /// The child nodes, skipping the tokens
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// Derives the AST of a program from its nodes. An item that has a missing or unexpected
+    /// token is replaced by [Item::Error], and its error is the one the parser gives for the same
+    /// source, see [parse_recovering](crate::parser::parse_recovering).
+    pub fn to_items(&self) -> (Vec<Item>, Vec<ParserError>) {
+        let mut deriver = Deriver {
+            tokens: self.tokens(),
+            position: 0,
+        };
+        deriver.program(self)
+    }
+}
+
+/// Writes the source back, byte for byte
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in self.tokens() {
+            for trivia in &token.leading {
+                write!(f, "{}", trivia.text)?;
+            }
+            write!(f, "{}", token.text)?;
+            for trivia in &token.trailing {
+                write!(f, "{}", trivia.text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tokenizes the source and builds its tree
+pub fn parse(source: &str) -> Result<SyntaxNode, LexerError> {
+    Ok(SyntaxNode::new(source, tokenize(source)?))
+}
+
+/// The offset right after the token. The comparison operators do not end where their span does,
+/// and a type left empty after a colon takes no characters at all.
+fn token_end(source: &str, token: &Token) -> usize {
+    match &token.kind {
+        TokenKind::Type(name) if name.is_empty() => token.begin,
+        TokenKind::LessThan | TokenKind::GreaterThan => token.begin + 1,
+        TokenKind::LessEqualThan | TokenKind::GreaterEqualThan => token.begin + 2,
+        TokenKind::EOF => source.len(),
+        _ => token.end + source[token.end..].chars().next().map_or(0, char::len_utf8),
+    }
+}
+
+/// Turns the comments and the whitespace between the tokens into trivia, attached to the tokens
+/// of the grammar
+fn attach_trivia(source: &str, tokens: Vec<Token>) -> Vec<SyntaxToken> {
+    let mut result: Vec<SyntaxToken> = vec![];
+    let mut pending = vec![];
+    // Trivia is trailing until the end of the line of the last token
+    let mut same_line = false;
+    let mut offset = 0;
+    for token in tokens {
+        let begin = match token.kind {
+            TokenKind::EOF => source.len(),
+            _ => token.begin,
+        };
+        if offset < begin {
+            let text = &source[offset..begin];
+            let trivia = Trivia {
+                text: text.to_string(),
+                token: None,
+            };
+            match result.last_mut() {
+                Some(last) if same_line && !text.contains('\n') => last.trailing.push(trivia),
+                _ => {
+                    same_line = false;
+                    pending.push(trivia);
+                }
+            }
+        }
+        let end = token_end(source, &token);
+        offset = end;
+        let text = source[begin..end].to_string();
+        match token.kind {
+            TokenKind::Comment(_) => {
+                let trivia = Trivia {
+                    text,
+                    token: Some(token),
+                };
+                match result.last_mut() {
+                    Some(last) if same_line => last.trailing.push(trivia),
+                    _ => pending.push(trivia),
+                }
+            }
+            TokenKind::DocComment(_) => {
+                same_line = false;
+                pending.push(Trivia {
+                    text,
+                    token: Some(token),
+                });
+            }
+            _ => {
+                same_line = true;
+                result.push(SyntaxToken {
+                    token,
+                    text,
+                    leading: std::mem::take(&mut pending),
+                    trailing: vec![],
+                });
+            }
+        }
+    }
+    result
+}
+
+/// The binary operators, from the loosest to the tightest binding
+const PRECEDENCE: [&[TokenKind]; 4] = [
+    &[TokenKind::Equal, TokenKind::NotEqual],
+    &[
+        TokenKind::LessThan,
+        TokenKind::LessEqualThan,
+        TokenKind::GreaterThan,
+        TokenKind::GreaterEqualThan,
+    ],
+    &[TokenKind::Addition, TokenKind::Subtraction],
+    &[
+        TokenKind::Multiplication,
+        TokenKind::Division,
+        TokenKind::Modulus,
+    ],
+];
+
+struct Builder {
+    /// Always ends with the end of file
+    tokens: Vec<SyntaxToken>,
+    position: usize,
+}
+
+impl Builder {
+    fn peek(&self) -> &TokenKind {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, distance: usize) -> &TokenKind {
+        let last = self.tokens.len() - 1;
+        self.tokens[(self.position + distance).min(last)].kind()
+    }
+
+    fn bump(&mut self, children: &mut Vec<SyntaxElement>) {
+        children.push(SyntaxElement::Token(self.tokens[self.position].clone()));
+        self.position += 1;
+    }
+
+    /// Consumes the next token if it matches
+    fn eat(&mut self, children: &mut Vec<SyntaxElement>, matches: fn(&TokenKind) -> bool) {
+        if matches(self.peek()) {
+            self.bump(children);
+        }
+    }
+
+    /// Wraps the next token in an error node
+    fn error(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        node(SyntaxKind::Error, children)
+    }
+
+    /// Whether the token ends the current item, so that lists and matches stop there
+    fn at_item_end(&self) -> bool {
+        matches!(
+            self.peek(),
+            TokenKind::Semicolon | TokenKind::Let | TokenKind::EOF
+        )
+    }
+
+    fn program(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        while *self.peek() != TokenKind::EOF {
+            children.push(SyntaxElement::Node(self.item()));
+        }
+        self.bump(&mut children);
+        node(SyntaxKind::Program, children)
+    }
+
+    fn item(&mut self) -> SyntaxNode {
+        let mut children = vec![];
+        if *self.peek() == TokenKind::Let {
+            self.bump(&mut children);
+            self.eat(&mut children, |kind| {
+                matches!(kind, TokenKind::Identifier(_))
+            });
+            self.eat(&mut children, |kind| *kind == TokenKind::Colon);
+            self.eat(&mut children, |kind| matches!(kind, TokenKind::Type(_)));
+            self.eat(&mut children, |kind| *kind == TokenKind::Assignment);
+            if let Some(expression) = self.expression() {
+                children.push(SyntaxElement::Node(expression));
+            }
+            self.eat(&mut children, |kind| *kind == TokenKind::Semicolon);
+            return node(SyntaxKind::Declaration, children);
+        }
+        match self.expression() {
+            Some(expression) => {
+                children.push(SyntaxElement::Node(expression));
+                self.eat(&mut children, |kind| *kind == TokenKind::Semicolon);
+                node(SyntaxKind::ExpressionItem, children)
+            }
+            None => self.error(),
+        }
+    }
+
+    /// Returns `None` without consuming anything if no expression starts here
+    fn expression(&mut self) -> Option<SyntaxNode> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Option<SyntaxNode> {
+        if level == PRECEDENCE.len() {
+            return self.postfix();
+        }
+        let mut left = self.binary(level + 1)?;
+        while PRECEDENCE[level].contains(self.peek()) {
+            let mut children = vec![SyntaxElement::Node(left)];
+            self.bump(&mut children);
+            if let Some(right) = self.binary(level + 1) {
+                children.push(SyntaxElement::Node(right));
+            }
+            left = node(SyntaxKind::Binary, children);
+        }
+        Some(left)
+    }
+
+    fn postfix(&mut self) -> Option<SyntaxNode> {
+        let value = self.primary()?;
+        if *self.peek() != TokenKind::Question {
+            return Some(value);
+        }
+        let mut children = vec![SyntaxElement::Node(value)];
+        self.bump(&mut children);
+        self.eat(&mut children, |kind| *kind == TokenKind::LeftBrace);
+        loop {
+            match self.peek() {
+                TokenKind::RightBrace => {
+                    self.bump(&mut children);
+                    break;
+                }
+                TokenKind::Comma => self.bump(&mut children),
+                _ if self.at_item_end() => break,
+                _ => {
+                    let arm = match self.expression() {
+                        Some(pattern) if *self.peek() == TokenKind::Arrow => {
+                            let mut arm = vec![SyntaxElement::Node(pattern)];
+                            self.bump(&mut arm);
+                            if let Some(expression) = self.expression() {
+                                arm.push(SyntaxElement::Node(expression));
+                            }
+                            node(SyntaxKind::MatchArm, arm)
+                        }
+                        Some(expression) => {
+                            node(SyntaxKind::Default, vec![SyntaxElement::Node(expression)])
+                        }
+                        None => self.error(),
+                    };
+                    children.push(SyntaxElement::Node(arm));
+                }
+            }
+        }
+        Some(node(SyntaxKind::Match, children))
+    }
+
+    fn primary(&mut self) -> Option<SyntaxNode> {
+        let mut children = vec![];
+        let kind = match self.peek() {
+            TokenKind::Identifier(_) => {
+                self.bump(&mut children);
+                if *self.peek() != TokenKind::LeftParen {
+                    return Some(node(SyntaxKind::Name, children));
+                }
+                children.push(SyntaxElement::Node(self.list(
+                    SyntaxKind::Arguments,
+                    TokenKind::RightParen,
+                    Self::expression,
+                )));
+                SyntaxKind::Call
+            }
+            TokenKind::Number(..)
+            | TokenKind::Character(_)
+            | TokenKind::Boolean(_)
+            | TokenKind::String(_) => {
+                self.bump(&mut children);
+                SyntaxKind::Literal
+            }
+            TokenKind::Subtraction => {
+                self.bump(&mut children);
+                if let Some(expression) = self.primary() {
+                    children.push(SyntaxElement::Node(expression));
+                }
+                SyntaxKind::Negation
+            }
+            TokenKind::LeftParen if self.at_function() => {
+                children.push(SyntaxElement::Node(self.list(
+                    SyntaxKind::Parameters,
+                    TokenKind::RightParen,
+                    Self::parameter,
+                )));
+                self.eat(&mut children, |kind| *kind == TokenKind::Colon);
+                self.eat(&mut children, |kind| matches!(kind, TokenKind::Type(_)));
+                self.eat(&mut children, |kind| *kind == TokenKind::Arrow);
+                if let Some(expression) = self.expression() {
+                    children.push(SyntaxElement::Node(expression));
+                }
+                SyntaxKind::Function
+            }
+            TokenKind::LeftParen => {
+                self.bump(&mut children);
+                if let Some(expression) = self.expression() {
+                    children.push(SyntaxElement::Node(expression));
+                }
+                self.eat(&mut children, |kind| *kind == TokenKind::RightParen);
+                SyntaxKind::Group
+            }
+            TokenKind::LeftBracket => {
+                return Some(self.list(
+                    SyntaxKind::Array,
+                    TokenKind::RightBracket,
+                    Self::expression,
+                ))
+            }
+            _ => return None,
+        };
+        Some(node(kind, children))
+    }
+
+    /// A function starts with `()`, `(,` or `(name:`
+    fn at_function(&self) -> bool {
+        match self.peek_at(1) {
+            TokenKind::RightParen | TokenKind::Comma => true,
+            TokenKind::Identifier(_) => *self.peek_at(2) == TokenKind::Colon,
+            _ => false,
+        }
+    }
+
+    fn parameter(&mut self) -> Option<SyntaxNode> {
+        if !matches!(self.peek(), TokenKind::Identifier(_)) {
+            return None;
+        }
+        let mut children = vec![];
+        self.bump(&mut children);
+        self.eat(&mut children, |kind| *kind == TokenKind::Colon);
+        self.eat(&mut children, |kind| matches!(kind, TokenKind::Type(_)));
+        Some(node(SyntaxKind::Parameter, children))
+    }
+
+    /// Elements separated by commas, between the current token and `close`
+    fn list(
+        &mut self,
+        kind: SyntaxKind,
+        close: TokenKind,
+        element: fn(&mut Self) -> Option<SyntaxNode>,
+    ) -> SyntaxNode {
+        let mut children = vec![];
+        self.bump(&mut children);
+        loop {
+            if *self.peek() == close {
+                self.bump(&mut children);
+                break;
+            }
+            if *self.peek() == TokenKind::Comma {
+                self.bump(&mut children);
+                continue;
+            }
+            if self.at_item_end() {
+                break;
+            }
+            let element = match element(self) {
+                Some(element) => element,
+                None => self.error(),
+            };
+            children.push(SyntaxElement::Node(element));
+        }
+        node(kind, children)
+    }
+}
+
+fn node(kind: SyntaxKind, children: Vec<SyntaxElement>) -> SyntaxNode {
+    SyntaxNode { kind, children }
+}
+
+type Children<'a> = Peekable<Iter<'a, SyntaxElement>>;
+
+/// Derives the AST from the tree, see [SyntaxNode::to_items]. The tokens are visited in source
+/// order, so that an error can name the token that was found instead of the expected one.
+struct Deriver<'a> {
+    /// Every token of the tree, ending with the end of file
+    tokens: Vec<&'a SyntaxToken>,
+    /// How many of the tokens have been visited
+    position: usize,
+}
+
+impl<'a> Deriver<'a> {
+    /// The first token that has not been visited
+    fn found(&self) -> &'a Token {
+        &self.tokens[self.position.min(self.tokens.len() - 1)].token
+    }
+
+    fn unexpected(&self) -> ParserError {
+        let found = self.found();
+        ParserError {
+            message: format!("Unexpected token: {:?}", found.kind),
+            token: Some(found.clone()),
+        }
+    }
+
+    fn expected(&self, expected: &str) -> ParserError {
+        let found = self.found();
+        ParserError {
+            message: format!("Expected {}, got {:?}", expected, found.kind),
+            token: Some(found.clone()),
+        }
+    }
+
+    /// Visits the next child if it is a token that matches
+    fn token(
+        &mut self,
+        children: &mut Children<'a>,
+        matches: fn(&TokenKind) -> bool,
+    ) -> Option<&'a SyntaxToken> {
+        match children.peek().copied() {
+            Some(SyntaxElement::Token(token)) if matches(token.kind()) => {
+                children.next();
+                self.position += 1;
+                Some(token)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(
+        &mut self,
+        children: &mut Children<'a>,
+        expected: &str,
+        matches: fn(&TokenKind) -> bool,
+    ) -> Result<&'a SyntaxToken, ParserError> {
+        self.token(children, matches)
+            .ok_or_else(|| self.expected(expected))
+    }
+
+    /// Takes the next child if it is a node
+    fn node(children: &mut Children<'a>) -> Option<&'a SyntaxNode> {
+        match children.peek().copied() {
+            Some(SyntaxElement::Node(node)) => {
+                children.next();
+                Some(node)
+            }
+            _ => None,
+        }
+    }
+
+    fn identifier(
+        &mut self,
+        children: &mut Children<'a>,
+    ) -> Result<(String, Location), ParserError> {
+        let token = self.expect(children, "identifier", |kind| {
+            matches!(kind, TokenKind::Identifier(_))
+        })?;
+        match token.kind() {
+            TokenKind::Identifier(name) => Ok((
+                name.clone(),
+                Location::new(token.token.begin, token.token.end),
+            )),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// The type and where it ends
+    fn type_(&mut self, children: &mut Children<'a>) -> Result<(Type, usize), ParserError> {
+        let token = self.expect(children, "type", |kind| matches!(kind, TokenKind::Type(_)))?;
+        let name = match token.kind() {
+            TokenKind::Type(name) => name,
+            _ => return Err(self.unexpected()),
+        };
+        match parse_type(name) {
+            Some(type_) => Ok((type_, token.token.end)),
+            None => Err(ParserError {
+                message: format!("Unknown type: {:?}", name),
+                token: Some(token.token.clone()),
+            }),
+        }
+    }
+
+    fn program(&mut self, program: &'a SyntaxNode) -> (Vec<Item>, Vec<ParserError>) {
+        let mut items = vec![];
+        let mut errors = vec![];
+        // The tokens that fit no item right after an item with an error are part of that error,
+        // up to a semicolon
+        let mut failed = false;
+        for node in program.nodes() {
+            let tokens = node.tokens();
+            let end = self.position + tokens.len();
+            if failed && node.kind == SyntaxKind::Error {
+                failed = *tokens[0].kind() != TokenKind::Semicolon;
+            } else {
+                match self.item(node) {
+                    Ok(item) => {
+                        failed = false;
+                        items.push(item);
+                    }
+                    Err(error) => {
+                        failed = true;
+                        errors.push(error);
+                        items.push(Item::Error);
+                    }
+                }
+            }
+            self.position = end;
+        }
+        (items, errors)
+    }
+
+    fn item(&mut self, node: &'a SyntaxNode) -> Result<Item, ParserError> {
+        let mut children = node.children.iter().peekable();
+        match node.kind {
+            SyntaxKind::Declaration => {
+                let let_ = self.expect(&mut children, "let", |kind| *kind == TokenKind::Let)?;
+                let (name, _) = self.identifier(&mut children)?;
+                self.expect(&mut children, "colon", |kind| *kind == TokenKind::Colon)?;
+                let (type_, _) = self.type_(&mut children)?;
+                self.expect(&mut children, "assignment", |kind| {
+                    *kind == TokenKind::Assignment
+                })?;
+                let expression = self.child(&mut children)?;
+                let semicolon = self.expect(&mut children, "semicolon", |kind| {
+                    *kind == TokenKind::Semicolon
+                })?;
+                let doc: Vec<&str> = let_
+                    .leading
+                    .iter()
+                    .filter_map(
+                        |trivia| match trivia.token.as_ref().map(|token| &token.kind) {
+                            Some(TokenKind::DocComment(line)) => Some(line.as_str()),
+                            _ => None,
+                        },
+                    )
+                    .collect();
+                Ok(Item::Declaration(Declaration {
+                    name,
+                    type_,
+                    expression: Box::new(expression),
+                    location: Location::new(let_.token.begin, semicolon.token.end),
+                    doc: (!doc.is_empty()).then(|| doc.join("\n")),
+                }))
+            }
+            SyntaxKind::ExpressionItem => {
+                let expression = self.child(&mut children)?;
+                self.expect(&mut children, "semicolon", |kind| {
+                    *kind == TokenKind::Semicolon
+                })?;
+                Ok(Item::Expression(expression))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// An expression that has to be there
+    fn child(&mut self, children: &mut Children<'a>) -> Result<Expression, ParserError> {
+        match Self::node(children) {
+            Some(node) => self.expression(node),
+            None => Err(self.unexpected()),
+        }
+    }
+
+    fn expression(&mut self, node: &'a SyntaxNode) -> Result<Expression, ParserError> {
+        let mut children = node.children.iter().peekable();
+        match node.kind {
+            SyntaxKind::Literal => match self.token(&mut children, |_| true).map(SyntaxToken::kind)
+            {
+                Some(TokenKind::Number(value, _)) => Ok(Expression::Number(*value)),
+                Some(TokenKind::Character(value)) => Ok(Expression::Char(*value)),
+                Some(TokenKind::Boolean(value)) => Ok(Expression::Bool(*value)),
+                Some(TokenKind::String(value)) => Ok(Expression::Array(
+                    Type::Char,
+                    value.bytes().map(Expression::Char).collect(),
+                )),
+                _ => Err(self.unexpected()),
+            },
+            SyntaxKind::Name => {
+                let (name, location) = self.identifier(&mut children)?;
+                Ok(Expression::Identifier(name, location))
+            }
+            // Only numbers can be negated
+            SyntaxKind::Negation => {
+                self.token(&mut children, |kind| *kind == TokenKind::Subtraction);
+                let number = Self::node(&mut children)
+                    .filter(|operand| operand.kind == SyntaxKind::Literal)
+                    .and_then(|operand| {
+                        let mut children = operand.children.iter().peekable();
+                        self.token(&mut children, |kind| matches!(kind, TokenKind::Number(..)))
+                    });
+                match number.map(SyntaxToken::kind) {
+                    Some(TokenKind::Number(value, _)) => Ok(Expression::Number(-value)),
+                    _ => Err(self.expected("number")),
+                }
+            }
+            SyntaxKind::Binary => {
+                let left = self.child(&mut children)?;
+                let operator = self.token(&mut children, |_| true);
+                let (operator, location) = match operator.and_then(binary_operator) {
+                    Some(operator) => operator,
+                    None => return Err(self.unexpected()),
+                };
+                let right = self.child(&mut children)?;
+                Ok(Expression::BinaryOperation(BinaryOperation {
+                    operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    location,
+                }))
+            }
+            // The parser takes a group that does not close for a function, and gives the error
+            // of the parameters
+            SyntaxKind::Group => {
+                let begin = self.position;
+                self.token(&mut children, |kind| *kind == TokenKind::LeftParen);
+                let group = self.child(&mut children).and_then(|expression| {
+                    self.expect(&mut children, "right parenthesis", |kind| {
+                        *kind == TokenKind::RightParen
+                    })?;
+                    Ok(expression)
+                });
+                group.map_err(|_| {
+                    self.position = begin + 1;
+                    if let TokenKind::Identifier(_) = self.found().kind {
+                        self.position += 1;
+                        return self.expected("colon");
+                    }
+                    self.expected("identifier")
+                })
+            }
+            SyntaxKind::Function => {
+                let node = match Self::node(&mut children) {
+                    Some(node) => node,
+                    None => return Err(self.unexpected()),
+                };
+                let (parameters, _) = self.list(node, Self::parameter, |deriver| {
+                    deriver.expected("identifier")
+                })?;
+                self.expect(&mut children, "colon", |kind| *kind == TokenKind::Colon)?;
+                let (return_type, _) = self.type_(&mut children)?;
+                let arrow =
+                    self.expect(&mut children, "arrow", |kind| *kind == TokenKind::Arrow)?;
+                let expression = self.child(&mut children)?;
+                Ok(Expression::FunctionDeclaration(FunctionDeclaration {
+                    parameters,
+                    return_type,
+                    expression: Box::new(expression),
+                    location: Location::new(opening(node), arrow.token.end),
+                }))
+            }
+            SyntaxKind::Call => {
+                let (name, location) = self.identifier(&mut children)?;
+                let (arguments, end) = match Self::node(&mut children) {
+                    Some(arguments) => self.list(arguments, Self::expression, Self::unexpected)?,
+                    None => return Err(self.unexpected()),
+                };
+                Ok(Expression::FunctionCall(FunctionCall {
+                    name,
+                    arguments,
+                    tail: false,
+                    location: Location::new(location.begin, end),
+                }))
+            }
+            SyntaxKind::Array => {
+                let (elements, _) = self.list(node, Self::expression, Self::unexpected)?;
+                match elements.first() {
+                    Some(first) => Ok(Expression::Array(array_type(first), elements)),
+                    None => {
+                        let begin = opening(node);
+                        Err(ParserError {
+                            message: "Empty array, cannot infer array type".to_string(),
+                            token: Some(Token::new(TokenKind::LeftBracket, begin, begin)),
+                        })
+                    }
+                }
+            }
+            SyntaxKind::Match => self.match_(children),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn parameter(&mut self, node: &'a SyntaxNode) -> Result<FunctionParameter, ParserError> {
+        if node.kind != SyntaxKind::Parameter {
+            return Err(self.expected("identifier"));
+        }
+        let mut children = node.children.iter().peekable();
+        let (name, location) = self.identifier(&mut children)?;
+        self.expect(&mut children, "colon", |kind| *kind == TokenKind::Colon)?;
+        let (type_, end) = self.type_(&mut children)?;
+        Ok(FunctionParameter {
+            name,
+            type_,
+            location: Location::new(location.begin, end),
+        })
+    }
+
+    /// The elements of a list and where it ends. `missing` is the error when the list is not
+    /// closed.
+    fn list<T>(
+        &mut self,
+        node: &'a SyntaxNode,
+        element: fn(&mut Self, &'a SyntaxNode) -> Result<T, ParserError>,
+        missing: fn(&Self) -> ParserError,
+    ) -> Result<(Vec<T>, usize), ParserError> {
+        let mut children = node.children.iter().peekable();
+        self.token(&mut children, |_| true);
+        let mut elements = vec![];
+        loop {
+            let close = self.token(&mut children, |kind| {
+                matches!(kind, TokenKind::RightParen | TokenKind::RightBracket)
+            });
+            if let Some(close) = close {
+                return Ok((elements, close.token.end));
+            }
+            if self
+                .token(&mut children, |kind| *kind == TokenKind::Comma)
+                .is_some()
+            {
+                continue;
+            }
+            match Self::node(&mut children) {
+                Some(node) => elements.push(element(self, node)?),
+                None => return Err(missing(self)),
+            }
+        }
+    }
+
+    /// The children of a match, from its value
+    fn match_(&mut self, mut children: Children<'a>) -> Result<Expression, ParserError> {
+        let value = self.child(&mut children)?;
+        let begin = match self.token(&mut children, |kind| *kind == TokenKind::Question) {
+            Some(question) => question.token.begin,
+            None => return Err(self.unexpected()),
+        };
+        self.expect(&mut children, "left brace", |kind| {
+            *kind == TokenKind::LeftBrace
+        })?;
+        let mut patterns = vec![];
+        let mut default = None;
+        let end = loop {
+            if let Some(brace) = self.token(&mut children, |kind| *kind == TokenKind::RightBrace) {
+                break brace.token.end;
+            }
+            if self
+                .token(&mut children, |kind| *kind == TokenKind::Comma)
+                .is_some()
+            {
+                continue;
+            }
+            match Self::node(&mut children) {
+                Some(arm) if arm.kind == SyntaxKind::MatchArm => {
+                    let mut arm = arm.children.iter().peekable();
+                    let pattern = self.child(&mut arm)?;
+                    self.token(&mut arm, |kind| *kind == TokenKind::Arrow);
+                    patterns.push((pattern, self.child(&mut arm)?));
+                }
+                // An arm without a pattern has to be the last one
+                Some(arm) if arm.kind == SyntaxKind::Default => {
+                    default = Some(Box::new(self.child(&mut arm.children.iter().peekable())?));
+                    if !matches!(
+                        children.peek(),
+                        Some(SyntaxElement::Token(token)) if *token.kind() == TokenKind::RightBrace
+                    ) {
+                        return Err(self.expected("arrow or default"));
+                    }
+                }
+                _ => return Err(self.unexpected()),
+            }
+        };
+        Ok(Expression::Match(Match {
+            value: Box::new(value),
+            patterns,
+            default,
+            location: Location::new(begin, end),
+        }))
+    }
+}
+
+/// Where a node that starts with a token, like a list, begins
+fn opening(node: &SyntaxNode) -> usize {
+    match node.children.first() {
+        Some(SyntaxElement::Token(token)) => token.token.begin,
+        _ => 0,
+    }
+}
+
+fn binary_operator(token: &SyntaxToken) -> Option<(BinaryOperator, Location)> {
+    let operator = match token.kind() {
+        TokenKind::Equal => BinaryOperator::Equal,
+        TokenKind::NotEqual => BinaryOperator::NotEqual,
+        TokenKind::LessThan => BinaryOperator::LessThan,
+        TokenKind::LessEqualThan => BinaryOperator::LessEqualThan,
+        TokenKind::GreaterThan => BinaryOperator::GreaterThan,
+        TokenKind::GreaterEqualThan => BinaryOperator::GreaterEqualThan,
+        TokenKind::Addition => BinaryOperator::Add,
+        TokenKind::Subtraction => BinaryOperator::Subtract,
+        TokenKind::Multiplication => BinaryOperator::Multiply,
+        TokenKind::Division => BinaryOperator::Divide,
+        TokenKind::Modulus => BinaryOperator::Modulo,
+        _ => return None,
+    };
+    Some((operator, Location::new(token.token.begin, token.token.end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(node: &SyntaxNode) -> Vec<SyntaxKind> {
+        node.nodes().map(|node| node.kind).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = "  # leading\nlet x:num = 1 #[ block ]# +2;  # trailing\n\n## doc\nlet y:bool = x<=1 ? {\n\ttrue => 'a',\n\tfalse\n};\nlet é:[char] = \"ß\"; ";
+        assert_eq!(parse(source).unwrap().to_string(), source);
+    }
+
+    #[test]
+    fn test_empty_type() {
+        // The lexer gives a missing type an empty token at the next character
+        for source in ["let x: = 3;", "let x:;", "(a:, b:num):num => a;"] {
+            assert_eq!(parse(source).unwrap().to_string(), source);
+        }
+    }
+
+    #[test]
+    fn test_trivia() {
+        let tree = parse("# first\nlet x:num = 1; # same line\n## doc\nlet y:num = 2;").unwrap();
+        let tokens = tree.tokens();
+        assert_eq!(tokens[0].text, "let");
+        assert_eq!(tokens[0].leading[0].text, "# first");
+        assert_eq!(tokens[0].leading[1].text, "\n");
+        // The semicolon of the first declaration keeps the comment on its line
+        let semicolon = tokens[6];
+        assert_eq!(semicolon.text, ";");
+        let trailing: Vec<&str> = semicolon.trailing.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(trailing, vec![" ", "# same line"]);
+        let leading: Vec<&str> = tokens[7].leading.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(leading, vec!["\n", "## doc", "\n"]);
+        assert_eq!(*tokens.last().unwrap().kind(), TokenKind::EOF);
+    }
+
+    #[test]
+    fn test_structure() {
+        let tree =
+            parse("let f:fn = (a:num, b:num):num => a + b * 2;\nf(1, -2) ? { 3 => [1], 4 };")
+                .unwrap();
+        assert_eq!(
+            kinds(&tree),
+            vec![SyntaxKind::Declaration, SyntaxKind::ExpressionItem]
+        );
+        let function = tree.nodes().next().unwrap().nodes().next().unwrap();
+        assert_eq!(function.kind, SyntaxKind::Function);
+        assert_eq!(
+            kinds(function),
+            vec![SyntaxKind::Parameters, SyntaxKind::Binary]
+        );
+        let sum = function.nodes().nth(1).unwrap();
+        assert_eq!(kinds(sum), vec![SyntaxKind::Name, SyntaxKind::Binary]);
+
+        let match_ = tree.nodes().nth(1).unwrap().nodes().next().unwrap();
+        assert_eq!(
+            kinds(match_),
+            vec![SyntaxKind::Call, SyntaxKind::MatchArm, SyntaxKind::Default]
+        );
+        let arguments = match_.nodes().next().unwrap().nodes().next().unwrap();
+        assert_eq!(
+            kinds(arguments),
+            vec![SyntaxKind::Literal, SyntaxKind::Negation]
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        let tree = parse("(1 + 2) * 3;").unwrap();
+        let product = tree.nodes().next().unwrap().nodes().next().unwrap();
+        assert_eq!(kinds(product), vec![SyntaxKind::Group, SyntaxKind::Literal]);
+    }
+
+    #[test]
+    fn test_errors_are_kept() {
+        let source = "let x:num = ) 1;\n) let y = [1, ;";
+        let tree = parse(source).unwrap();
+        assert_eq!(tree.to_string(), source);
+        assert_eq!(
+            kinds(&tree),
+            vec![
+                SyntaxKind::Declaration,
+                SyntaxKind::Error,
+                SyntaxKind::ExpressionItem,
+                SyntaxKind::Error,
+                SyntaxKind::Declaration,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_items() {
+        let source = "## Doubles\nlet double:fn = (x:num):num => x * 2; # comment\ndouble(2);";
+        let tokens = tokenize(source).unwrap();
+        let (items, errors) = parse(source).unwrap().to_items();
+        assert!(errors.is_empty());
+        assert_eq!(items, crate::parser::parse(&tokens).unwrap());
+        match &items[0] {
+            Item::Declaration(declaration) => {
+                assert_eq!(declaration.doc, Some("Doubles".to_string()))
+            }
+            item => panic!("Expected a declaration, got {:?}", item),
+        }
+    }
+
+    #[test]
+    fn test_to_items_errors() {
+        let (items, errors) = parse("let a:num = ;\nprint(;\nprint(a);")
+            .unwrap()
+            .to_items();
+        let errors: Vec<(String, usize)> = errors
+            .into_iter()
+            .map(|error| (error.message, error.token.unwrap().begin))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("Unexpected token: Semicolon".to_string(), 12),
+                ("Unexpected token: Semicolon".to_string(), 20),
+            ]
+        );
+        assert_eq!(items[..2], [Item::Error, Item::Error]);
+        assert!(matches!(items[2], Item::Expression(..)));
+
+        // The tokens that fit no item after an error are part of it
+        let (items, errors) = parse("let x:num = 1 ) ) ;\n) 2;").unwrap().to_items();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "Expected semicolon, got RightParen");
+        assert_eq!(errors[1].message, "Unexpected token: RightParen");
+        assert_eq!(errors[1].token.as_ref().unwrap().begin, 20);
+        assert_eq!(items.len(), 3);
+
+        let (_, errors) = parse("f(-x, [], (a:num) => a);").unwrap().to_items();
+        assert_eq!(errors[0].message, "Expected number, got Identifier(\"x\")");
+        let (_, errors) = parse("x ? { 1, 2 => 3 };").unwrap().to_items();
+        assert_eq!(errors[0].message, "Expected arrow or default, got Comma");
+        let (_, errors) = parse("let x:int = [];").unwrap().to_items();
+        assert_eq!(errors[0].message, "Unknown type: \"int\"");
+    }
+}
@@ -0,0 +1,155 @@
+//! Tail-position analysis.
+//!
+//! A function call is in tail position when its result is also the result of the function it
+//! appears in. That is the case for the body of a function and, if the body is a match, for every
+//! arm and the default of that match (recursively). The virtual machine does not need to keep the
+//! frame of the caller around for such calls, so it reuses it instead. This makes any tail call,
+//! including mutually recursive ones, run without growing the call stack.
+
+use crate::parser::{Expression, Item};
+
+/// Marks every function call in the program that is in tail position.
+pub fn mark_tail_calls(items: &mut [Item]) {
+    for item in items {
+        match item {
+            Item::Declaration(declaration) => mark_expression(&mut declaration.expression, false),
+            Item::Expression(expression) => mark_expression(expression, false),
+            Item::Error => {}
+        }
+    }
+}
+
+/// Marks the function calls in the expression. `tail` tells whether the expression itself is in
+/// tail position.
+pub fn mark_expression(expression: &mut Expression, tail: bool) {
+    match expression {
+        Expression::Identifier(..)
+        | Expression::Number(..)
+        | Expression::Char(..)
+        | Expression::Bool(..) => {}
+        Expression::Array(_, elements) => {
+            for element in elements {
+                mark_expression(element, false);
+            }
+        }
+        Expression::FunctionDeclaration(function) => {
+            mark_expression(&mut function.expression, true)
+        }
+        Expression::FunctionCall(function_call) => {
+            function_call.tail = tail;
+            for argument in &mut function_call.arguments {
+                mark_expression(argument, false);
+            }
+        }
+        Expression::Match(match_) => {
+            mark_expression(&mut match_.value, false);
+            for (pattern, arm) in &mut match_.patterns {
+                mark_expression(pattern, false);
+                mark_expression(arm, tail);
+            }
+            if let Some(default) = &mut match_.default {
+                mark_expression(default, tail);
+            }
+        }
+        Expression::BinaryOperation(binary) => {
+            mark_expression(&mut binary.left, false);
+            mark_expression(&mut binary.right, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::tokenize;
+    use crate::parser::{parse, FunctionCall};
+
+    use super::*;
+
+    /// Collects the name and tail flag of every call in the expression, in source order
+    fn calls(expression: &Expression, result: &mut Vec<(String, bool)>) {
+        match expression {
+            Expression::Array(_, elements) => elements.iter().for_each(|e| calls(e, result)),
+            Expression::FunctionDeclaration(function) => calls(&function.expression, result),
+            Expression::FunctionCall(FunctionCall {
+                name,
+                arguments,
+                tail,
+                ..
+            }) => {
+                result.push((name.clone(), *tail));
+                arguments.iter().for_each(|e| calls(e, result));
+            }
+            Expression::Match(match_) => {
+                calls(&match_.value, result);
+                for (pattern, arm) in &match_.patterns {
+                    calls(pattern, result);
+                    calls(arm, result);
+                }
+                if let Some(default) = &match_.default {
+                    calls(default, result);
+                }
+            }
+            Expression::BinaryOperation(binary) => {
+                calls(&binary.left, result);
+                calls(&binary.right, result);
+            }
+            _ => {}
+        }
+    }
+
+    fn marked_calls(code: &str) -> Vec<(String, bool)> {
+        let mut items = parse(&tokenize(code).unwrap()).unwrap();
+        mark_tail_calls(&mut items);
+        let mut result = vec![];
+        for item in &items {
+            match item {
+                Item::Declaration(declaration) => calls(&declaration.expression, &mut result),
+                Item::Expression(expression) => calls(expression, &mut result),
+                Item::Error => {}
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_function_body() {
+        assert_eq!(
+            marked_calls("let f:fn = (x:num):num => g(h(x));"),
+            vec![("g".to_string(), true), ("h".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn test_match_arms() {
+        assert_eq!(
+            marked_calls(
+                "let f:fn = (x:num):num => g(x) ? {
+    h(0) => i(x),
+    j(x)
+};"
+            ),
+            vec![
+                ("g".to_string(), false),
+                ("h".to_string(), false),
+                ("i".to_string(), true),
+                ("j".to_string(), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binary_operation() {
+        assert_eq!(
+            marked_calls("let f:fn = (x:num):num => x * f(x - 1);"),
+            vec![("f".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn test_top_level() {
+        assert_eq!(
+            marked_calls("let x:num = f(1); g(x);"),
+            vec![("f".to_string(), false), ("g".to_string(), false)]
+        );
+    }
+}
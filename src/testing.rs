@@ -0,0 +1,119 @@
+//! Tests written in nino, see `ninoi test`. A test is a function declared at the top level whose
+//! name starts with `test_` and that takes no parameters. It fails when it stops the program with
+//! an error, usually from `assert` or `assert_eq`.
+//!
+//! Every test runs in a virtual machine of its own, after the declarations of its file. The
+//! expressions at the top level are what the file does when it is run, so they are left out.
+
+use crate::parser::{Expression, FunctionCall, Item};
+use crate::virtual_machine::{RuntimeError, VirtualMachine};
+
+/// The prefix of the names of the tests
+pub const PREFIX: &str = "test_";
+
+#[derive(Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// What the test printed
+    pub output: String,
+    pub error: Option<RuntimeError>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The names of the tests of a program, in the order they are declared
+pub fn tests(program: &[Item]) -> Vec<String> {
+    program
+        .iter()
+        .filter_map(|item| match item {
+            Item::Declaration(declaration) if declaration.name.starts_with(PREFIX) => {
+                match declaration.expression.as_ref() {
+                    Expression::FunctionDeclaration(function) if function.parameters.is_empty() => {
+                        Some(declaration.name.clone())
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs the declarations of the program, then calls the test
+pub fn run_test(program: &[Item], name: &str) -> TestResult {
+    let mut items: Vec<Item> = vec![];
+    let mut location = None;
+    for item in program {
+        if let Item::Declaration(declaration) = item {
+            if declaration.name == name {
+                location = Some(declaration.location);
+            }
+            items.push(Item::Declaration(declaration.clone()));
+        }
+    }
+    items.push(Item::Expression(Expression::FunctionCall(FunctionCall {
+        name: name.to_string(),
+        arguments: vec![],
+        tail: false,
+        location: location.unwrap_or_default(),
+    })));
+
+    let mut output = vec![];
+    let result = {
+        let mut vm = VirtualMachine::new();
+        vm.set_output(&mut output);
+        vm.run(items)
+    };
+    TestResult {
+        name: name.to_string(),
+        output: String::from_utf8_lossy(&output).to_string(),
+        error: result.err(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    const SOURCE: &str = "let add:fn = (a:num, b:num):num => a + b;
+let test_add:fn = ():bool => assert_eq(add(1, 2), 3);
+let test_print:fn = ():bool => assert(print(\"hi\") == \"hi\");
+let test_wrong:fn = ():num => assert_eq(print([add(1, 1), 3]), [2, 4]);
+let test_not_a_test:fn = (x:num):num => x;
+print(\"not run\");";
+
+    #[test]
+    fn test_discovery() {
+        let items = parse(&tokenize(SOURCE).unwrap()).unwrap();
+        assert_eq!(tests(&items), vec!["test_add", "test_print", "test_wrong"]);
+    }
+
+    #[test]
+    fn test_results() {
+        let items = parse(&tokenize(SOURCE).unwrap()).unwrap();
+        let add = run_test(&items, "test_add");
+        assert!(add.passed());
+        assert_eq!(add.output, "");
+
+        let print = run_test(&items, "test_print");
+        assert!(print.passed());
+        assert_eq!(print.output, "hi\n");
+
+        let wrong = run_test(&items, "test_wrong");
+        assert_eq!(wrong.output, "[2, 3]\n");
+        let error = wrong.error.unwrap();
+        assert_eq!(
+            error.message,
+            "The values are not equal\nleft:  [2, 3]\nright: [2, 4]"
+        );
+        // The error points at the assertion
+        let span = error.span.unwrap();
+        assert!(SOURCE[span.begin..].starts_with("assert_eq(print"));
+    }
+}
@@ -1,95 +1,236 @@
-use core::panic;
+use std::io::Write;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::parser::Expression;
+use super::value::Value;
 
-pub fn print(expression: Expression, end: &str) -> Expression {
-    match &expression {
-        Expression::Char(val) => print!("{}{}", *val as char, end),
-        Expression::Number(val) => print!("{}{}", val, end),
-        Expression::Bool(val) => print!("{}{}", val, end),
-        Expression::Array(type_, val) => {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Builtin {
+    Print,
+    DebugPrint,
+    Time,
+    Sqrt,
+    Head,
+    Last,
+    Tail,
+    Len,
+    Assert,
+    AssertEq,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 10] = [
+        Builtin::Print,
+        Builtin::DebugPrint,
+        Builtin::Time,
+        Builtin::Sqrt,
+        Builtin::Head,
+        Builtin::Last,
+        Builtin::Tail,
+        Builtin::Len,
+        Builtin::Assert,
+        Builtin::AssertEq,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Builtin::ALL
+            .into_iter()
+            .find(|builtin| builtin.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Print => "print",
+            Builtin::DebugPrint => "debug_print",
+            Builtin::Time => "time",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Head => "head",
+            Builtin::Last => "last",
+            Builtin::Tail => "tail",
+            Builtin::Len => "len",
+            Builtin::Assert => "assert",
+            Builtin::AssertEq => "assert_eq",
+        }
+    }
+
+    /// What the builtin does, shown by the editor tools
+    pub fn description(self) -> &'static str {
+        match self {
+            Builtin::Print => "Prints a value and returns it",
+            Builtin::DebugPrint => "Prints the internal representation of a value and returns it",
+            Builtin::Time => "The number of milliseconds since the Unix epoch",
+            Builtin::Sqrt => "The square root of a number",
+            Builtin::Head => "The first element of an array, false if it is empty",
+            Builtin::Last => "The last element of an array, false if it is empty",
+            Builtin::Tail => "The array without its first element",
+            Builtin::Len => "The number of elements in an array",
+            Builtin::Assert => "Stops the program if the value is false, returns it otherwise",
+            Builtin::AssertEq => {
+                "Stops the program if the two values are not equal, returns the first one otherwise"
+            }
+        }
+    }
+
+    /// The number of arguments the builtin takes
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Time => 0,
+            Builtin::AssertEq => 2,
+            _ => 1,
+        }
+    }
+
+    /// Calls the builtin, printing to the output
+    pub fn call(self, mut arguments: Vec<Value>, output: &mut dyn Write) -> Result<Value, String> {
+        if arguments.len() != self.arity() {
+            return Err(format!(
+                "Expected {} arguments, got {}",
+                self.arity(),
+                arguments.len()
+            ));
+        }
+        match self {
+            Builtin::Print => {
+                let mut text = String::new();
+                let value = print(arguments.remove(0), "\n", &mut text)?;
+                write_output(output, &text)?;
+                Ok(value)
+            }
+            Builtin::DebugPrint => {
+                let value = arguments.remove(0);
+                write_output(output, &format!("{:#?}\n", value))?;
+                Ok(value)
+            }
+            Builtin::Time => Ok(time()),
+            Builtin::Sqrt => match arguments[0] {
+                Value::Number(val) => Ok(Value::Number(val.sqrt())),
+                ref value => Err(format!("Cannot take square root of {:?}", value)),
+            },
+            Builtin::Head => head(&arguments[0]).cloned(),
+            Builtin::Last => last(&arguments[0]).cloned(),
+            Builtin::Tail => tail(&arguments[0]),
+            Builtin::Len => len(&arguments[0]),
+            Builtin::Assert => assert(arguments.remove(0)),
+            Builtin::AssertEq => {
+                let right = arguments.pop().unwrap();
+                assert_eq(arguments.pop().unwrap(), right)
+            }
+        }
+    }
+}
+
+fn write_output(output: &mut dyn Write, text: &str) -> Result<(), String> {
+    output
+        .write_all(text.as_bytes())
+        .map_err(|error| format!("Cannot print: {}", error))
+}
+
+/// Appends the value to the text, the way `print` shows it
+pub fn print(value: Value, end: &str, text: &mut String) -> Result<Value, String> {
+    match &value {
+        Value::Char(val) => text.push_str(&format!("{}{}", String::from_utf8_lossy(&[*val]), end)),
+        Value::Number(val) => text.push_str(&format!("{}{}", val, end)),
+        Value::Bool(val) => text.push_str(&format!("{}{}", val, end)),
+        Value::Array(type_, val) => {
             let is_string = type_ == &crate::parser::Type::Char;
 
             if is_string {
-                let collect: String = val
+                // Strings are UTF-8 encoded
+                let bytes = val
                     .iter()
                     .map(|x| match x {
-                        Expression::Char(val) => *val as char,
-                        _ => panic!("Cannot convert {:?} to string", x),
+                        Value::Char(val) => Ok(*val),
+                        _ => Err(format!("Cannot convert {:?} to string", x)),
                     })
-                    .collect();
-                print!("{}", collect);
+                    .collect::<Result<Vec<u8>, String>>()?;
+                text.push_str(&String::from_utf8_lossy(&bytes));
             } else {
-                print!("[");
+                text.push('[');
                 for (i, item) in val.iter().enumerate() {
                     if i != 0 && !is_string {
-                        print!(", ");
+                        text.push_str(", ");
                     }
-                    print(item.clone(), "");
+                    print(item.clone(), "", text)?;
                 }
-                print!("]");
+                text.push(']');
             }
-            print!("{}", end);
+            text.push_str(end);
         }
-        _ => print!("{:?}", expression),
+        _ => text.push_str(&format!("{:?}", value)),
     }
-    return expression;
+    Ok(value)
 }
 
-pub fn debug_print(expression: Expression) -> Expression {
-    println!("{:#?}", expression);
-    return expression;
-}
-
-pub fn time() -> Expression {
+pub fn time() -> Value {
     let start = SystemTime::now();
     let since_the_epoch = start
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards");
     let in_ms = since_the_epoch.as_millis();
-    return Expression::Number(in_ms as f64);
+    Value::Number(in_ms as f64)
 }
 
-pub fn head(expression: &Expression) -> &Expression {
-    let array = match expression {
-        Expression::Array(.., array) => array,
-        _ => panic!("Cannot take head of {:?}", expression),
+pub fn head(value: &Value) -> Result<&Value, String> {
+    let array = match value {
+        Value::Array(.., array) => array,
+        _ => return Err(format!("Cannot take head of {:?}", value)),
     };
 
     match array.first() {
-        Some(expression) => expression,
-        None => &Expression::Bool(false),
+        Some(value) => Ok(value),
+        None => Ok(&Value::Bool(false)),
     }
 }
 
-pub fn tail(expression: &Expression) -> Expression {
-    let (type_, array) = match expression {
-        Expression::Array(type_, array) => (type_, array),
-        _ => panic!("Cannot take tail of {:?}", expression),
+pub fn tail(value: &Value) -> Result<Value, String> {
+    let (type_, array) = match value {
+        Value::Array(type_, array) => (type_, array),
+        _ => return Err(format!("Cannot take tail of {:?}", value)),
     };
     let mut array = array.clone();
-    if array.len() > 0 {
+    if !array.is_empty() {
         array.remove(0);
     }
-    Expression::Array(type_.clone(), array)
+    Ok(Value::Array(type_.clone(), array))
 }
 
-pub fn last(expression: &Expression) -> &Expression {
-    let array = match expression {
-        Expression::Array(.., array) => array,
-        _ => panic!("Cannot take last of {:?}", expression),
+pub fn last(value: &Value) -> Result<&Value, String> {
+    let array = match value {
+        Value::Array(.., array) => array,
+        _ => return Err(format!("Cannot take last of {:?}", value)),
     };
     match array.last() {
-        Some(expression) => expression,
-        None => &Expression::Bool(false),
+        Some(value) => Ok(value),
+        None => Ok(&Value::Bool(false)),
     }
 }
 
-pub fn len(expression: &Expression) -> Expression {
-    let array = match expression {
-        Expression::Array(.., array) => array,
-        _ => panic!("Cannot take first of {:?}", expression),
+pub fn len(value: &Value) -> Result<Value, String> {
+    let array = match value {
+        Value::Array(.., array) => array,
+        _ => return Err(format!("Cannot take length of {:?}", value)),
     };
-    Expression::Number(array.len() as f64)
+    Ok(Value::Number(array.len() as f64))
+}
+
+pub fn assert(value: Value) -> Result<Value, String> {
+    match value {
+        Value::Bool(true) => Ok(value),
+        Value::Bool(false) => Err("Assertion failed".to_string()),
+        value => Err(format!("Cannot assert {:?}, expected a bool", value)),
+    }
+}
+
+/// The values are shown like `print` shows them, on the lines after the message
+pub fn assert_eq(left: Value, right: Value) -> Result<Value, String> {
+    if left == right {
+        return Ok(left);
+    }
+    let mut left_text = String::new();
+    let mut right_text = String::new();
+    print(left, "", &mut left_text)?;
+    print(right, "", &mut right_text)?;
+    Err(format!(
+        "The values are not equal\nleft:  {}\nright: {}",
+        left_text, right_text
+    ))
 }
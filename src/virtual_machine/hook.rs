@@ -0,0 +1,130 @@
+//! Lets tools watch the virtual machine run: the debugger, the profiler and the coverage report.
+//!
+//! The virtual machine only keeps values and slots, so the [State] given to a hook turns them
+//! back into declarations and names when it is asked to, which costs nothing when nobody asks.
+
+use crate::lexer::Span;
+use crate::parser::Declaration;
+use crate::scoped_symbols::ScopedSymbols;
+
+use super::resolver::{NodeId, Program};
+use super::value::Value;
+
+/// Is told what the virtual machine does, see [VirtualMachine::run_with_hook](super::VirtualMachine::run_with_hook)
+pub trait Hook {
+    /// Before an expression that comes from the source is evaluated. Literals and arrays have no
+    /// location, so they are not reported. An error stops the program.
+    fn evaluate(&mut self, _state: &State) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Before a global declaration is evaluated, the state is at the whole declaration
+    fn declaration(&mut self, state: &State, _declaration: &Declaration) -> Result<(), String> {
+        self.evaluate(state)
+    }
+
+    /// After the arguments are bound to the parameters, before the body is evaluated. A tail call
+    /// replaces the frame of its caller, so the stack does not grow.
+    fn call(&mut self, _state: &State, _tail: bool) {}
+
+    /// When a function returns, before its frame is removed
+    fn return_(&mut self, _state: &State) {}
+
+    /// When a match takes an arm, before it is evaluated. The arms are counted from 0 in the
+    /// order of the patterns, the default arm being the last one. The state is at the match.
+    fn arm(&mut self, _state: &State, _arm: usize) {}
+}
+
+/// A function that has been called and has not returned yet
+pub(super) struct Call {
+    /// The index of the function in the [Program]
+    pub function: usize,
+    pub node: NodeId,
+}
+
+/// A frame of the call stack, for display
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    pub function: String,
+    /// Where the function was called. A tail call replaces the frame of its caller, so this stays
+    /// where the caller was called.
+    pub call: Option<Span>,
+    /// The parameters, bound to the values of the arguments
+    pub arguments: Vec<Declaration>,
+}
+
+/// What the virtual machine is doing when a hook is called
+pub struct State<'s> {
+    pub(super) program: &'s Program,
+    pub(super) frames: &'s [Vec<Value>],
+    pub(super) calls: &'s [Call],
+    pub(super) globals: &'s ScopedSymbols<'s>,
+    pub(super) span: Option<Span>,
+}
+
+impl<'s> State<'s> {
+    /// Where the virtual machine is in the source
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// How many functions have been called and have not returned
+    pub fn depth(&self) -> usize {
+        self.calls.len()
+    }
+
+    fn function_name(&self, function: usize) -> String {
+        match &self.program.functions[function].name {
+            Some(name) => name.clone(),
+            None => "<anonymous>".to_string(),
+        }
+    }
+
+    /// The name of the function that is running, if any
+    pub fn function(&self) -> Option<String> {
+        self.calls
+            .last()
+            .map(|call| self.function_name(call.function))
+    }
+
+    /// The frames of the call stack, the current function being the last one
+    pub fn stack(&self) -> Vec<StackFrame> {
+        self.calls
+            .iter()
+            .zip(self.frames)
+            .map(|(call, values)| {
+                let declaration = &self.program.functions[call.function].declaration;
+                let arguments = declaration
+                    .parameters
+                    .iter()
+                    .zip(values)
+                    .map(|(parameter, value)| Declaration {
+                        name: parameter.name.clone(),
+                        type_: parameter.type_.clone(),
+                        expression: Box::new(self.program.expression(value)),
+                        location: parameter.location,
+                        doc: None,
+                    })
+                    .collect();
+                StackFrame {
+                    function: self.function_name(call.function),
+                    call: self.program.spans[call.node],
+                    arguments,
+                }
+            })
+            .collect()
+    }
+
+    /// The symbols that can be used where the virtual machine is: the arguments of the functions
+    /// on the call stack, the innermost ones hiding the others, on top of the globals declared so
+    /// far
+    pub fn symbols(&self) -> ScopedSymbols<'s> {
+        let mut symbols = ScopedSymbols::with_parent(self.globals);
+        for frame in self.stack() {
+            for argument in frame.arguments {
+                symbols.insert(argument.name.clone(), argument);
+            }
+        }
+        symbols
+    }
+}
@@ -1,208 +1,752 @@
 mod builtins;
+mod hook;
+mod resolver;
+mod value;
 
+use std::io::{self, Write};
 use std::mem::discriminant;
 
+use crate::lexer::Span;
 use crate::parser::{BinaryOperator, Declaration, Expression, Item};
 use crate::scoped_symbols::ScopedSymbols;
+use crate::tail_calls::{mark_expression, mark_tail_calls};
 
-use self::builtins::{debug_print, head, last, len, print, tail, time};
-
-fn binary_float_float(left_val: f64, right_val: f64, operator: BinaryOperator) -> Expression {
-    match operator {
-        BinaryOperator::Add => Expression::Number(left_val + right_val),
-        BinaryOperator::Subtract => Expression::Number(left_val - right_val),
-        BinaryOperator::Multiply => Expression::Number(left_val * right_val),
-        BinaryOperator::Divide => Expression::Number(left_val / right_val),
-        BinaryOperator::Modulo => Expression::Number(left_val % right_val),
-        BinaryOperator::Equal => Expression::Bool(left_val == right_val),
-        BinaryOperator::NotEqual => Expression::Bool(left_val != right_val),
-        BinaryOperator::GreaterThan => Expression::Bool(left_val > right_val),
-        BinaryOperator::LessThan => Expression::Bool(left_val < right_val),
-        BinaryOperator::GreaterEqualThan => Expression::Bool(left_val >= right_val),
-        BinaryOperator::LessEqualThan => Expression::Bool(left_val <= right_val),
-        _ => unimplemented!("Unknown operator {:?}", operator),
+pub use self::builtins::Builtin;
+pub use self::hook::{Hook, StackFrame, State};
+
+use self::hook::Call;
+use self::resolver::{Node, NodeId, Program, Resolver, ResolverError, Slot};
+use self::value::Value;
+
+/// An error that stops the program
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    /// Where the error happened, if it is known
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
     }
 }
 
-fn evaluate(expression: Expression, symbols: &ScopedSymbols) -> Expression {
-    let mut current_expression = expression;
-    let mut current_symbols = ScopedSymbols::with_parent(&symbols);
+impl From<ResolverError> for RuntimeError {
+    fn from(error: ResolverError) -> Self {
+        RuntimeError {
+            message: error.message,
+            span: error.span,
+        }
+    }
+}
+
+fn binary_float_float(
+    left_val: f64,
+    right_val: f64,
+    operator: BinaryOperator,
+) -> Result<Value, String> {
+    Ok(match operator {
+        BinaryOperator::Add => Value::Number(left_val + right_val),
+        BinaryOperator::Subtract => Value::Number(left_val - right_val),
+        BinaryOperator::Multiply => Value::Number(left_val * right_val),
+        BinaryOperator::Divide => Value::Number(left_val / right_val),
+        BinaryOperator::Modulo => Value::Number(left_val % right_val),
+        BinaryOperator::Equal => Value::Bool(left_val == right_val),
+        BinaryOperator::NotEqual => Value::Bool(left_val != right_val),
+        BinaryOperator::GreaterThan => Value::Bool(left_val > right_val),
+        BinaryOperator::LessThan => Value::Bool(left_val < right_val),
+        BinaryOperator::GreaterEqualThan => Value::Bool(left_val >= right_val),
+        BinaryOperator::LessEqualThan => Value::Bool(left_val <= right_val),
+        _ => return Err(format!("Unknown operator {:?}", operator)),
+    })
+}
+
+/// Work that is left to do once the values it depends on have been pushed onto the value stack.
+///
+/// The evaluator never recurses on the Rust stack. Instead every sub-expression that has to be
+/// evaluated before its parent can continue is pushed onto a heap allocated stack of
+/// continuations, which means that the recursion depth of a nino program is only bounded by
+/// memory.
+enum Continuation {
+    /// Evaluate the node in the current frame and push the result onto the value stack
+    Evaluate(NodeId),
+    /// Collect the topmost values into the array of the node
+    Array(NodeId),
+    /// Apply the operator of the node to the two topmost values
+    BinaryOperation(NodeId),
+    /// Call the builtin of the node with the topmost values as arguments
+    Builtin(NodeId),
+    /// Bind the topmost values to the parameters and evaluate the body of the function with the
+    /// given index. Tail calls replace the current frame instead of pushing a new one.
+    Call(NodeId, usize),
+    /// The function of the current frame has returned
+    Return,
+    /// The value that is matched on is on top of the value stack
+    Match(NodeId),
+    /// The pattern with the given index is on top of the value stack, right above the value that
+    /// is matched on
+    Pattern(NodeId, usize),
+}
 
-    loop {
-        return match current_expression {
-            Expression::Number(..) | Expression::Bool(..) | Expression::Char(..) => {
-                current_expression
+/// Schedules the pattern with the given index, or the default arm if there are no patterns left.
+fn next_pattern(
+    program: &Program,
+    continuations: &mut Vec<Continuation>,
+    values: &mut Vec<Value>,
+    match_: NodeId,
+    index: usize,
+    frames: &[Vec<Value>],
+    tracer: &mut impl Trace,
+) -> Result<(), RuntimeError> {
+    let (patterns, default) = match &program.nodes[match_] {
+        Node::Match {
+            patterns, default, ..
+        } => (patterns, default),
+        node => unreachable!("Expected a match, got {:?}", node),
+    };
+    match patterns.get(index) {
+        Some((pattern, _)) => {
+            continuations.push(Continuation::Pattern(match_, index));
+            continuations.push(Continuation::Evaluate(*pattern));
+        }
+        None => {
+            values.pop();
+            match default {
+                Some(default) => {
+                    tracer.arm(program, frames, match_, index);
+                    continuations.push(Continuation::Evaluate(*default));
+                }
+                None => {
+                    return Err(RuntimeError {
+                        message: "No matching pattern found. You should add a default pattern."
+                            .to_string(),
+                        span: program.spans[match_],
+                    })
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn binary_operation(left: Value, right: Value, operator: BinaryOperator) -> Result<Value, String> {
+    match (left, right) {
+        // Perform operations based on the types and the operator
+        (Value::Number(left_val), Value::Number(right_val)) => {
+            binary_float_float(left_val, right_val, operator)
+        }
+        (Value::Array(left_type, left_val), Value::Array(right_type, right_val)) => {
+            match operator {
+                BinaryOperator::Equal => Ok(Value::Bool(left_val == right_val)),
+                BinaryOperator::Add if left_type == right_type => {
+                    let mut result = left_val;
+                    result.extend(right_val);
+                    Ok(Value::Array(left_type, result))
+                }
+                BinaryOperator::Add => Err(format!(
+                    "Cannot add arrays of {:?} and {:?}",
+                    left_type, right_type
+                )),
+                _ => Err(format!("Invalid operation {:?} on arrays", operator)),
             }
-            Expression::Array(type_, elements) => {
-                let mut result = vec![];
-                for element in elements {
-                    result.push(evaluate(element, &current_symbols));
+        }
+        (left, right) => Err(format!(
+            "Invalid types for {:?}: {:?} and {:?}",
+            operator, left, right
+        )),
+    }
+}
+
+/// What a frame binds besides the parameters of its function. A tail call replaces the frame,
+/// but the function it calls still sees the variables of the function that made the call, like
+/// in the first interpreter, where a tail call inserted its arguments next to them.
+struct Scope {
+    function: usize,
+    /// The names of [Program::names] that are bound, with their values
+    inherited: Vec<(usize, Value)>,
+}
+
+/// The functions that have been called and have not returned yet, the last one being the current
+struct Stack {
+    /// The arguments of each call
+    frames: Vec<Vec<Value>>,
+    scopes: Vec<Scope>,
+    /// How many bindings of each of the names the frames hold, so that a name that no caller
+    /// binds is not looked for on the whole stack
+    bound: Vec<usize>,
+}
+
+impl Stack {
+    fn new(program: &Program) -> Self {
+        Stack {
+            frames: vec![],
+            scopes: vec![],
+            bound: vec![0; program.names.len()],
+        }
+    }
+
+    fn push(&mut self, program: &Program, function: usize, arguments: Vec<Value>) {
+        for name in &program.functions[function].parameters {
+            self.bound[*name] += 1;
+        }
+        self.frames.push(arguments);
+        self.scopes.push(Scope {
+            function,
+            inherited: vec![],
+        });
+    }
+
+    /// Replaces the current frame with the one of a tail call
+    fn replace(&mut self, program: &Program, function: usize, arguments: Vec<Value>) {
+        let Stack {
+            frames,
+            scopes,
+            bound,
+        } = self;
+        let (frame, scope) = match (frames.last_mut(), scopes.last_mut()) {
+            (Some(frame), Some(scope)) => (frame, scope),
+            _ => unreachable!("A tail call is always made from a function"),
+        };
+        let previous = std::mem::replace(frame, arguments);
+        for (name, value) in program.functions[scope.function]
+            .parameters
+            .iter()
+            .zip(previous)
+        {
+            match scope.inherited.iter_mut().find(|(other, _)| other == name) {
+                Some(inherited) => {
+                    inherited.1 = value;
+                    bound[*name] -= 1;
                 }
-                Expression::Array(type_, result)
+                None => scope.inherited.push((*name, value)),
             }
-            Expression::Identifier(identifier) => {
-                let declaration = current_symbols.get(&identifier).unwrap();
-                let expression = declaration.expression.clone();
-                current_expression = *expression;
-                continue;
+        }
+        let parameters = &program.functions[function].parameters;
+        scope.inherited.retain(|(name, _)| {
+            let shadowed = parameters.contains(name);
+            if shadowed {
+                bound[*name] -= 1;
             }
-            Expression::FunctionCall(ref function_call) => match function_call.name.as_str() {
-                "print" => print(
-                    evaluate(function_call.arguments[0].clone(), &current_symbols),
-                    "\n",
-                ),
-                "debug_print" => debug_print(evaluate(
-                    function_call.arguments[0].clone(),
-                    &current_symbols,
-                )),
-                "time" => time(),
-                "sqrt" => {
-                    let expression = evaluate(function_call.arguments[0].clone(), &current_symbols);
-                    match expression {
-                        Expression::Number(val) => Expression::Number(val.sqrt()),
-                        _ => panic!("Invalid type"),
+            !shadowed
+        });
+        for name in parameters {
+            bound[*name] += 1;
+        }
+        scope.function = function;
+    }
+
+    fn pop(&mut self, program: &Program) {
+        self.frames.pop();
+        if let Some(scope) = self.scopes.pop() {
+            for name in &program.functions[scope.function].parameters {
+                self.bound[*name] -= 1;
+            }
+            for (name, _) in scope.inherited {
+                self.bound[name] -= 1;
+            }
+        }
+    }
+
+    /// Loads the value of a variable
+    fn load<'v>(
+        &'v self,
+        program: &Program,
+        globals: &'v [Option<Value>],
+        node: NodeId,
+        slot: Slot,
+    ) -> Result<&'v Value, RuntimeError> {
+        let error = |message: String| RuntimeError {
+            message,
+            span: program.spans[node],
+        };
+        let global = |index: usize| {
+            globals[index]
+                .as_ref()
+                .ok_or_else(|| error("Symbol used before it was declared".to_string()))
+        };
+        match slot {
+            Slot::Parameter(index) => Ok(&self.frames[self.frames.len() - 1][index]),
+            Slot::Global(index) => global(index),
+            Slot::Caller {
+                name,
+                global: index,
+            } => {
+                if self.bound[name] > 0 {
+                    for (scope, frame) in self.scopes.iter().zip(&self.frames).rev() {
+                        let parameters = &program.functions[scope.function].parameters;
+                        if let Some(i) = parameters.iter().position(|other| *other == name) {
+                            return Ok(&frame[i]);
+                        }
+                        if let Some((_, value)) =
+                            scope.inherited.iter().find(|(other, _)| *other == name)
+                        {
+                            return Ok(value);
+                        }
                     }
                 }
-                "head" => {
-                    let expression = evaluate(function_call.arguments[0].clone(), &current_symbols);
-                    head(&expression).clone()
+                match index {
+                    Some(index) => global(index),
+                    None => Err(error(format!(
+                        "Unknown identifier: {}",
+                        program.names[name]
+                    ))),
                 }
-                "last" => {
-                    let expression = evaluate(function_call.arguments[0].clone(), &current_symbols);
-                    last(&expression).clone()
-                }
-                "tail" => {
-                    let expression = evaluate(function_call.arguments[0].clone(), &current_symbols);
-                    tail(&expression)
-                }
-                "len" => {
-                    let expression = evaluate(function_call.arguments[0].clone(), &current_symbols);
-                    len(&expression)
-                }
-                _ => {
-                    // Get the function declaration
-                    let declaration = current_symbols.get(&function_call.name).unwrap();
-                    // The expression which we know to be a function declaration
-                    let function_declaration_expression = *declaration.expression;
-
-                    let function = match function_declaration_expression {
-                        Expression::FunctionDeclaration(function) => function,
-                        _ => panic!("Invalid function"),
-                    };
+            }
+        }
+    }
+}
 
-                    // First the arguments need to be evaluated
-                    let mut temp = vec![];
-                    for (i, argument) in function_call.arguments.iter().enumerate() {
-                        let name = &function.parameters[i].name;
-                        let type_ = &function.parameters[i].type_;
-                        let expression = evaluate(argument.clone(), &current_symbols);
-                        let declaration = Declaration {
-                            name: name.clone(),
-                            type_: type_.clone(),
-                            expression: Box::new(expression),
-                        };
-                        temp.push(declaration);
+/// What the evaluation reports to. Without a hook this is `()`, which does nothing, so the
+/// evaluation is compiled without any of it.
+trait Trace {
+    fn evaluate(
+        &mut self,
+        program: &Program,
+        frames: &[Vec<Value>],
+        node: NodeId,
+    ) -> Result<(), String>;
+    fn call(&mut self, program: &Program, frames: &[Vec<Value>], call: Call, tail: bool);
+    fn return_(&mut self, program: &Program, frames: &[Vec<Value>]);
+    fn arm(&mut self, program: &Program, frames: &[Vec<Value>], node: NodeId, arm: usize);
+}
+
+impl Trace for () {
+    fn evaluate(&mut self, _: &Program, _: &[Vec<Value>], _: NodeId) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn call(&mut self, _: &Program, _: &[Vec<Value>], _: Call, _: bool) {}
+
+    fn return_(&mut self, _: &Program, _: &[Vec<Value>]) {}
+
+    fn arm(&mut self, _: &Program, _: &[Vec<Value>], _: NodeId, _: usize) {}
+}
+
+/// The hook of a run, along with what it needs to know besides the state of the evaluation
+struct Tracer<'t> {
+    hook: &'t mut dyn Hook,
+    globals: &'t ScopedSymbols<'t>,
+    /// The functions of the frames, which the evaluation itself does not need
+    calls: Vec<Call>,
+}
+
+impl<'t> Trace for Tracer<'t> {
+    fn evaluate(
+        &mut self,
+        program: &Program,
+        frames: &[Vec<Value>],
+        node: NodeId,
+    ) -> Result<(), String> {
+        // Only the expressions from the source are reported
+        match program.spans[node] {
+            Some(span) => {
+                let state = State {
+                    program,
+                    frames,
+                    calls: &self.calls,
+                    globals: self.globals,
+                    span: Some(span),
+                };
+                self.hook.evaluate(&state)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn call(&mut self, program: &Program, frames: &[Vec<Value>], call: Call, tail: bool) {
+        let span = program.spans[call.node];
+        match self.calls.last_mut() {
+            // The frame is still entered from where the caller made its call
+            Some(last) if tail => last.function = call.function,
+            _ => self.calls.push(call),
+        }
+        let state = State {
+            program,
+            frames,
+            calls: &self.calls,
+            globals: self.globals,
+            span,
+        };
+        self.hook.call(&state, tail);
+    }
+
+    fn return_(&mut self, program: &Program, frames: &[Vec<Value>]) {
+        let span = self.calls.last().and_then(|call| program.spans[call.node]);
+        let state = State {
+            program,
+            frames,
+            calls: &self.calls,
+            globals: self.globals,
+            span,
+        };
+        self.hook.return_(&state);
+        self.calls.pop();
+    }
+
+    fn arm(&mut self, program: &Program, frames: &[Vec<Value>], node: NodeId, arm: usize) {
+        let state = State {
+            program,
+            frames,
+            calls: &self.calls,
+            globals: self.globals,
+            span: program.spans[node],
+        };
+        self.hook.arm(&state, arm);
+    }
+}
+
+fn evaluate(
+    program: &Program,
+    globals: &[Option<Value>],
+    node: NodeId,
+    output: &mut dyn Write,
+    tracer: &mut impl Trace,
+) -> Result<Value, RuntimeError> {
+    let error = |node: NodeId, message: String| RuntimeError {
+        message,
+        span: program.spans[node],
+    };
+    let mut continuations = vec![Continuation::Evaluate(node)];
+    let mut values: Vec<Value> = vec![];
+    let mut stack = Stack::new(program);
+
+    while let Some(continuation) = continuations.pop() {
+        match continuation {
+            Continuation::Evaluate(node) => {
+                tracer
+                    .evaluate(program, &stack.frames, node)
+                    .map_err(|message| error(node, message))?;
+                match &program.nodes[node] {
+                    Node::Number(value) => values.push(Value::Number(*value)),
+                    Node::Char(value) => values.push(Value::Char(*value)),
+                    Node::Bool(value) => values.push(Value::Bool(*value)),
+                    Node::Function(function) => values.push(Value::Function(*function)),
+                    Node::Array(_, elements) => {
+                        continuations.push(Continuation::Array(node));
+                        for element in elements.iter().rev() {
+                            continuations.push(Continuation::Evaluate(*element));
+                        }
                     }
-                    // Then the arguments can be inserted into the current symbols
-                    for (i, argument) in temp.iter().enumerate() {
-                        current_symbols
-                            .insert(function.parameters[i].name.clone(), argument.clone());
+                    Node::Variable(slot) => {
+                        values.push(stack.load(program, globals, node, *slot)?.clone())
                     }
-
-                    current_expression = *function.expression;
-                    continue;
-                }
-            },
-            Expression::BinaryOperation(binary) => {
-                let left = evaluate(*binary.left, &current_symbols);
-                let right = evaluate(*binary.right, &current_symbols);
-                let operator = binary.operator;
-                return match (left, right) {
-                    // Perform operations based on the types and the operator
-                    (Expression::Number(left_val), Expression::Number(right_val)) => {
-                        binary_float_float(left_val, right_val, operator)
+                    Node::Call {
+                        callee, arguments, ..
+                    } => {
+                        let function = match stack.load(program, globals, node, *callee)? {
+                            Value::Function(function) => *function,
+                            value => {
+                                return Err(error(node, format!("Invalid function {:?}", value)))
+                            }
+                        };
+                        continuations.push(Continuation::Call(node, function));
+                        // The arguments are evaluated first, from left to right
+                        for argument in arguments.iter().rev() {
+                            continuations.push(Continuation::Evaluate(*argument));
+                        }
                     }
-                    (
-                        Expression::Array(left_type, left_val),
-                        Expression::Array(right_type, right_val),
-                    ) => match operator {
-                        BinaryOperator::Equal => Expression::Bool(left_val == right_val),
-                        BinaryOperator::Add => {
-                            assert_eq!(left_type, right_type);
-                            let mut result = left_val.clone();
-                            result.extend(right_val);
-                            Expression::Array(left_type, result)
+                    Node::Builtin(_, arguments) => {
+                        continuations.push(Continuation::Builtin(node));
+                        for argument in arguments.iter().rev() {
+                            continuations.push(Continuation::Evaluate(*argument));
                         }
-                        _ => panic!("Invalid operation"),
-                    },
-                    _ => panic!("Invalid types or operation"), // Handle other cases or operations
+                    }
+                    Node::BinaryOperation(_, left, right) => {
+                        continuations.push(Continuation::BinaryOperation(node));
+                        continuations.push(Continuation::Evaluate(*right));
+                        continuations.push(Continuation::Evaluate(*left));
+                    }
+                    Node::Match { value, .. } => {
+                        continuations.push(Continuation::Match(node));
+                        continuations.push(Continuation::Evaluate(*value));
+                    }
+                }
+            }
+            Continuation::Array(node) => {
+                let (type_, count) = match &program.nodes[node] {
+                    Node::Array(type_, elements) => (type_, elements.len()),
+                    node => unreachable!("Expected an array, got {:?}", node),
                 };
+                let elements = values.split_off(values.len() - count);
+                values.push(Value::Array(type_.clone(), elements));
             }
-
-            Expression::Match(match_) => {
-                let expression = evaluate(*match_.value, &current_symbols);
-                for case in match_.patterns {
-                    let left = evaluate(case.0, &current_symbols);
-                    // TODO: perform this check in the parser
-                    if discriminant(&expression) != discriminant(&left) {
-                        panic!("Invalid types: {:?} and {:?}", expression, left);
-                    } else if expression == left {
-                        return evaluate(case.1, &current_symbols);
+            Continuation::BinaryOperation(node) => {
+                let operator = match &program.nodes[node] {
+                    Node::BinaryOperation(operator, ..) => *operator,
+                    node => unreachable!("Expected a binary operation, got {:?}", node),
+                };
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                let result = binary_operation(left, right, operator);
+                values.push(result.map_err(|message| error(node, message))?);
+            }
+            Continuation::Builtin(node) => {
+                let (builtin, count) = match &program.nodes[node] {
+                    Node::Builtin(builtin, arguments) => (*builtin, arguments.len()),
+                    node => unreachable!("Expected a builtin, got {:?}", node),
+                };
+                let arguments = values.split_off(values.len() - count);
+                let result = builtin.call(arguments, output);
+                values.push(result.map_err(|message| error(node, message))?);
+            }
+            Continuation::Call(node, index) => {
+                let (count, tail) = match &program.nodes[node] {
+                    Node::Call {
+                        arguments, tail, ..
+                    } => (arguments.len(), *tail),
+                    node => unreachable!("Expected a call, got {:?}", node),
+                };
+                let arguments = values.split_off(values.len() - count);
+                let function = &program.functions[index];
+                let parameters = function.declaration.parameters.len();
+                if arguments.len() != parameters {
+                    return Err(error(
+                        node,
+                        format!("Expected {} arguments, got {}", parameters, arguments.len()),
+                    ));
+                }
+                let body = function.body;
+                let call = Call {
+                    function: index,
+                    node,
+                };
+                // Nothing is left to do in the current frame, so it can be reused
+                let tail = tail && !stack.frames.is_empty();
+                match tail {
+                    true => stack.replace(program, index, arguments),
+                    false => {
+                        continuations.push(Continuation::Return);
+                        stack.push(program, index, arguments);
                     }
                 }
-                if match_.default.is_some() {
-                    current_expression = *match_.default.unwrap();
+                tracer.call(program, &stack.frames, call, tail);
+                continuations.push(Continuation::Evaluate(body));
+            }
+            Continuation::Return => {
+                tracer.return_(program, &stack.frames);
+                stack.pop(program);
+            }
+            Continuation::Match(node) => {
+                next_pattern(
+                    program,
+                    &mut continuations,
+                    &mut values,
+                    node,
+                    0,
+                    &stack.frames,
+                    tracer,
+                )?;
+            }
+            Continuation::Pattern(node, index) => {
+                let pattern = values.pop().unwrap();
+                let value = values.last().unwrap();
+                // TODO: perform this check in the parser
+                if discriminant(value) != discriminant(&pattern) {
+                    return Err(error(
+                        node,
+                        format!("Invalid types: {:?} and {:?}", value, pattern),
+                    ));
+                } else if *value == pattern {
+                    values.pop();
+                    let arm = match &program.nodes[node] {
+                        Node::Match { patterns, .. } => patterns[index].1,
+                        node => unreachable!("Expected a match, got {:?}", node),
+                    };
+                    tracer.arm(program, &stack.frames, node, index);
+                    continuations.push(Continuation::Evaluate(arm));
                 } else {
-                    panic!("No matching pattern found. You should add a default pattern.");
+                    next_pattern(
+                        program,
+                        &mut continuations,
+                        &mut values,
+                        node,
+                        index + 1,
+                        &stack.frames,
+                        tracer,
+                    )?;
                 }
-                continue;
             }
-            _ => panic!("Unknown expression {:?}", current_expression),
-        };
+        }
     }
+
+    Ok(values.pop().unwrap())
 }
 
 pub struct VirtualMachine<'a> {
+    /// The global symbols by name. The virtual machine itself uses slots, so these are only kept
+    /// up to date for debugging.
     pub symbols: ScopedSymbols<'a>,
+    resolver: Resolver,
+    program: Program,
+    globals: Vec<Option<Value>>,
+    /// Where the program prints to, stdout unless it is set
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Default for VirtualMachine<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> VirtualMachine<'a> {
     pub fn new() -> VirtualMachine<'a> {
         VirtualMachine {
             symbols: ScopedSymbols::new(),
+            resolver: Resolver::new(),
+            program: Program::default(),
+            globals: vec![],
+            output: Box::new(io::stdout()),
         }
     }
 
-    pub fn evaluate(&mut self, expression: Expression) -> Expression {
-        evaluate(expression, &self.symbols)
+    /// Makes the program print to the output instead of stdout
+    pub fn set_output(&mut self, output: impl Write + 'a) {
+        self.output = Box::new(output);
+    }
+
+    fn resolve(&mut self, expression: &Expression) -> Result<NodeId, RuntimeError> {
+        Ok(self.resolver.resolve(&mut self.program, expression)?)
+    }
+
+    pub fn evaluate(&mut self, mut expression: Expression) -> Result<Expression, RuntimeError> {
+        mark_expression(&mut expression, false);
+        let node = self.resolve(&expression)?;
+        let value = evaluate(
+            &self.program,
+            &self.globals,
+            node,
+            &mut self.output,
+            &mut (),
+        )?;
+        Ok(self.program.expression(&value))
+    }
+
+    pub fn run(&mut self, program: Vec<Item>) -> Result<(), RuntimeError> {
+        self.execute(program, None)
+    }
+
+    /// Runs the program, telling the hook what happens along the way
+    pub fn run_with_hook(
+        &mut self,
+        program: Vec<Item>,
+        hook: &mut dyn Hook,
+    ) -> Result<(), RuntimeError> {
+        self.execute(program, Some(hook))
     }
 
-    pub fn run(&mut self, program: Vec<Item>) {
+    fn execute(
+        &mut self,
+        mut program: Vec<Item>,
+        mut hook: Option<&mut dyn Hook>,
+    ) -> Result<(), RuntimeError> {
+        mark_tail_calls(&mut program);
+        // Every global gets its slot up front, so that functions can call the ones declared
+        // after them
+        for statement in &program {
+            if let Item::Declaration(declaration) = statement {
+                self.resolver.declare(&declaration.name);
+            }
+        }
+        self.globals.resize(self.resolver.global_count(), None);
+
         for statement in program {
             match statement {
                 Item::Declaration(declaration) => {
-                    let declaration = match *declaration.expression {
-                        Expression::FunctionDeclaration(_) => declaration,
-                        _ => Declaration {
-                            name: declaration.name.clone(),
-                            type_: declaration.type_.clone(),
-                            expression: Box::new(evaluate(*declaration.expression, &self.symbols)),
-                        },
+                    let node = self.resolve(&declaration.expression)?;
+                    if let Node::Function(function) = self.program.nodes[node] {
+                        self.program.functions[function].name = Some(declaration.name.clone());
+                    }
+                    let value = match hook.as_deref_mut() {
+                        Some(hook) => {
+                            let span = Some(declaration.location.span());
+                            let state = State {
+                                program: &self.program,
+                                frames: &[],
+                                calls: &[],
+                                globals: &self.symbols,
+                                span,
+                            };
+                            hook.declaration(&state, &declaration)
+                                .map_err(|message| RuntimeError { message, span })?;
+                            let mut tracer = Tracer {
+                                hook,
+                                globals: &self.symbols,
+                                calls: vec![],
+                            };
+                            evaluate(
+                                &self.program,
+                                &self.globals,
+                                node,
+                                &mut self.output,
+                                &mut tracer,
+                            )?
+                        }
+                        None => evaluate(
+                            &self.program,
+                            &self.globals,
+                            node,
+                            &mut self.output,
+                            &mut (),
+                        )?,
                     };
-                    self.symbols.insert(declaration.name.clone(), declaration);
+                    let slot = self.resolver.declare(&declaration.name);
+                    self.symbols.insert(
+                        declaration.name.clone(),
+                        Declaration {
+                            name: declaration.name,
+                            type_: declaration.type_,
+                            expression: Box::new(self.program.expression(&value)),
+                            location: declaration.location,
+                            doc: declaration.doc,
+                        },
+                    );
+                    self.globals[slot] = Some(value);
                 }
                 Item::Expression(expression) => {
-                    evaluate(expression, &self.symbols);
+                    let node = self.resolve(&expression)?;
+                    match hook.as_deref_mut() {
+                        Some(hook) => {
+                            let mut tracer = Tracer {
+                                hook,
+                                globals: &self.symbols,
+                                calls: vec![],
+                            };
+                            evaluate(
+                                &self.program,
+                                &self.globals,
+                                node,
+                                &mut self.output,
+                                &mut tracer,
+                            )?
+                        }
+                        None => evaluate(
+                            &self.program,
+                            &self.globals,
+                            node,
+                            &mut self.output,
+                            &mut (),
+                        )?,
+                    };
+                }
+                Item::Error => {
+                    return Err(RuntimeError {
+                        message: "Cannot run an item that failed to parse".to_string(),
+                        span: None,
+                    })
                 }
             }
         }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::{BinaryOperation, Type};
+    use crate::parser::{BinaryOperation, Location, Type};
 
+    use super::builtins::{last, len, tail};
 
     use super::*;
 
@@ -214,12 +758,87 @@ mod tests {
             operator: BinaryOperator::Add,
             left: Box::new(Expression::Number(1.0)),
             right: Box::new(Expression::Number(2.0)),
+            location: Location::default(),
         });
 
         let mut vm = VirtualMachine::new();
         let result = vm.evaluate(expression);
 
-        assert_eq!(result, Expression::Number(3.0));
+        assert_eq!(result, Ok(Expression::Number(3.0)));
+    }
+
+    /// Records the events of a run
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl Hook for Recorder {
+        fn declaration(&mut self, _state: &State, declaration: &Declaration) -> Result<(), String> {
+            self.events.push(format!("let {}", declaration.name));
+            Ok(())
+        }
+
+        fn call(&mut self, state: &State, tail: bool) {
+            let frame = state.stack().pop().unwrap();
+            let arguments: Vec<String> = frame
+                .arguments
+                .iter()
+                .map(|argument| format!("{:?}", argument.expression))
+                .collect();
+            self.events.push(format!(
+                "{}call {}({}) at depth {}",
+                if tail { "tail " } else { "" },
+                frame.function,
+                arguments.join(", "),
+                state.depth()
+            ));
+        }
+
+        fn return_(&mut self, state: &State) {
+            self.events
+                .push(format!("return {}", state.function().unwrap()));
+        }
+
+        fn arm(&mut self, _state: &State, arm: usize) {
+            self.events.push(format!("arm {}", arm));
+        }
+    }
+
+    #[test]
+    fn test_hook() {
+        let code = "let count:fn = (n:num):num => n ? { 0 => 0, count(n - 1) };
+let x:num = count(2);";
+        let items = crate::parser::parse(&crate::lexer::tokenize(code).unwrap()).unwrap();
+        let mut recorder = Recorder::default();
+        let mut vm = VirtualMachine::new();
+        vm.run_with_hook(items, &mut recorder).unwrap();
+        assert_eq!(
+            recorder.events,
+            vec![
+                "let count",
+                "let x",
+                "call count(Number(2.0)) at depth 1",
+                "arm 1",
+                "tail call count(Number(1.0)) at depth 1",
+                "arm 1",
+                "tail call count(Number(0.0)) at depth 1",
+                "arm 0",
+                "return count",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let code = "print(\"hi\");\nprint([1, 2]);";
+        let items = crate::parser::parse(&crate::lexer::tokenize(code).unwrap()).unwrap();
+        let mut output = vec![];
+        let mut vm = VirtualMachine::new();
+        vm.set_output(&mut output);
+        vm.run(items).unwrap();
+        drop(vm);
+        assert_eq!(String::from_utf8(output).unwrap(), "hi\n[1, 2]\n");
     }
 
     #[test]
@@ -244,48 +863,37 @@ mod tests {
     }
     #[test]
     fn test_last() {
-        let array = Expression::Array(
-            Type::Number,
-            vec![Expression::Number(1.0), Expression::Number(2.0)],
-        );
+        let array = Value::Array(Type::Number, vec![Value::Number(1.0), Value::Number(2.0)]);
 
         let result = last(&array);
-        assert_eq!(result, &Expression::Number(2.0));
+        assert_eq!(result, Ok(&Value::Number(2.0)));
     }
 
     #[test]
     fn test_tail() {
-        let array = Expression::Array(
+        let array = Value::Array(
             Type::Number,
-            vec![
-                Expression::Number(1.0),
-                Expression::Number(2.0),
-                Expression::Number(3.0),
-            ],
+            vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)],
         );
 
         let result = tail(&array);
         assert_eq!(
             result,
-            Expression::Array(
+            Ok(Value::Array(
                 Type::Number,
-                vec![Expression::Number(2.0), Expression::Number(3.0)]
-            )
+                vec![Value::Number(2.0), Value::Number(3.0)]
+            ))
         );
     }
 
     #[test]
     fn test_len() {
-        let array = Expression::Array(
+        let array = Value::Array(
             Type::Number,
-            vec![
-                Expression::Number(1.0),
-                Expression::Number(2.0),
-                Expression::Number(3.0),
-            ],
+            vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)],
         );
 
         let result = len(&array);
-        assert_eq!(result, Expression::Number(3.0));
+        assert_eq!(result, Ok(Value::Number(3.0)));
     }
 }
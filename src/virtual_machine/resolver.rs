@@ -0,0 +1,384 @@
+//! Resolves the identifiers of the AST to slots.
+//!
+//! Looking up a symbol by name means hashing the name and walking a chain of scopes on every
+//! access. The resolver does that work once, before the program runs: every variable is given a
+//! [Slot] and every expression is lowered into a [Node] that the virtual machine can evaluate
+//! with plain vector indexing.
+
+use std::collections::HashMap;
+
+use crate::lexer::Span;
+use crate::parser::{BinaryOperator, Expression, FunctionDeclaration, Type};
+
+use super::builtins::Builtin;
+use super::value::Value;
+
+#[derive(Debug, PartialEq)]
+pub struct ResolverError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for ResolverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Where a variable is stored at runtime.
+///
+/// A function sees the parameters of the functions that called it, like in the first interpreter,
+/// which inserted the arguments of a call into the symbols of the caller. Only the parameters of
+/// the function itself and the names used at the top level can be given their place before the
+/// program runs. The other names are looked up on the call stack when they are used.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Slot {
+    /// The parameter with the index, in the frame of the current function
+    Parameter(usize),
+    /// The global with the index
+    Global(usize),
+    /// A name used in a function that does not have a parameter with that name. It refers to the
+    /// parameter of the innermost caller that has one, or else to the global, if there is one.
+    /// `name` is the index of the name in [Program::names].
+    Caller { name: usize, global: Option<usize> },
+}
+
+/// Index of a node in the [Program]
+pub type NodeId = usize;
+
+/// An expression whose identifiers have been resolved
+#[derive(Debug, PartialEq, Clone)]
+pub enum Node {
+    Number(f64),
+    Char(u8),
+    Bool(bool),
+    Array(Type, Vec<NodeId>),
+    /// Index of the function in the [Program]
+    Function(usize),
+    Variable(Slot),
+    Call {
+        callee: Slot,
+        arguments: Vec<NodeId>,
+        tail: bool,
+    },
+    Builtin(Builtin, Vec<NodeId>),
+    BinaryOperation(BinaryOperator, NodeId, NodeId),
+    Match {
+        value: NodeId,
+        patterns: Vec<(NodeId, NodeId)>,
+        default: Option<NodeId>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    /// The function as it was declared, kept for debugging
+    pub declaration: FunctionDeclaration,
+    /// The index in [Program::names] of each parameter
+    pub parameters: Vec<usize>,
+    /// The global it was declared as, if any. Functions have no name of their own.
+    pub name: Option<String>,
+    pub body: NodeId,
+}
+
+/// All the nodes and functions that have been resolved so far. Nodes refer to each other by
+/// index, so the program can be evaluated without cloning any part of it.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub nodes: Vec<Node>,
+    /// Where each node is in the source, if it was parsed from it
+    pub spans: Vec<Option<Span>>,
+    pub functions: Vec<Function>,
+    /// The names of the parameters and of the variables that are looked up on the call stack
+    pub names: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl Program {
+    /// The index of the name in [Program::names], which is added if it is not there yet
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(index) = self.indices.get(name) {
+            return *index;
+        }
+        self.names.push(name.to_string());
+        self.indices.insert(name.to_string(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    fn push(&mut self, node: Node, span: Option<Span>) -> NodeId {
+        self.nodes.push(node);
+        self.spans.push(span);
+        self.nodes.len() - 1
+    }
+
+    /// Converts a value back into an expression
+    pub fn expression(&self, value: &Value) -> Expression {
+        match value {
+            Value::Number(value) => Expression::Number(*value),
+            Value::Char(value) => Expression::Char(*value),
+            Value::Bool(value) => Expression::Bool(*value),
+            Value::Array(type_, elements) => Expression::Array(
+                type_.clone(),
+                elements
+                    .iter()
+                    .map(|value| self.expression(value))
+                    .collect(),
+            ),
+            Value::Function(function) => {
+                Expression::FunctionDeclaration(self.functions[*function].declaration.clone())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Resolver {
+    globals: HashMap<String, usize>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            globals: HashMap::new(),
+        }
+    }
+
+    /// Gives the global a slot, unless it already has one, and returns its index
+    pub fn declare(&mut self, name: &str) -> usize {
+        let count = self.globals.len();
+        *self.globals.entry(name.to_string()).or_insert(count)
+    }
+
+    /// The number of globals that have been declared
+    pub fn global_count(&self) -> usize {
+        self.globals.len()
+    }
+
+    /// Lowers a top level expression into the program
+    pub fn resolve(
+        &self,
+        program: &mut Program,
+        expression: &Expression,
+    ) -> Result<NodeId, ResolverError> {
+        self.lower(program, expression, None)
+    }
+
+    /// Inside a function, a name that is not one of its parameters may be one of a caller, so
+    /// only at the top level is a name that is not a global unknown
+    fn lookup(
+        &self,
+        program: &mut Program,
+        name: &str,
+        parameters: Option<&HashMap<String, usize>>,
+    ) -> Option<Slot> {
+        let global = self.globals.get(name).copied();
+        match parameters {
+            Some(parameters) => Some(match parameters.get(name) {
+                Some(index) => Slot::Parameter(*index),
+                None => Slot::Caller {
+                    name: program.name(name),
+                    global,
+                },
+            }),
+            None => global.map(Slot::Global),
+        }
+    }
+
+    /// `parameters` are the parameters of the function the expression is in, if any
+    fn lower(
+        &self,
+        program: &mut Program,
+        expression: &Expression,
+        parameters: Option<&HashMap<String, usize>>,
+    ) -> Result<NodeId, ResolverError> {
+        let span = expression.span();
+        let node = match expression {
+            Expression::Number(value) => Node::Number(*value),
+            Expression::Char(value) => Node::Char(*value),
+            Expression::Bool(value) => Node::Bool(*value),
+            Expression::Array(type_, elements) => {
+                let mut nodes = vec![];
+                for element in elements {
+                    nodes.push(self.lower(program, element, parameters)?);
+                }
+                Node::Array(type_.clone(), nodes)
+            }
+            Expression::Identifier(name, _) => match self.lookup(program, name, parameters) {
+                Some(slot) => Node::Variable(slot),
+                None => {
+                    return Err(ResolverError {
+                        message: format!("Unknown identifier: {}", name),
+                        span,
+                    })
+                }
+            },
+            Expression::FunctionDeclaration(function) => {
+                let parameters = function
+                    .parameters
+                    .iter()
+                    .enumerate()
+                    .map(|(i, parameter)| (parameter.name.clone(), i))
+                    .collect();
+                let body = self.lower(program, &function.expression, Some(&parameters))?;
+                let names = function
+                    .parameters
+                    .iter()
+                    .map(|parameter| program.name(&parameter.name))
+                    .collect();
+                program.functions.push(Function {
+                    declaration: function.clone(),
+                    parameters: names,
+                    name: None,
+                    body,
+                });
+                Node::Function(program.functions.len() - 1)
+            }
+            Expression::FunctionCall(function_call) => {
+                let mut arguments = vec![];
+                for argument in &function_call.arguments {
+                    arguments.push(self.lower(program, argument, parameters)?);
+                }
+                match Builtin::from_name(&function_call.name) {
+                    Some(builtin) => Node::Builtin(builtin, arguments),
+                    None => match self.lookup(program, &function_call.name, parameters) {
+                        Some(callee) => Node::Call {
+                            callee,
+                            arguments,
+                            tail: function_call.tail,
+                        },
+                        None => {
+                            return Err(ResolverError {
+                                message: format!("Unknown function: {}", function_call.name),
+                                span,
+                            })
+                        }
+                    },
+                }
+            }
+            Expression::BinaryOperation(binary) => {
+                let left = self.lower(program, &binary.left, parameters)?;
+                let right = self.lower(program, &binary.right, parameters)?;
+                Node::BinaryOperation(binary.operator, left, right)
+            }
+            Expression::Match(match_) => {
+                let value = self.lower(program, &match_.value, parameters)?;
+                let mut patterns = vec![];
+                for (pattern, arm) in &match_.patterns {
+                    let pattern = self.lower(program, pattern, parameters)?;
+                    let arm = self.lower(program, arm, parameters)?;
+                    patterns.push((pattern, arm));
+                }
+                let default = match &match_.default {
+                    Some(default) => Some(self.lower(program, default, parameters)?),
+                    None => None,
+                };
+                Node::Match {
+                    value,
+                    patterns,
+                    default,
+                }
+            }
+        };
+        Ok(program.push(node, span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::tokenize;
+    use crate::parser::{parse_expression, Item};
+
+    use super::*;
+
+    fn resolve(resolver: &Resolver, code: &str) -> (Program, Result<NodeId, ResolverError>) {
+        let tokens = tokenize(code).unwrap();
+        let expression = parse_expression(&mut tokens.iter().peekable()).unwrap();
+        let mut program = Program::default();
+        let result = resolver.resolve(&mut program, &expression);
+        (program, result)
+    }
+
+    #[test]
+    fn test_slots() {
+        let mut resolver = Resolver::new();
+        assert_eq!(resolver.declare("y"), 0);
+        assert_eq!(resolver.declare("x"), 1);
+        assert_eq!(resolver.declare("y"), 0);
+
+        let (program, result) = resolve(&resolver, "(x:num):num => x + y");
+        let function = &program.functions[0];
+        let (left, right) = match program.nodes[function.body] {
+            Node::BinaryOperation(BinaryOperator::Add, left, right) => (left, right),
+            ref node => panic!("Expected an addition, got {:?}", node),
+        };
+        // The parameter shadows the global, and the global may be shadowed by a caller
+        assert_eq!(program.nodes[left], Node::Variable(Slot::Parameter(0)));
+        assert_eq!(
+            program.nodes[right],
+            Node::Variable(Slot::Caller {
+                name: 0,
+                global: Some(0)
+            })
+        );
+        assert_eq!(program.names, vec!["y", "x"]);
+        assert_eq!(program.functions[0].parameters, vec![1]);
+        assert_eq!(program.nodes[result.unwrap()], Node::Function(0));
+
+        let (program, result) = resolve(&resolver, "x");
+        assert_eq!(
+            program.nodes[result.unwrap()],
+            Node::Variable(Slot::Global(1))
+        );
+    }
+
+    #[test]
+    fn test_caller_names() {
+        let mut resolver = Resolver::new();
+        let tokens = tokenize("let f:fn = (x:num):num => (y:num):num => x + y;").unwrap();
+        let items = crate::parser::parse(&tokens).unwrap();
+        let expression = match &items[0] {
+            Item::Declaration(declaration) => &declaration.expression,
+            _ => unreachable!(),
+        };
+        resolver.declare("f");
+        let mut program = Program::default();
+        resolver.resolve(&mut program, expression).unwrap();
+        // The inner function is called after f has returned, so x is looked up then
+        let x = program.names.iter().position(|name| name == "x").unwrap();
+        assert!(program.nodes.contains(&Node::Variable(Slot::Caller {
+            name: x,
+            global: None
+        })));
+
+        // At the top level there are no callers
+        let (_, result) = resolve(&resolver, "x");
+        assert_eq!(
+            result,
+            Err(ResolverError {
+                message: "Unknown identifier: x".to_string(),
+                span: Some(Span { begin: 0, end: 0 }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        let (_, result) = resolve(&Resolver::new(), "f(1)");
+        assert_eq!(
+            result,
+            Err(ResolverError {
+                message: "Unknown function: f".to_string(),
+                span: Some(Span { begin: 0, end: 3 }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_builtin() {
+        let (program, result) = resolve(&Resolver::new(), "print(1)");
+        assert_eq!(
+            program.nodes[result.unwrap()],
+            Node::Builtin(Builtin::Print, vec![0])
+        );
+    }
+}
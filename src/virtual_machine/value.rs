@@ -0,0 +1,12 @@
+use crate::parser::Type;
+
+/// A value computed by the virtual machine
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Number(f64),
+    Char(u8),
+    Bool(bool),
+    Array(Type, Vec<Value>),
+    /// Index of the function in the [Program](super::resolver::Program)
+    Function(usize),
+}
@@ -0,0 +1,59 @@
+/// Replays recorded sessions with the debug adapter. In a transcript, `->` lines are the requests
+/// of the client and `<-` lines what the adapter sent, in order. Paths are relative to the crate.
+use nino::dap::run;
+use nino::json::Json;
+use nino::lsp::read_message;
+
+fn replay(name: &str) {
+    let path = format!("{}/tests/dap/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+    let transcript = std::fs::read_to_string(&path).unwrap();
+    let mut input = String::new();
+    let mut expected = vec![];
+    for line in transcript.lines() {
+        if let Some(request) = line.strip_prefix("-> ") {
+            input.push_str(&format!(
+                "Content-Length: {}\r\n\r\n{}",
+                request.len(),
+                request
+            ));
+        } else if let Some(message) = line.strip_prefix("<- ") {
+            expected.push(Json::parse(message).unwrap());
+        }
+    }
+
+    let mut output = vec![];
+    run(input.as_bytes(), &mut output).unwrap();
+    let mut reader = output.as_slice();
+    let mut messages = vec![];
+    while let Some(message) = read_message(&mut reader).unwrap() {
+        messages.push(message.unwrap());
+    }
+
+    for (i, (message, expected)) in messages.iter().zip(&expected).enumerate() {
+        assert_eq!(
+            message,
+            expected,
+            "message {} of {} differs\ngot:      {}\nexpected: {}",
+            i + 1,
+            name,
+            message,
+            expected
+        );
+    }
+    assert_eq!(messages.len(), expected.len(), "in {}", name);
+}
+
+#[test]
+fn breakpoints() {
+    replay("breakpoints");
+}
+
+#[test]
+fn stepping() {
+    replay("stepping");
+}
+
+#[test]
+fn errors() {
+    replay("errors");
+}
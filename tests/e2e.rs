@@ -1,7 +1,9 @@
 use nino::{
-    lexer::tokenize,
+    lexer::{tokenize, Span},
+    optimizer::optimize,
     parser::{parse, Expression, Type},
-    virtual_machine::VirtualMachine,
+    testing::{run_test, tests},
+    virtual_machine::{RuntimeError, VirtualMachine},
 };
 
 /// Testing a declaration of factorial function and calling it
@@ -12,12 +14,12 @@ fn test_recursion() {
     n * factorial(n-1)
 };
 let result:num = factorial(5);";
-    let tokens = tokenize(code);
+    let tokens = tokenize(code).unwrap();
     let ast = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
 
-    vm.run(ast);
+    vm.run(ast).unwrap();
 
     let function = vm.symbols.get("factorial").unwrap();
     assert_eq!(function.name, "factorial");
@@ -34,12 +36,12 @@ fn test_array_operations() {
         let array2:[num] = [6, 7, 8, 9, 10];
         let array3:[num] = array + array2;";
 
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
     let ast = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
 
-    vm.run(ast);
+    vm.run(ast).unwrap();
 
     assert!(vm.symbols.get("array").is_some());
     assert_eq!(vm.symbols.get("array").unwrap().name, "array");
@@ -88,12 +90,12 @@ fn test_array_operations() {
 fn test_string() {
     let declare = "let string:[char] = \"Hello\" + \", World!\";";
 
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
 
     let program = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
-    vm.run(program);
+    vm.run(program).unwrap();
 
     let result = *vm.symbols.get("string").unwrap().expression.clone();
     assert_eq!(
@@ -119,16 +121,61 @@ fn test_string() {
     );
 }
 
+/// Testing identifiers that start with an uppercase letter, an underscore or a non-ASCII letter
+#[test]
+fn test_identifiers() {
+    let code = "let Double:fn = (_n:num):num => _n * 2;
+let größe:num = Double(0x10);";
+    let tokens = tokenize(code).unwrap();
+    let mut vm = VirtualMachine::new();
+    vm.run(parse(&tokens).unwrap()).unwrap();
+
+    let result = *vm.symbols.get("größe").unwrap().expression.clone();
+    assert_eq!(result, Expression::Number(32.0));
+}
+
+/// Testing escapes and that strings hold UTF-8
+#[test]
+fn test_string_escapes() {
+    let declare = r#"let string:[char] = "é\t\"\x41";
+let length:num = len(string);
+let quote:char = '\'';"#;
+
+    let tokens = tokenize(declare).unwrap();
+    let program = parse(&tokens).unwrap();
+    let mut vm = VirtualMachine::new();
+    vm.run(program).unwrap();
+
+    let result = *vm.symbols.get("string").unwrap().expression.clone();
+    assert_eq!(
+        result,
+        Expression::Array(
+            Type::Char,
+            vec![
+                Expression::Char(0xC3),
+                Expression::Char(0xA9),
+                Expression::Char(b'\t'),
+                Expression::Char(b'"'),
+                Expression::Char(b'A'),
+            ]
+        )
+    );
+    let result = *vm.symbols.get("length").unwrap().expression.clone();
+    assert_eq!(result, Expression::Number(5.0));
+    let result = *vm.symbols.get("quote").unwrap().expression.clone();
+    assert_eq!(result, Expression::Char(b'\''));
+}
+
 #[test]
 fn test_string_equality() {
     let declare = "let string:[char] = \"Hello\" == \"Hello\";";
 
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
 
     let program = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
-    vm.run(program);
+    vm.run(program).unwrap();
 
     let result = *vm.symbols.get("string").unwrap().expression.clone();
     assert_eq!(result, Expression::Bool(true));
@@ -140,12 +187,12 @@ fn test_declare_string_as_array() {
 let string2:[char] = ['H', 'e', 'l', 'l', 'o'];
 let equality:bool = string1 == string2;";
 
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
 
     let program = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
-    vm.run(program);
+    vm.run(program).unwrap();
 
     let result = *vm.symbols.get("string1").unwrap().expression.clone();
     assert_eq!(
@@ -189,13 +236,13 @@ fn tail_optimization() {
     increment(x+1, i-1)
 };
 let incremented:num = increment(0, 20000);";
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
 
     let program = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
 
-    vm.run(program);
+    vm.run(program).unwrap();
 
     let result = *vm.symbols.get("incremented").unwrap().expression.clone();
     assert_eq!(result, Expression::Number(20000.0));
@@ -209,13 +256,13 @@ let func:fn = (x:num):num=>x+1;
 func(1);
 ";
 
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
 
     let program = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
 
-    vm.run(program);
+    vm.run(program).unwrap();
 
     let result = *vm.symbols.get("x").unwrap().expression.clone();
     assert_eq!(result, Expression::Number(0.0));
@@ -226,26 +273,26 @@ func(1);
 fn test_precedence() {
     let declare = "let x:num = 1 + 2 * 3;";
 
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
 
     let program = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
 
-    vm.run(program);
+    vm.run(program).unwrap();
 
     let result = *vm.symbols.get("x").unwrap().expression.clone();
     assert_eq!(result, Expression::Number(7.0));
 
     let declare = "let x:num = (1 * 2) + 3;";
 
-    let tokens = tokenize(declare);
+    let tokens = tokenize(declare).unwrap();
 
     let program = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
 
-    vm.run(program);
+    vm.run(program).unwrap();
 
     let result = *vm.symbols.get("x").unwrap().expression.clone();
     assert_eq!(result, Expression::Number(5.0));
@@ -255,12 +302,12 @@ fn test_precedence() {
 #[test]
 fn test_factorial() {
     let code = include_str!("programs/factorial.ni");
-    let tokens = tokenize(code);
+    let tokens = tokenize(code).unwrap();
     let ast = parse(&tokens).unwrap();
 
     let mut vm = VirtualMachine::new();
 
-    vm.run(ast);
+    vm.run(ast).unwrap();
 
     let result = *vm.symbols.get("result1").unwrap().expression.clone();
     assert_eq!(result, Expression::Number(1.0));
@@ -274,10 +321,10 @@ fn test_factorial() {
 fn test_is_prime() {
     let code = include_str!("programs/is-prime.ni");
 
-    let tokens = tokenize(code);
+    let tokens = tokenize(code).unwrap();
     let ast = parse(&tokens).unwrap();
     let mut vm = VirtualMachine::new();
-    vm.run(ast);
+    vm.run(ast).unwrap();
 
     let result = *vm.symbols.get("is_prime_23").unwrap().expression.clone();
     assert_eq!(result, Expression::Bool(true));
@@ -296,10 +343,10 @@ fn test_is_prime() {
 fn test_fibonacci() {
     let code = include_str!("programs/fibonacci.ni");
 
-    let tokens = tokenize(code);
+    let tokens = tokenize(code).unwrap();
     let ast = parse(&tokens).unwrap();
     let mut vm = VirtualMachine::new();
-    vm.run(ast);
+    vm.run(ast).unwrap();
 
     let result = *vm.symbols.get("simple").unwrap().expression.clone();
     assert_eq!(result, Expression::Number(21.0));
@@ -308,3 +355,217 @@ fn test_fibonacci() {
     let result = *vm.symbols.get("tail").unwrap().expression.clone();
     assert_eq!(result, Expression::Number(21.0));
 }
+
+/// Testing deep non-tail recursion, which must not overflow the stack
+#[test]
+fn test_deep_recursion() {
+    let code = "let sum:fn = (n:num):num => n ? {
+    0 => 0,
+    n + sum(n-1)
+};
+let result:num = sum(100000);";
+    let tokens = tokenize(code).unwrap();
+    let ast = parse(&tokens).unwrap();
+
+    let mut vm = VirtualMachine::new();
+
+    vm.run(ast).unwrap();
+
+    let result = *vm.symbols.get("result").unwrap().expression.clone();
+    assert_eq!(result, Expression::Number(5000050000.0));
+}
+
+/// Testing tail calls in a matched arm
+#[test]
+fn tail_optimization_match_arm() {
+    let code = "let count:fn = (x:num, i:num):num => (i == 0) ? {
+    false => count(x+1, i-1),
+    x
+};
+let counted:num = count(0, 200000);";
+    let tokens = tokenize(code).unwrap();
+    let ast = parse(&tokens).unwrap();
+
+    let mut vm = VirtualMachine::new();
+
+    vm.run(ast).unwrap();
+
+    let result = *vm.symbols.get("counted").unwrap().expression.clone();
+    assert_eq!(result, Expression::Number(200000.0));
+}
+
+/// Testing tail calls between mutually recursive functions
+#[test]
+fn tail_optimization_mutual_recursion() {
+    let code = "let is_even:fn = (n:num):bool => n ? {
+    0 => true,
+    is_odd(n-1)
+};
+let is_odd:fn = (n:num):bool => n ? {
+    0 => false,
+    is_even(n-1)
+};
+let even:bool = is_even(200000);
+let odd:bool = is_odd(200001);";
+    let tokens = tokenize(code).unwrap();
+    let ast = parse(&tokens).unwrap();
+
+    let mut vm = VirtualMachine::new();
+
+    vm.run(ast).unwrap();
+
+    let result = *vm.symbols.get("even").unwrap().expression.clone();
+    assert_eq!(result, Expression::Bool(true));
+    let result = *vm.symbols.get("odd").unwrap().expression.clone();
+    assert_eq!(result, Expression::Bool(true));
+}
+
+/// Testing that optimized programs compute the same results
+#[test]
+fn test_optimized() {
+    let code = include_str!("programs/fibonacci.ni").to_string()
+        + include_str!("programs/factorial.ni")
+        + include_str!("programs/is-prime.ni");
+    let tokens = tokenize(&code).unwrap();
+
+    let mut vm = VirtualMachine::new();
+    vm.run(parse(&tokens).unwrap()).unwrap();
+    let mut optimized_vm = VirtualMachine::new();
+    optimized_vm.run(optimize(parse(&tokens).unwrap())).unwrap();
+
+    for name in [
+        "simple",
+        "correct",
+        "tail",
+        "result1",
+        "result2",
+        "result3",
+        "is_prime_23",
+        "is_prime_100",
+        "is_prime_10000189",
+    ] {
+        assert_eq!(
+            optimized_vm.symbols.get(name).unwrap().expression,
+            vm.symbols.get(name).unwrap().expression
+        );
+    }
+}
+
+fn run_error(code: &str) -> RuntimeError {
+    let tokens = tokenize(code).unwrap();
+    let ast = parse(&tokens).unwrap();
+    VirtualMachine::new().run(ast).unwrap_err()
+}
+
+/// Testing that runtime errors point at the expression that failed
+#[test]
+fn test_runtime_errors() {
+    assert_eq!(
+        run_error("let f:fn = (x:num):num => x ? { 1 => 2 };\nlet y:num = f(3);"),
+        RuntimeError {
+            message: "No matching pattern found. You should add a default pattern.".to_string(),
+            span: Some(Span { begin: 28, end: 39 }),
+        }
+    );
+    assert_eq!(
+        run_error("let x:num = 1 + \"a\";"),
+        RuntimeError {
+            message: "Invalid types for Add: Number(1.0) and Array(Char, [Char(97)])".to_string(),
+            span: Some(Span { begin: 14, end: 14 }),
+        }
+    );
+    assert_eq!(
+        run_error("let f:fn = (x:num):num => x;\nf(1, 2);"),
+        RuntimeError {
+            message: "Expected 1 arguments, got 2".to_string(),
+            span: Some(Span { begin: 29, end: 35 }),
+        }
+    );
+    assert_eq!(
+        run_error("print(y);\nlet y:num = 1;"),
+        RuntimeError {
+            message: "Symbol used before it was declared".to_string(),
+            span: Some(Span { begin: 6, end: 6 }),
+        }
+    );
+    assert_eq!(
+        run_error("print(head(1));"),
+        RuntimeError {
+            message: "Cannot take head of Number(1.0)".to_string(),
+            span: Some(Span { begin: 6, end: 12 }),
+        }
+    );
+    assert_eq!(
+        run_error("print(z);"),
+        RuntimeError {
+            message: "Unknown identifier: z".to_string(),
+            span: Some(Span { begin: 6, end: 6 }),
+        }
+    );
+}
+
+/// Functions see the parameters of the functions that called them, see the scope section of the
+/// language specification
+#[test]
+fn test_dynamic_scope() {
+    let code = "let polarity:fn = (x:num):num => n mod 2;
+let helper:fn = (n:num):num => polarity(n);
+let n:num = 10;
+let a:num = helper(3);
+let b:num = polarity(0);
+let outer:fn = (m:num):num => inner(m + 1);
+let inner:fn = (k:num):num => k * m;
+let c:num = outer(2);";
+    let mut vm = VirtualMachine::new();
+    vm.run(parse(&tokenize(code).unwrap()).unwrap()).unwrap();
+    let value = |name: &str| *vm.symbols.get(name).unwrap().expression.clone();
+    // The parameter of the caller hides the global
+    assert_eq!(value("a"), Expression::Number(1.0));
+    assert_eq!(value("b"), Expression::Number(0.0));
+    // The tail call replaces the frame of outer, but inner still sees m
+    assert_eq!(value("c"), Expression::Number(6.0));
+
+    assert_eq!(
+        run_error("let f:fn = (x:num):num => y;\nprint(f(1));"),
+        RuntimeError {
+            message: "Unknown identifier: y".to_string(),
+            span: Some(Span { begin: 26, end: 26 }),
+        }
+    );
+}
+
+/// The tests written in nino, see `ninoi test`
+#[test]
+fn test_nino_tests() {
+    let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/nino");
+    let mut count = 0;
+    for entry in std::fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let code = std::fs::read_to_string(&path).unwrap();
+        let ast = parse(&tokenize(&code).unwrap()).unwrap();
+        for name in tests(&ast) {
+            let result = run_test(&ast, &name);
+            assert_eq!(result.error, None, "{} in {}", name, path.display());
+            count += 1;
+        }
+    }
+    assert_eq!(count, 10);
+}
+
+#[test]
+fn test_assertions() {
+    assert_eq!(
+        run_error("assert(1 == 2);"),
+        RuntimeError {
+            message: "Assertion failed".to_string(),
+            span: Some(Span { begin: 0, end: 13 }),
+        }
+    );
+    assert_eq!(
+        run_error("assert_eq(\"ab\", \"ac\");"),
+        RuntimeError {
+            message: "The values are not equal\nleft:  ab\nright: ac".to_string(),
+            span: Some(Span { begin: 0, end: 20 }),
+        }
+    );
+}
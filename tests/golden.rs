@@ -0,0 +1,306 @@
+/// Runs the examples and the programs in tests/programs, and compares what they print with the
+/// `.out` file next to them, and the error they stop with with the `.err` file. A missing file is
+/// expected to be empty. Run with `BLESS=1` to write the files from what the programs do:
+///
+/// ```bash
+/// BLESS=1 cargo test --test golden
+/// ```
+///
+/// The lines printed by the calls to `print` that depend on `time()` are different every time, so
+/// they are written as `<time>` and match any line. A print depends on the time when its argument
+/// calls `time()` or uses a global whose declaration does, directly or through other globals. The
+/// values passed to a function are not followed, so a print of a parameter is never masked.
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use nino::{
+    diagnostics::{Diagnostic, SourceMap},
+    lexer::Span,
+    parser::{Expression, Item},
+    syntax,
+    virtual_machine::{Hook, State, VirtualMachine},
+};
+
+const TIME: &str = "<time>";
+
+/// What the program printed, shared with the hook
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether the expression calls `time()` or uses one of the globals that depend on it
+fn calls_time(expression: &Expression, timed: &HashSet<String>) -> bool {
+    let mut found = false;
+    visit(expression, &mut |expression| {
+        found |= match expression {
+            Expression::FunctionCall(call) => call.name == "time" || timed.contains(&call.name),
+            Expression::Identifier(name, _) => timed.contains(name),
+            _ => false,
+        }
+    });
+    found
+}
+
+fn visit(expression: &Expression, f: &mut dyn FnMut(&Expression)) {
+    f(expression);
+    match expression {
+        Expression::FunctionDeclaration(function) => visit(&function.expression, f),
+        Expression::FunctionCall(call) => {
+            for argument in &call.arguments {
+                visit(argument, f);
+            }
+        }
+        Expression::Match(match_) => {
+            visit(&match_.value, f);
+            for (pattern, arm) in &match_.patterns {
+                visit(pattern, f);
+                visit(arm, f);
+            }
+            if let Some(default) = &match_.default {
+                visit(default, f);
+            }
+        }
+        Expression::BinaryOperation(binary) => {
+            visit(&binary.left, f);
+            visit(&binary.right, f);
+        }
+        Expression::Array(_, elements) => {
+            for element in elements {
+                visit(element, f);
+            }
+        }
+        Expression::Identifier(..)
+        | Expression::Number(_)
+        | Expression::Char(_)
+        | Expression::Bool(_) => {}
+    }
+}
+
+/// Notes where in the output the prints that depend on the time begin
+struct Timing {
+    prints: Vec<Span>,
+    output: Output,
+    offsets: Vec<usize>,
+}
+
+impl Timing {
+    fn new(program: &[Item], output: Output) -> Self {
+        // The globals whose value depends on the time, until no more are found
+        let mut timed = HashSet::new();
+        loop {
+            let count = timed.len();
+            for item in program {
+                if let Item::Declaration(declaration) = item {
+                    if calls_time(&declaration.expression, &timed) {
+                        timed.insert(declaration.name.clone());
+                    }
+                }
+            }
+            if timed.len() == count {
+                break;
+            }
+        }
+
+        let mut prints = vec![];
+        for item in program {
+            let expression = match item {
+                Item::Declaration(declaration) => &declaration.expression,
+                Item::Expression(expression) => expression,
+                Item::Error => continue,
+            };
+            visit(expression, &mut |expression| match expression {
+                Expression::FunctionCall(call)
+                    if call.name == "print"
+                        && call
+                            .arguments
+                            .iter()
+                            .any(|argument| calls_time(argument, &timed)) =>
+                {
+                    prints.extend(expression.span())
+                }
+                _ => {}
+            });
+        }
+        Timing {
+            prints,
+            output,
+            offsets: vec![],
+        }
+    }
+
+    /// The output, with the lines printed by the prints that depend on the time replaced
+    fn mask(&self) -> String {
+        let output = self.output.0.borrow();
+        let mut masked = String::new();
+        let mut begin = 0;
+        for line in String::from_utf8_lossy(&output).split_inclusive('\n') {
+            let end = begin + line.len();
+            match self
+                .offsets
+                .iter()
+                .any(|&offset| (begin..end).contains(&offset))
+            {
+                true => masked += &format!("{}\n", TIME),
+                false => masked += line,
+            }
+            begin = end;
+        }
+        masked
+    }
+}
+
+impl Hook for Timing {
+    fn evaluate(&mut self, state: &State) -> Result<(), String> {
+        if state.span().is_some_and(|span| self.prints.contains(&span)) {
+            self.offsets.push(self.output.0.borrow().len());
+        }
+        Ok(())
+    }
+}
+
+/// What the program prints and the error it stops with, rendered without colors
+fn run(name: &str, source: &str) -> (String, String) {
+    let render = |diagnostics: Vec<Diagnostic>| {
+        let map = SourceMap::new(source);
+        let rendered: Vec<String> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(name, &map, false) + "\n")
+            .collect();
+        rendered.join("\n")
+    };
+    let tree = match syntax::parse(source) {
+        Ok(tree) => tree,
+        Err(error) => return (String::new(), render(vec![Diagnostic::from(&error)])),
+    };
+    let program = match tree.to_items() {
+        (program, errors) if errors.is_empty() => program,
+        (_, errors) => {
+            return (
+                String::new(),
+                render(errors.iter().map(Diagnostic::from).collect()),
+            )
+        }
+    };
+
+    let output = Output::default();
+    let mut timing = Timing::new(&program, output.clone());
+    let result = {
+        let mut vm = VirtualMachine::new();
+        vm.set_output(output);
+        vm.run_with_hook(program, &mut timing)
+    };
+    let error = match result {
+        Ok(()) => String::new(),
+        Err(error) => render(vec![Diagnostic::from(&error)]),
+    };
+    (timing.mask(), error)
+}
+
+fn matches(expected: &str, actual: &str) -> bool {
+    expected.lines().count() == actual.lines().count()
+        && expected
+            .lines()
+            .zip(actual.lines())
+            .all(|(expected, actual)| expected == TIME || expected == actual)
+}
+
+/// Compares the file with what was expected, or writes it when blessing. Returns the difference.
+fn check(path: &Path, actual: &str, bless: bool) -> Option<String> {
+    let expected = fs::read_to_string(path).unwrap_or_default();
+    if bless {
+        match actual.is_empty() {
+            true if path.exists() => fs::remove_file(path).unwrap(),
+            true => {}
+            false => fs::write(path, actual).unwrap(),
+        }
+        return None;
+    }
+    if matches(&expected, actual) {
+        return None;
+    }
+    Some(format!(
+        "{}\n--- expected\n{}--- actual\n{}",
+        path.display(),
+        expected,
+        actual
+    ))
+}
+
+/// The `.ni` files in the directories, relative to the root of the crate
+fn programs(directories: &[&str]) -> Vec<PathBuf> {
+    let mut programs = vec![];
+    for directory in directories {
+        for entry in fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(directory)).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|extension| extension == "ni") {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                programs.push(Path::new(directory).join(name));
+            }
+        }
+    }
+    programs.sort();
+    programs
+}
+
+#[test]
+fn test_golden_files() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let programs = programs(&["examples", "tests/programs"]);
+    assert!(!programs.is_empty());
+
+    let mut differences = vec![];
+    for program in programs {
+        let source = fs::read_to_string(root.join(&program)).unwrap();
+        let (output, error) = run(&program.to_string_lossy(), &source);
+        let path = root.join(&program);
+        differences.extend(check(&path.with_extension("out"), &output, bless));
+        differences.extend(check(&path.with_extension("err"), &error, bless));
+    }
+    assert!(
+        differences.is_empty(),
+        "{}\nRun with BLESS=1 to update the files",
+        differences.join("\n")
+    );
+}
+
+#[test]
+fn test_timing_is_masked() {
+    let source = "let start:num = time();
+print(\"Took\");
+print(time() - start);
+print(1);";
+    let (output, error) = run("timing.ni", source);
+    assert_eq!(output, "Took\n<time>\n1\n");
+    assert_eq!(error, "");
+    assert!(matches(&output, "Took\n12\n1\n"));
+    assert!(!matches(&output, "Took\n12\n2\n"));
+}
+
+#[test]
+fn test_derived_timing_is_masked() {
+    let source = "let start:num = time();
+let elapsed:fn = ():num => time() - start;
+let t:num = time() - start;
+let ms:num = t * 1000;
+print(t);
+print(ms);
+print(elapsed());
+print(1);";
+    let (output, error) = run("timing.ni", source);
+    assert_eq!(output, "<time>\n<time>\n<time>\n1\n");
+    assert_eq!(error, "");
+}
@@ -1,15 +1,15 @@
 /// Integration tests for the lexer and parser
 use nino::{
-    lexer::tokenize,
+    lexer::{tokenize, Span},
     parser::{
-        parse, parse_declaration, BinaryOperation, BinaryOperator, Declaration, Expression,
-        FunctionDeclaration, FunctionParameter, Item, Match, Type,
+        parse, parse_declaration, parse_recovering, BinaryOperation, BinaryOperator, Declaration,
+        Expression, FunctionDeclaration, FunctionParameter, Item, Location, Match, Type,
     },
 };
 
 #[test]
 fn test_parse_declaration() {
-    let tokens = tokenize("let x:num = 3;");
+    let tokens = tokenize("let x:num = 3;").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
@@ -17,20 +17,22 @@ fn test_parse_declaration() {
             name: "x".to_string(),
             type_: Type::Number,
             expression: Box::new(Expression::Number(3.0)),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
 
 #[test]
 fn test_fail_parse_declaration() {
-    let tokens = tokenize("let x: = 3.0;");
+    let tokens = tokenize("let x: = 3.0;").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert!(declaration.is_err());
 }
 
 #[test]
 fn test_type_number() {
-    let tokens = tokenize("let x:num = 3;");
+    let tokens = tokenize("let x:num = 3;").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
@@ -38,13 +40,15 @@ fn test_type_number() {
             name: "x".to_string(),
             type_: Type::Number,
             expression: Box::new(Expression::Number(3.0)),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
 
 #[test]
 fn test_type_number_float() {
-    let tokens = tokenize("let x:num = 3.0;");
+    let tokens = tokenize("let x:num = 3.0;").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
@@ -52,13 +56,15 @@ fn test_type_number_float() {
             name: "x".to_string(),
             type_: Type::Number,
             expression: Box::new(Expression::Number(3.0)),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
 
 #[test]
 fn test_type_negative_number_float() {
-    let tokens = tokenize("let x:num = -3.0;");
+    let tokens = tokenize("let x:num = -3.0;").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
@@ -66,27 +72,31 @@ fn test_type_negative_number_float() {
             name: "x".to_string(),
             type_: Type::Number,
             expression: Box::new(Expression::Number(-3.0)),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
 
 #[test]
 fn test_type_char() {
-    let tokens = tokenize("let x:char = 'a';");
+    let tokens = tokenize("let x:char = 'a';").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
         Ok(Declaration {
             name: "x".to_string(),
             type_: Type::Char,
-            expression: Box::new(Expression::Char('a' as u8)),
+            expression: Box::new(Expression::Char(b'a')),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
 
 #[test]
 fn test_type_bool() {
-    let tokens = tokenize("let x:bool = true;");
+    let tokens = tokenize("let x:bool = true;").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
@@ -94,6 +104,8 @@ fn test_type_bool() {
             name: "x".to_string(),
             type_: Type::Boolean,
             expression: Box::new(Expression::Bool(true)),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
@@ -101,7 +113,7 @@ fn test_type_bool() {
 #[test]
 fn test_type_fn() {
     use nino::parser::Expression;
-    let tokens = tokenize("let x:fn = (x:num, y:num):num => x+y;");
+    let tokens = tokenize("let x:fn = (x:num, y:num):num => x+y;").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
@@ -113,19 +125,25 @@ fn test_type_fn() {
                     FunctionParameter {
                         name: "x".to_string(),
                         type_: Type::Number,
+                        location: Location::default(),
                     },
                     FunctionParameter {
                         name: "y".to_string(),
                         type_: Type::Number,
+                        location: Location::default(),
                     }
                 ],
                 return_type: Type::Number,
                 expression: Box::new(Expression::BinaryOperation(BinaryOperation {
                     operator: BinaryOperator::Add,
-                    left: Box::new(Expression::Identifier("x".to_string())),
-                    right: Box::new(Expression::Identifier("y".to_string())),
+                    left: Box::new(Expression::Identifier("x".to_string(), Location::default())),
+                    right: Box::new(Expression::Identifier("y".to_string(), Location::default())),
+                    location: Location::default(),
                 })),
-            }))
+                location: Location::default(),
+            })),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
@@ -133,7 +151,7 @@ fn test_type_fn() {
 /// Testing type [num] `let x:[num] = [1, 2, 3];`
 #[test]
 fn test_type_array() {
-    let tokens = tokenize("let x:[num] = [1, 2, 3];");
+    let tokens = tokenize("let x:[num] = [1, 2, 3];").unwrap();
     let declaration = parse_declaration(&mut tokens.iter().peekable());
     assert_eq!(
         declaration,
@@ -147,7 +165,9 @@ fn test_type_array() {
                     Expression::Number(2.0),
                     Expression::Number(3.0),
                 ]
-            ))
+            )),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
@@ -155,7 +175,7 @@ fn test_type_array() {
 /// Testing `let add:fn = (x:num, y:num):num => x+y;`
 #[test]
 fn test_function_declaration() {
-    let tokens = tokenize("let add:fn = (x:num, y:num):num => x+y;");
+    let tokens = tokenize("let add:fn = (x:num, y:num):num => x+y;").unwrap();
     let items = parse(&tokens).unwrap();
     assert_eq!(
         items[0],
@@ -167,19 +187,25 @@ fn test_function_declaration() {
                     FunctionParameter {
                         name: "x".to_string(),
                         type_: Type::Number,
+                        location: Location::default(),
                     },
                     FunctionParameter {
                         name: "y".to_string(),
                         type_: Type::Number,
+                        location: Location::default(),
                     }
                 ],
                 return_type: Type::Number,
                 expression: Box::new(Expression::BinaryOperation(BinaryOperation {
                     operator: BinaryOperator::Add,
-                    left: Box::new(Expression::Identifier("x".to_string())),
-                    right: Box::new(Expression::Identifier("y".to_string())),
+                    left: Box::new(Expression::Identifier("x".to_string(), Location::default())),
+                    right: Box::new(Expression::Identifier("y".to_string(), Location::default())),
+                    location: Location::default(),
                 })),
-            }))
+                location: Location::default(),
+            })),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
@@ -187,7 +213,7 @@ fn test_function_declaration() {
 /// Testing `let x:num = 1 ? {1 => 2, 2 => 3, 4 };`
 #[test]
 fn test_match() {
-    let tokens = tokenize("let x:num = 1 ? {1 => 2, 2 => 3, 4 };");
+    let tokens = tokenize("let x:num = 1 ? {1 => 2, 2 => 3, 4 };").unwrap();
 
     let items = parse(&tokens).unwrap();
 
@@ -203,7 +229,10 @@ fn test_match() {
                     (Expression::Number(2.0), Expression::Number(3.0),),
                 ],
                 default: Some(Box::new(Expression::Number(4.0))),
-            }))
+                location: Location::default(),
+            })),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
@@ -211,7 +240,7 @@ fn test_match() {
 /// Testing `let x:[num] = [1, 2, 3];`
 #[test]
 fn test_array() {
-    let tokens = tokenize("let x:[num] = [1, 2, 3];");
+    let tokens = tokenize("let x:[num] = [1, 2, 3];").unwrap();
 
     let items = parse(&tokens).unwrap();
 
@@ -227,7 +256,9 @@ fn test_array() {
                     Expression::Number(2.0),
                     Expression::Number(3.0),
                 ]
-            ))
+            )),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
@@ -235,7 +266,7 @@ fn test_array() {
 /// Testing `let x:[char] = "nino";`
 #[test]
 fn test_string() {
-    let tokens = tokenize("let x:[char] = \"nino\";");
+    let tokens = tokenize("let x:[char] = \"nino\";").unwrap();
 
     let items = parse(&tokens).unwrap();
 
@@ -247,12 +278,14 @@ fn test_string() {
             expression: Box::new(Expression::Array(
                 Type::Char,
                 vec![
-                    Expression::Char('n' as u8),
-                    Expression::Char('i' as u8),
-                    Expression::Char('n' as u8),
-                    Expression::Char('o' as u8),
+                    Expression::Char(b'n'),
+                    Expression::Char(b'i'),
+                    Expression::Char(b'n'),
+                    Expression::Char(b'o'),
                 ]
-            ))
+            )),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
@@ -260,7 +293,7 @@ fn test_string() {
 /// Testing `let x:bool = 1+3>2 == 1;`
 #[test]
 fn test_parser() {
-    let tokens = tokenize("let x:bool = 1+3>2 == 1;");
+    let tokens = tokenize("let x:bool = 1+3>2 == 1;").unwrap();
 
     let items = parse(&tokens).unwrap();
     assert_eq!(
@@ -276,11 +309,142 @@ fn test_parser() {
                         operator: BinaryOperator::Add,
                         left: Box::new(Expression::Number(1.0)),
                         right: Box::new(Expression::Number(3.0)),
+                        location: Location::default(),
                     })),
                     right: Box::new(Expression::Number(2.0)),
+                    location: Location::default(),
                 })),
                 right: Box::new(Expression::Number(1.0)),
+                location: Location::default(),
+            })),
+            location: Location::default(),
+            doc: None,
+        })
+    );
+}
+
+#[test]
+fn test_reports_every_error() {
+    let tokens = tokenize("let x:num = ;\nlet y:num = 2;\nprint(;\nprint(y);").unwrap();
+    let (items, errors) = parse_recovering(&tokens);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].message, "Unexpected token: Semicolon");
+    assert_eq!(errors[0].token.as_ref().unwrap().begin, 12);
+    assert_eq!(errors[1].token.as_ref().unwrap().begin, 35);
+    assert_eq!(items.len(), 4);
+    assert_eq!(items[0], Item::Error);
+    assert!(matches!(&items[1], Item::Declaration(declaration) if declaration.name == "y"));
+    assert_eq!(items[2], Item::Error);
+    assert!(matches!(items[3], Item::Expression(..)));
+    assert_eq!(parse(&tokens).unwrap_err(), errors);
+}
+
+#[test]
+fn test_recovers_after_braces() {
+    let tokens = tokenize("let f:fn = (x:num):num => x ? { 1 => , 2 => 3 };\nprint(1);").unwrap();
+    let (items, errors) = parse_recovering(&tokens);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(items[0], Item::Error);
+    assert!(matches!(items[1], Item::Expression(..)));
+}
+
+#[test]
+fn test_recovers_before_next_declaration() {
+    let tokens = tokenize("let x:num = 1\nlet y:num = 2;").unwrap();
+    let (items, errors) = parse_recovering(&tokens);
+    assert_eq!(errors[0].message, "Expected semicolon, got Let");
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(&items[1], Item::Declaration(declaration) if declaration.name == "y"));
+}
+
+#[test]
+fn test_end_of_input() {
+    let tokens = tokenize("print(1").unwrap();
+    // Without the EOF token the parser runs out of tokens
+    let errors = parse(&tokens[..tokens.len() - 1]).unwrap_err();
+    assert_eq!(errors[0].message, "Unexpected end of input");
+    assert_eq!(errors[0].token, None);
+    let errors = parse(&tokens).unwrap_err();
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn test_locations() {
+    let tokens = tokenize("let f:fn = (x:num):num => x ? { 1 => g(x) };").unwrap();
+    let declaration = parse_declaration(&mut tokens.iter().peekable()).unwrap();
+    assert_eq!(declaration.location.span(), Span { begin: 0, end: 43 });
+    let function = match *declaration.expression {
+        Expression::FunctionDeclaration(function) => function,
+        expression => panic!("Expected a function, got {:?}", expression),
+    };
+    assert_eq!(function.location.span(), Span { begin: 11, end: 24 });
+    assert_eq!(
+        function.parameters[0].location.span(),
+        Span { begin: 12, end: 16 }
+    );
+    let match_ = match *function.expression {
+        Expression::Match(match_) => match_,
+        expression => panic!("Expected a match, got {:?}", expression),
+    };
+    assert_eq!(match_.location.span(), Span { begin: 28, end: 42 });
+    match &match_.patterns[0].1 {
+        Expression::FunctionCall(function_call) => {
+            assert_eq!(function_call.location.span(), Span { begin: 37, end: 40 })
+        }
+        expression => panic!("Expected a call, got {:?}", expression),
+    }
+}
+
+#[test]
+fn test_doc_comments() {
+    let code = "## Doubles a number
+##
+## Only for numbers
+# not part of the documentation
+let double:fn = (x:num):num => x * 2;
+let y:num = 1; ## Documents z, not y
+let z:num = 2;
+## Separated by an expression
+1;
+let w:num = 3;
+## Not followed by anything";
+    let tokens = tokenize(code).unwrap();
+    let docs: Vec<Option<String>> = parse(&tokens)
+        .unwrap()
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::Declaration(declaration) => Some(declaration.doc),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        docs,
+        vec![
+            Some("Doubles a number\n\nOnly for numbers".to_string()),
+            None,
+            Some("Documents z, not y".to_string()),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn test_comments_inside_expressions() {
+    let tokens = tokenize("let x:num = 1 #[ one ]# + # two\n 2;").unwrap();
+    let ast = parse(&tokens).unwrap();
+    assert_eq!(
+        ast[0],
+        Item::Declaration(Declaration {
+            name: "x".to_string(),
+            type_: Type::Number,
+            expression: Box::new(Expression::BinaryOperation(BinaryOperation {
+                operator: BinaryOperator::Add,
+                left: Box::new(Expression::Number(1.0)),
+                right: Box::new(Expression::Number(2.0)),
+                location: Location::default(),
             })),
+            location: Location::default(),
+            doc: None,
         })
     );
 }
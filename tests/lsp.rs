@@ -0,0 +1,329 @@
+/// Scripted sessions with the language server
+use nino::json::Json;
+use nino::lsp::{read_message, run};
+
+const URI: &str = "file:///test.ni";
+
+/// Sends the messages to a server and returns its exit code and replies
+fn session(messages: &[String]) -> (i32, Vec<Json>) {
+    let mut input = String::new();
+    for message in messages {
+        input.push_str(&format!(
+            "Content-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        ));
+    }
+    let mut output = vec![];
+    let code = run(input.as_bytes(), &mut output).unwrap();
+
+    let mut reader = output.as_slice();
+    let mut replies = vec![];
+    while let Some(reply) = read_message(&mut reader).unwrap() {
+        replies.push(reply.unwrap());
+    }
+    (code, replies)
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+        id, method, params
+    )
+}
+
+fn notify(method: &str, params: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"{}","params":{}}}"#,
+        method, params
+    )
+}
+
+fn open(text: &str) -> String {
+    notify(
+        "textDocument/didOpen",
+        &format!(
+            r#"{{"textDocument":{{"uri":"{}","languageId":"nino","version":1,"text":{}}}}}"#,
+            URI,
+            Json::from(text)
+        ),
+    )
+}
+
+fn at(id: usize, method: &str, line: usize, character: usize) -> String {
+    request(
+        id,
+        method,
+        &format!(
+            r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+            URI, line, character
+        ),
+    )
+}
+
+/// The beginning and the end of a session
+fn script(messages: Vec<String>) -> (i32, Vec<Json>) {
+    let mut all = vec![
+        request(0, "initialize", r#"{"capabilities":{}}"#),
+        notify("initialized", "{}"),
+    ];
+    all.extend(messages);
+    all.push(request(99, "shutdown", "null"));
+    all.push(notify("exit", "null"));
+    let (code, replies) = session(&all);
+    assert_eq!(code, 0);
+    assert!(replies[0]
+        .get("result")
+        .unwrap()
+        .get("capabilities")
+        .is_some());
+    assert_eq!(replies.last().unwrap().get("result"), Some(&Json::Null));
+    (code, replies[1..replies.len() - 1].to_vec())
+}
+
+fn result(reply: &Json) -> &Json {
+    reply.get("result").unwrap()
+}
+
+fn range(json: &Json) -> (f64, f64, f64, f64) {
+    let number = |position: &str, key: &str| {
+        json.get(position)
+            .and_then(|position| position.get(key))
+            .and_then(Json::as_f64)
+            .unwrap()
+    };
+    (
+        number("start", "line"),
+        number("start", "character"),
+        number("end", "line"),
+        number("end", "character"),
+    )
+}
+
+const SOURCE: &str = "## Adds one
+let inc:fn = (x:num):num => x + 1;
+let y:num = inc(2);
+print(y);";
+
+#[test]
+fn test_diagnostics_on_change() {
+    let change = |text: &str| {
+        notify(
+            "textDocument/didChange",
+            &format!(
+                r#"{{"textDocument":{{"uri":"{}","version":2}},"contentChanges":[{{"text":{}}}]}}"#,
+                URI,
+                Json::from(text)
+            ),
+        )
+    };
+    let (_, replies) = script(vec![open(SOURCE), change("let x:num = ;"), change("")]);
+    assert_eq!(replies.len(), 3);
+    for reply in &replies {
+        assert_eq!(
+            reply.get("method").and_then(Json::as_str),
+            Some("textDocument/publishDiagnostics")
+        );
+    }
+    let diagnostics = |i: usize| {
+        replies[i]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .to_vec()
+    };
+    assert!(diagnostics(0).is_empty());
+    let errors = diagnostics(1);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].get("message").and_then(Json::as_str),
+        Some("Unexpected token: Semicolon")
+    );
+    assert_eq!(
+        range(errors[0].get("range").unwrap()),
+        (0.0, 12.0, 0.0, 13.0)
+    );
+    assert!(diagnostics(2).is_empty());
+}
+
+#[test]
+fn test_definition() {
+    let (_, replies) = script(vec![
+        open(SOURCE),
+        // The parameter x in the body
+        at(1, "textDocument/definition", 1, 28),
+        // The call of inc
+        at(2, "textDocument/definition", 2, 13),
+        // Nothing there
+        at(3, "textDocument/definition", 0, 0),
+    ]);
+    let definition = result(&replies[1]);
+    assert_eq!(definition.get("uri").and_then(Json::as_str), Some(URI));
+    assert_eq!(
+        range(definition.get("range").unwrap()),
+        (1.0, 14.0, 1.0, 15.0)
+    );
+    let definition = result(&replies[2]);
+    assert_eq!(
+        range(definition.get("range").unwrap()),
+        (1.0, 4.0, 1.0, 7.0)
+    );
+    assert_eq!(result(&replies[3]), &Json::Null);
+}
+
+#[test]
+fn test_hover() {
+    let (_, replies) = script(vec![
+        open(SOURCE),
+        at(1, "textDocument/hover", 2, 12),
+        at(2, "textDocument/hover", 3, 6),
+    ]);
+    let contents = result(&replies[1]).get("contents").unwrap();
+    assert_eq!(
+        contents.get("value").and_then(Json::as_str),
+        Some("```nino\ninc:fn (x:num):num\n```\n\nAdds one")
+    );
+    let contents = result(&replies[2]).get("contents").unwrap();
+    assert_eq!(
+        contents.get("value").and_then(Json::as_str),
+        Some("```nino\ny:num\n```")
+    );
+}
+
+#[test]
+fn test_hover_after_lexer_error() {
+    let (_, replies) = script(vec![
+        open(&format!("{}\nprint(\"unterminated);", SOURCE)),
+        at(1, "textDocument/hover", 2, 12),
+    ]);
+    let diagnostics = replies[0]
+        .get("params")
+        .and_then(|params| params.get("diagnostics"))
+        .and_then(Json::as_array)
+        .unwrap();
+    assert_eq!(
+        diagnostics[0].get("message").and_then(Json::as_str),
+        Some("Unterminated string")
+    );
+    let contents = result(&replies[1]).get("contents").unwrap();
+    assert_eq!(
+        contents.get("value").and_then(Json::as_str),
+        Some("```nino\ninc:fn (x:num):num\n```\n\nAdds one")
+    );
+}
+
+#[test]
+fn test_document_symbols() {
+    let (_, replies) = script(vec![
+        open(SOURCE),
+        request(
+            1,
+            "textDocument/documentSymbol",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        ),
+    ]);
+    let symbols = result(&replies[1]).as_array().unwrap();
+    let names: Vec<(&str, f64)> = symbols
+        .iter()
+        .map(|symbol| {
+            (
+                symbol.get("name").and_then(Json::as_str).unwrap(),
+                symbol.get("kind").and_then(Json::as_f64).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(names, vec![("inc", 12.0), ("y", 13.0)]);
+    assert_eq!(
+        range(symbols[1].get("selectionRange").unwrap()),
+        (2.0, 4.0, 2.0, 5.0)
+    );
+}
+
+#[test]
+fn test_completion() {
+    let (_, replies) = script(vec![
+        open(SOURCE),
+        // Inside the body of inc
+        at(1, "textDocument/completion", 1, 30),
+        at(2, "textDocument/completion", 3, 0),
+    ]);
+    let labels = |reply: &Json| -> Vec<String> {
+        result(reply)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| {
+                item.get("label")
+                    .and_then(Json::as_str)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    };
+    let inside = labels(&replies[1]);
+    assert!(inside.contains(&"x".to_string()));
+    assert!(inside.contains(&"inc".to_string()));
+    assert!(!inside.contains(&"y".to_string()));
+    assert!(inside.contains(&"print".to_string()));
+    let outside = labels(&replies[2]);
+    assert!(!outside.contains(&"x".to_string()));
+    assert!(outside.contains(&"y".to_string()));
+    assert!(outside.contains(&"time".to_string()));
+}
+
+#[test]
+fn test_errors() {
+    let (code, replies) = session(&[
+        "{".to_string(),
+        request(1, "unknown", "{}"),
+        at(2, "textDocument/hover", 0, 0),
+        notify("exit", "null"),
+    ]);
+    // Exiting without shutting down first
+    assert_eq!(code, 1);
+    let code = |reply: &Json| {
+        reply
+            .get("error")
+            .and_then(|error| error.get("code"))
+            .and_then(Json::as_f64)
+    };
+    assert_eq!(code(&replies[0]), Some(-32700.0));
+    assert_eq!(code(&replies[1]), Some(-32601.0));
+    assert_eq!(code(&replies[2]), Some(-32602.0));
+}
+
+#[test]
+fn test_semantic_tokens() {
+    let (_, replies) = script(vec![
+        open("let x:num = 1;\n#[ a\nb ]# x;"),
+        request(
+            1,
+            "textDocument/semanticTokens/full",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        ),
+    ]);
+    let data: Vec<f64> = result(&replies[1])
+        .get("data")
+        .and_then(Json::as_array)
+        .unwrap()
+        .iter()
+        .map(|number| number.as_f64().unwrap())
+        .collect();
+    #[rustfmt::skip]
+    assert_eq!(
+        data,
+        vec![
+            0.0, 0.0, 3.0, 0.0, 0.0, // let
+            0.0, 4.0, 1.0, 5.0, 1.0, // x, declared
+            0.0, 2.0, 3.0, 1.0, 0.0, // num
+            0.0, 4.0, 1.0, 2.0, 0.0, // =
+            0.0, 2.0, 1.0, 3.0, 0.0, // 1
+            1.0, 0.0, 4.0, 8.0, 0.0, // the first line of the comment
+            1.0, 0.0, 4.0, 8.0, 0.0, // the second one
+            0.0, 5.0, 1.0, 5.0, 0.0, // x
+        ]
+    );
+}
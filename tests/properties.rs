@@ -0,0 +1,419 @@
+/// Properties of the lexer, the parser and the virtual machine, checked on generated input. The
+/// fuzz targets in fuzz/ check the same on the inputs libFuzzer finds.
+use proptest::prelude::*;
+
+use nino::{
+    lexer::tokenize,
+    optimizer::optimize,
+    parser::{
+        parse, parse_recovering, BinaryOperation, BinaryOperator, Declaration, Expression,
+        FunctionCall, FunctionDeclaration, FunctionParameter, Item, Location, Match, Type,
+    },
+    syntax,
+    virtual_machine::VirtualMachine,
+};
+
+/// Writes the program back as source
+fn source(program: &[Item]) -> String {
+    program.iter().map(|item| format!("{}\n", item)).collect()
+}
+
+fn identifier() -> impl Strategy<Value = String> {
+    "[a-z_][a-z0-9_]{0,5}".prop_filter("keywords are not identifiers", |name| {
+        !matches!(name.as_str(), "let" | "fn" | "mod" | "true" | "false")
+    })
+}
+
+fn type_() -> impl Strategy<Value = Type> {
+    let scalar = prop_oneof![
+        Just(Type::Number),
+        Just(Type::Char),
+        Just(Type::Boolean),
+        Just(Type::Function),
+    ];
+    prop_oneof![
+        3 => scalar.clone(),
+        1 => scalar.prop_map(|type_| Type::Array(Box::new(type_))),
+    ]
+}
+
+/// The type the parser gives to an array from its first element
+fn array(elements: Vec<Expression>) -> Expression {
+    let type_ = match elements[0] {
+        Expression::Number(..)
+        | Expression::BinaryOperation(..)
+        | Expression::Identifier(..)
+        | Expression::FunctionCall(..)
+        | Expression::Match(..) => Type::Number,
+        Expression::Char(..) => Type::Char,
+        Expression::Bool(..) => Type::Boolean,
+        Expression::Array(..) => Type::Array(Box::new(Type::Function)),
+        Expression::FunctionDeclaration(..) => Type::Function,
+    };
+    Expression::Array(type_, elements)
+}
+
+fn binary(operator: BinaryOperator, left: Expression, right: Expression) -> Expression {
+    Expression::BinaryOperation(BinaryOperation {
+        operator,
+        left: Box::new(left),
+        right: Box::new(right),
+        location: Location::default(),
+    })
+}
+
+fn call(name: String, arguments: Vec<Expression>) -> Expression {
+    Expression::FunctionCall(FunctionCall {
+        name,
+        arguments,
+        tail: false,
+        location: Location::default(),
+    })
+}
+
+fn match_(
+    value: Expression,
+    patterns: Vec<(Expression, Expression)>,
+    default: Option<Expression>,
+) -> Expression {
+    Expression::Match(Match {
+        value: Box::new(value),
+        patterns,
+        default: default.map(Box::new),
+        location: Location::default(),
+    })
+}
+
+fn operator() -> impl Strategy<Value = BinaryOperator> {
+    prop_oneof![
+        Just(BinaryOperator::Add),
+        Just(BinaryOperator::Subtract),
+        Just(BinaryOperator::Multiply),
+        Just(BinaryOperator::Divide),
+        Just(BinaryOperator::Modulo),
+        Just(BinaryOperator::Equal),
+        Just(BinaryOperator::NotEqual),
+        Just(BinaryOperator::LessThan),
+        Just(BinaryOperator::LessEqualThan),
+        Just(BinaryOperator::GreaterThan),
+        Just(BinaryOperator::GreaterEqualThan),
+    ]
+}
+
+/// Any expression the parser can produce, whether it makes sense or not
+fn any_expression() -> impl Strategy<Value = Expression> {
+    let leaf = prop_oneof![
+        identifier().prop_map(|name| Expression::Identifier(name, Location::default())),
+        any::<f64>()
+            .prop_filter("numbers are finite", |value| value.is_finite())
+            .prop_map(Expression::Number),
+        (0u32..1000).prop_map(|value| Expression::Number(value.into())),
+        any::<u8>().prop_map(Expression::Char),
+        any::<bool>().prop_map(Expression::Bool),
+        prop::collection::vec(any::<u8>(), 0..8).prop_map(|bytes| {
+            Expression::Array(
+                Type::Char,
+                bytes.into_iter().map(Expression::Char).collect(),
+            )
+        }),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 1..4).prop_map(array),
+            (
+                prop::collection::vec((identifier(), type_()), 0..3),
+                type_(),
+                inner.clone()
+            )
+                .prop_map(|(parameters, return_type, body)| {
+                    Expression::FunctionDeclaration(FunctionDeclaration {
+                        parameters: parameters
+                            .into_iter()
+                            .map(|(name, type_)| FunctionParameter {
+                                name,
+                                type_,
+                                location: Location::default(),
+                            })
+                            .collect(),
+                        return_type,
+                        expression: Box::new(body),
+                        location: Location::default(),
+                    })
+                }),
+            (identifier(), prop::collection::vec(inner.clone(), 0..3))
+                .prop_map(|(name, arguments)| call(name, arguments)),
+            (
+                inner.clone(),
+                prop::collection::vec((inner.clone(), inner.clone()), 0..3),
+                prop::option::of(inner.clone())
+            )
+                .prop_map(|(value, patterns, default)| match_(value, patterns, default)),
+            (operator(), inner.clone(), inner)
+                .prop_map(|(operator, left, right)| binary(operator, left, right)),
+        ]
+    })
+}
+
+fn any_program() -> impl Strategy<Value = Vec<Item>> {
+    let doc = prop::option::of(
+        prop::collection::vec("[a-z]([a-z ]{0,10}[a-z])?", 1..3).prop_map(|lines| lines.join("\n")),
+    );
+    let declaration = (identifier(), type_(), any_expression(), doc).prop_map(
+        |(name, type_, expression, doc)| {
+            Item::Declaration(Declaration {
+                name,
+                type_,
+                expression: Box::new(expression),
+                location: Location::default(),
+                doc,
+            })
+        },
+    );
+    let item = prop_oneof![declaration, any_expression().prop_map(Item::Expression)];
+    prop::collection::vec(item, 0..4)
+}
+
+/// A program with a few characters cut out, so that it has errors in every kind of expression
+fn broken_program() -> impl Strategy<Value = String> {
+    (any_program(), any::<prop::sample::Index>(), 1..4usize).prop_map(|(program, index, length)| {
+        let mut printed = source(&program);
+        let begin = index.index(printed.len() + 1);
+        let end = (begin + length).min(printed.len());
+        printed.replace_range(begin..end, "");
+        printed
+    })
+}
+
+/// The global that the programs declare last, so that using it before fails
+const LATE: &str = "late";
+
+/// An expression that stops the program when it is evaluated: an operation on values of the wrong
+/// type, or a global used before its declaration
+fn failing() -> BoxedStrategy<Expression> {
+    let arithmetic = prop_oneof![
+        Just(BinaryOperator::Add),
+        Just(BinaryOperator::Subtract),
+        Just(BinaryOperator::Multiply),
+        Just(BinaryOperator::Divide),
+    ];
+    let value = prop_oneof![
+        any::<bool>().prop_map(Expression::Bool),
+        (b'a'..=b'z').prop_map(Expression::Char),
+    ];
+    prop_oneof![
+        (arithmetic, value, 0u32..20).prop_map(|(operator, value, number)| {
+            binary(operator, Expression::Number(number.into()), value)
+        }),
+        Just(Expression::Identifier(
+            LATE.to_string(),
+            Location::default()
+        )),
+    ]
+    .boxed()
+}
+
+/// An expression that evaluates to a number, using the parameters and calling the functions,
+/// which take two numbers. Some of the arguments of the calls fail, whether the function uses
+/// them or not.
+fn number(
+    parameters: &'static [&'static str],
+    functions: &'static [&'static str],
+) -> BoxedStrategy<Expression> {
+    let mut leaves = vec![
+        (0u32..20)
+            .prop_map(|value| Expression::Number(value.into()))
+            .boxed(),
+        (-100i32..100)
+            .prop_map(|value| Expression::Number(f64::from(value) / 4.0))
+            .boxed(),
+    ];
+    if !parameters.is_empty() {
+        leaves.push(
+            prop::sample::select(parameters)
+                .prop_map(|name| Expression::Identifier(name.to_string(), Location::default()))
+                .boxed(),
+        );
+    }
+    let leaf = prop::strategy::Union::new(leaves);
+    leaf.prop_recursive(4, 24, 3, move |inner| {
+        let arithmetic = prop_oneof![
+            Just(BinaryOperator::Add),
+            Just(BinaryOperator::Subtract),
+            Just(BinaryOperator::Multiply),
+            Just(BinaryOperator::Divide),
+            Just(BinaryOperator::Modulo),
+        ];
+        let comparison = prop_oneof![
+            Just(BinaryOperator::Equal),
+            Just(BinaryOperator::NotEqual),
+            Just(BinaryOperator::LessThan),
+            Just(BinaryOperator::GreaterEqualThan),
+        ];
+        let mut branches = vec![
+            (arithmetic, inner.clone(), inner.clone())
+                .prop_map(|(operator, left, right)| binary(operator, left, right))
+                .boxed(),
+            // A match on a number, or on true with conditions as the patterns
+            (
+                inner.clone(),
+                prop::collection::vec((inner.clone(), inner.clone()), 1..3),
+                prop::option::of(inner.clone()),
+            )
+                .prop_map(|(value, patterns, default)| match_(value, patterns, default))
+                .boxed(),
+            (
+                prop::collection::vec(
+                    ((comparison, inner.clone(), inner.clone()), inner.clone()),
+                    1..3,
+                ),
+                inner.clone(),
+            )
+                .prop_map(|(arms, default)| {
+                    let patterns = arms
+                        .into_iter()
+                        .map(|((operator, left, right), arm)| (binary(operator, left, right), arm))
+                        .collect();
+                    match_(Expression::Bool(true), patterns, Some(default))
+                })
+                .boxed(),
+        ];
+        if !functions.is_empty() {
+            branches.push(
+                (
+                    prop::sample::select(functions),
+                    prop_oneof![4 => inner.clone(), 1 => failing()],
+                    prop_oneof![4 => inner.clone(), 1 => failing()],
+                )
+                    .prop_map(|(name, left, right)| call(name.to_string(), vec![left, right]))
+                    .boxed(),
+            );
+        }
+        prop::strategy::Union::new(branches)
+    })
+    .boxed()
+}
+
+fn function(name: &str, body: Expression) -> Item {
+    let parameter = |name: &str| FunctionParameter {
+        name: name.to_string(),
+        type_: Type::Number,
+        location: Location::default(),
+    };
+    Item::Declaration(Declaration {
+        name: name.to_string(),
+        type_: Type::Function,
+        expression: Box::new(Expression::FunctionDeclaration(FunctionDeclaration {
+            parameters: vec![parameter("a"), parameter("b")],
+            return_type: Type::Number,
+            expression: Box::new(body),
+            location: Location::default(),
+        })),
+        location: Location::default(),
+        doc: None,
+    })
+}
+
+/// Two functions, the second one calling the first one, prints of numbers computed with them and
+/// the declaration of [LATE]. Nothing is recursive, so the programs always end.
+fn numeric_program() -> impl Strategy<Value = Vec<Item>> {
+    const PARAMETERS: &[&str] = &["a", "b"];
+    (
+        number(PARAMETERS, &[]),
+        number(PARAMETERS, &["f"]),
+        prop::collection::vec(number(&[], &["f", "g"]), 1..4),
+    )
+        .prop_map(|(f, g, prints)| {
+            let mut program = vec![function("f", f), function("g", g)];
+            for value in prints {
+                program.push(Item::Expression(call("print".to_string(), vec![value])));
+            }
+            program.push(Item::Declaration(Declaration {
+                name: LATE.to_string(),
+                type_: Type::Number,
+                expression: Box::new(Expression::Number(1.0)),
+                location: Location::default(),
+                doc: None,
+            }));
+            program
+        })
+}
+
+/// What the program prints, and the message of the error it stops with
+fn run(program: Vec<Item>) -> (String, Option<String>) {
+    let mut output = vec![];
+    let result = {
+        let mut vm = VirtualMachine::new();
+        vm.set_output(&mut output);
+        vm.run(program)
+    };
+    (
+        String::from_utf8_lossy(&output).to_string(),
+        result.err().map(|error| error.message),
+    )
+}
+
+proptest! {
+    #[test]
+    fn tokenize_never_panics(input in any::<String>()) {
+        let _ = tokenize(&input);
+    }
+
+    /// Strings made of the characters of the language find more than random text does
+    #[test]
+    fn parsing_never_panics(input in "[a-z0-9 ()\\[\\]{}:;,=>?<!+*/'\"#.\\n-]{0,40}") {
+        if let Ok(tokens) = tokenize(&input) {
+            let _ = parse_recovering(&tokens);
+        }
+        if let Ok(tree) = syntax::parse(&input) {
+            let _ = tree.to_items();
+        }
+    }
+
+    #[test]
+    fn trees_print_back_the_source(
+        input in prop_oneof![any::<String>(), "[a-z0-9 ()\\[\\]{}:;,=>?<!+*/'\"#.\\n-]{0,40}"]
+    ) {
+        if let Ok(tree) = syntax::parse(&input) {
+            prop_assert_eq!(tree.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn trees_derive_the_parsed_program(program in any_program()) {
+        let printed = source(&program);
+        let tree = syntax::parse(&printed).unwrap();
+        prop_assert_eq!(tree.to_items(), (program, vec![]), "{}", printed);
+    }
+
+    /// The tree and the parser stop at the same first error
+    #[test]
+    fn trees_find_the_parser_errors(
+        input in prop_oneof![
+            "[a-z0-9 ()\\[\\]{}:;,=>?<!+*/'\"#.\\n-]{0,40}",
+            broken_program(),
+        ]
+    ) {
+        if let Ok(tree) = syntax::parse(&input) {
+            let (items, errors) = tree.to_items();
+            let (parsed, parser_errors) = parse_recovering(&tokenize(&input).unwrap());
+            prop_assert_eq!(errors.first(), parser_errors.first(), "{}", input);
+            if errors.is_empty() {
+                prop_assert_eq!(items, parsed, "{}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn printed_programs_parse_back(program in any_program()) {
+        let printed = source(&program);
+        let tokens = tokenize(&printed).unwrap();
+        prop_assert_eq!(parse(&tokens).unwrap(), program, "{}", printed);
+    }
+
+    #[test]
+    fn optimized_programs_agree(program in numeric_program()) {
+        let printed = source(&program);
+        let optimized = optimize(program.clone());
+        prop_assert_eq!(run(optimized), run(program), "{}", printed);
+    }
+}
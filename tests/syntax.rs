@@ -0,0 +1,143 @@
+/// Integration tests for the concrete syntax tree
+use std::fs;
+use std::path::Path;
+
+use nino::{
+    formatter::format,
+    lexer::tokenize,
+    parser::{parse, parse_recovering},
+    syntax::{self, SyntaxKind, SyntaxNode},
+};
+
+/// The sources of every program in the given directory
+fn programs(directory: &str) -> Vec<(String, String)> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join(directory);
+    let mut programs: Vec<(String, String)> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ni"))
+        .map(|path| {
+            let source = fs::read_to_string(&path).unwrap();
+            (path.display().to_string(), source)
+        })
+        .collect();
+    programs.sort();
+    programs
+}
+
+/// The kinds of the node and of the nodes below it
+fn kinds(node: &SyntaxNode, kinds: &mut Vec<SyntaxKind>) {
+    kinds.push(node.kind);
+    for child in node.nodes() {
+        self::kinds(child, kinds);
+    }
+}
+
+#[test]
+fn test_examples_round_trip() {
+    let examples = programs("examples");
+    assert!(!examples.is_empty());
+    for (path, source) in examples.iter().chain(&programs("tests/programs")) {
+        let tree = syntax::parse(source).unwrap();
+        assert_eq!(tree.to_string(), *source, "{} is not printed back", path);
+    }
+}
+
+#[test]
+fn test_examples_derive_the_same_ast() {
+    for (path, source) in programs("examples")
+        .iter()
+        .chain(&programs("tests/programs"))
+    {
+        let (items, errors) = syntax::parse(source).unwrap().to_items();
+        match parse(&tokenize(source).unwrap()) {
+            Ok(parsed) => {
+                assert_eq!(items, parsed, "{}", path);
+                assert!(errors.is_empty(), "{}", path);
+            }
+            Err(parser_errors) => assert_eq!(errors, parser_errors, "{}", path),
+        }
+    }
+}
+
+#[test]
+fn test_every_node_kind_derives_the_same_ast() {
+    let source = "# A comment
+## Documented
+## on two lines
+let f:fn = (a:num, b:[char]):bool => a + -1 * (2 - a) / 3 mod 4 >= 5 == true;
+let g:fn = ():num => 'x' ? { 'y' => 1, 2 };
+let s:[char] = \"text\";
+let n:[num] = [1, 2, f(1, \"a\")];
+g() < 1 != (x:num):num => x ? { 1 => 2, 3 => 4 };
+";
+    let tree = syntax::parse(source).unwrap();
+    let mut covered = vec![];
+    kinds(&tree, &mut covered);
+    for kind in [
+        SyntaxKind::Declaration,
+        SyntaxKind::ExpressionItem,
+        SyntaxKind::Literal,
+        SyntaxKind::Name,
+        SyntaxKind::Negation,
+        SyntaxKind::Binary,
+        SyntaxKind::Group,
+        SyntaxKind::Function,
+        SyntaxKind::Parameters,
+        SyntaxKind::Parameter,
+        SyntaxKind::Call,
+        SyntaxKind::Arguments,
+        SyntaxKind::Array,
+        SyntaxKind::Match,
+        SyntaxKind::MatchArm,
+        SyntaxKind::Default,
+    ] {
+        assert!(covered.contains(&kind), "{:?} is not covered", kind);
+    }
+    let parsed = parse(&tokenize(source).unwrap()).unwrap();
+    assert_eq!(tree.to_items(), (parsed, vec![]));
+}
+
+#[test]
+fn test_examples_display_round_trip() {
+    for (path, source) in programs("examples")
+        .iter()
+        .chain(&programs("tests/programs"))
+    {
+        let (items, errors) = parse_recovering(&tokenize(source).unwrap());
+        if !errors.is_empty() {
+            continue;
+        }
+        let displayed: String = items.iter().map(|item| format!("{}\n", item)).collect();
+        let tokens = tokenize(&displayed).unwrap();
+        assert_eq!(
+            parse(&tokens).unwrap(),
+            items,
+            "{} is not displayed back",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_formatting_examples() {
+    for (path, source) in programs("examples")
+        .iter()
+        .chain(&programs("tests/programs"))
+    {
+        let tree = syntax::parse(source).unwrap();
+        let (items, errors) = tree.to_items();
+        if !errors.is_empty() {
+            continue;
+        }
+        let formatted = format(&tree);
+        let formatted_tree = syntax::parse(&formatted).unwrap();
+        assert_eq!(
+            formatted_tree.to_items().0,
+            items,
+            "{} changed meaning",
+            path
+        );
+        assert_eq!(format(&formatted_tree), formatted, "{} is not stable", path);
+    }
+}